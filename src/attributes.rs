@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::pathspec::{wildmatch, wildmatch_path};
+
+/// What an attribute says about a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeState {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+}
+
+/// Attributes of paths in the workspace, read from `.gitattributes` files and from
+/// `.git/info/attributes`, which overrides them. The `.gitattributes` file of each directory is
+/// read when a path inside it is first looked up, and overrides those of the directories above.
+#[derive(Debug)]
+pub struct Attributes {
+    root: PathBuf,
+    info: Vec<Rule>,
+    dirs: HashMap<PathBuf, Vec<Rule>>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Vec<u8>,
+    /// The attributes the rule assigns, with `None` for `!name`, which makes them unspecified
+    states: Vec<(String, Option<AttributeState>)>,
+}
+
+impl Attributes {
+    pub fn load(root: &Path, info_path: &Path) -> Result<Self> {
+        Ok(Self {
+            root: root.to_owned(),
+            info: read_rules(info_path)?,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// The state of attribute `name` for `path`, relative to the workspace root, or `None` if
+    /// it is unspecified.
+    pub fn get(&mut self, path: &Path, name: &str) -> Result<Option<AttributeState>> {
+        let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        dirs.reverse();
+        for dir in &dirs {
+            if !self.dirs.contains_key(*dir) {
+                let rules = read_rules(&self.root.join(dir).join(".gitattributes"))?;
+                self.dirs.insert(dir.to_path_buf(), rules);
+            }
+        }
+
+        let mut state = None;
+        let scopes = dirs
+            .iter()
+            .map(|dir| (*dir, &self.dirs[*dir]))
+            .chain([(Path::new(""), &self.info)]);
+        for (dir, rules) in scopes {
+            let rel_path = path.strip_prefix(dir).unwrap_or(path);
+            for rule in rules.iter().filter(|rule| rule.matches(rel_path)) {
+                for (rule_name, rule_state) in &rule.states {
+                    if rule_name == name {
+                        state = rule_state.clone();
+                    }
+                }
+            }
+        }
+        Ok(state)
+    }
+}
+
+fn read_rules(path: &Path) -> Result<Vec<Rule>> {
+    match fs::read(path) {
+        Ok(data) => Ok(parse_rules(&data)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+/// Parses lines of a pattern followed by attributes, all separated by whitespace. Negated
+/// patterns and patterns for directories only are not allowed, and are ignored.
+fn parse_rules(data: &[u8]) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in data.split(|&byte| byte == b'\n') {
+        let mut fields = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|field| !field.is_empty());
+        let pattern = match fields.next() {
+            Some(pattern) if !pattern.starts_with(b"#") => pattern,
+            _ => continue,
+        };
+        if pattern.starts_with(b"!") || pattern.ends_with(b"/") {
+            continue;
+        }
+
+        let mut states = Vec::new();
+        for field in fields {
+            let field = String::from_utf8_lossy(field);
+            let (name, state) = if let Some(name) = field.strip_prefix('-') {
+                (name, Some(AttributeState::Unset))
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name, None)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name, Some(AttributeState::Value(value.to_owned())))
+            } else {
+                (&field[..], Some(AttributeState::Set))
+            };
+            // `binary` is a built-in macro for not diffing, merging or normalizing the file
+            if name == "binary" && state == Some(AttributeState::Set) {
+                for name in ["diff", "merge", "text"] {
+                    states.push((name.to_owned(), Some(AttributeState::Unset)));
+                }
+            }
+            states.push((name.to_owned(), state));
+        }
+        rules.push(Rule {
+            pattern: pattern.to_vec(),
+            states,
+        });
+    }
+    rules
+}
+
+impl Rule {
+    /// Patterns without a slash match the file name in any directory, and others match the path
+    /// from the directory of the attributes file, with `**` matching any number of directories.
+    fn matches(&self, path: &Path) -> bool {
+        let path = path.as_os_str().as_bytes();
+        if !self.pattern.contains(&b'/') {
+            let name = path.rsplit(|&byte| byte == b'/').next().unwrap_or(path);
+            return wildmatch(&self.pattern, name, false);
+        }
+        wildmatch_path(&self.pattern, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use super::{AttributeState, Attributes};
+
+    #[test]
+    fn deeper_and_later_rules_win() {
+        let tempdir = tempdir().expect("tempdir");
+        let root = tempdir.path();
+        fs::create_dir_all(root.join("assets/icons")).unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::write(
+            root.join(".gitattributes"),
+            "# images\n*.png binary\n*.svg diff\ndocs/**/*.md text eol=lf\n",
+        )
+        .unwrap();
+        fs::write(root.join("assets/.gitattributes"), "*.svg -diff\n").unwrap();
+        fs::write(root.join("assets/icons/.gitattributes"), "/a.svg !diff\n").unwrap();
+        fs::write(root.join(".git/info/attributes"), "logo.png diff\n").unwrap();
+
+        let mut attributes =
+            Attributes::load(root, &root.join(".git/info/attributes")).expect("load");
+        let mut get = |path: &str, name: &str| attributes.get(Path::new(path), name).unwrap();
+
+        assert_eq!(get("x/y.png", "diff"), Some(AttributeState::Unset));
+        assert_eq!(get("x/y.png", "binary"), Some(AttributeState::Set));
+        assert_eq!(get("logo.png", "diff"), Some(AttributeState::Set));
+        assert_eq!(get("b.svg", "diff"), Some(AttributeState::Set));
+        assert_eq!(get("assets/b.svg", "diff"), Some(AttributeState::Unset));
+        assert_eq!(
+            get("assets/icons/b.svg", "diff"),
+            Some(AttributeState::Unset)
+        );
+        assert_eq!(get("assets/icons/a.svg", "diff"), None);
+        assert_eq!(
+            get("docs/guide/intro.md", "eol"),
+            Some(AttributeState::Value("lf".to_owned()))
+        );
+        assert_eq!(get("docs/intro.md", "text"), Some(AttributeState::Set));
+        assert_eq!(get("other/docs/intro.md", "text"), None);
+        assert_eq!(get("README", "diff"), None);
+    }
+}
@@ -1,14 +1,39 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-use crate::database::{Blob, Object};
+use crate::database::{Blob, Database, Object, ObjectId};
+use crate::pathspec::{MatchKind, Pathspec};
 use crate::repository::Repository;
+use crate::workspace::WorkspacePath;
+
+/// Files at least this large are streamed into the database rather than read into memory, so
+/// each thread holds at most this much file content at once
+const BIG_FILE_THRESHOLD: u64 = 1024 * 1024;
 
 #[derive(clap::Args, Debug)]
 pub struct Args {
+    #[clap(parse(from_os_str))]
     paths: Vec<PathBuf>,
+
+    /// Stage new, modified and deleted files, in the whole workspace if no paths are given
+    #[clap(short = 'A', long, conflicts_with = "update")]
+    all: bool,
+
+    /// Only stage modified and deleted files that are already tracked, in the whole workspace if
+    /// no paths are given
+    #[clap(short, long)]
+    update: bool,
+
+    /// Number of threads used to hash and store files (0 for one per CPU); defaults to `add.jobs`
+    #[clap(short, long)]
+    jobs: Option<usize>,
 }
 
 pub fn execute(args: Args) -> Result<()> {
@@ -16,25 +41,170 @@ pub fn execute(args: Args) -> Result<()> {
     let repo = Repository::new(root_path);
 
     let workspace = repo.workspace();
+    let pathspec = Pathspec::parse(&args.paths, &workspace)?;
+    if pathspec.is_empty() && !args.all && !args.update {
+        println!("Nothing specified, nothing added.");
+        return Ok(());
+    }
+
     let database = repo.database();
     let mut index = repo.index_for_update()?;
 
-    let files = args
-        .paths
+    let jobs = match args.jobs {
+        Some(jobs) => jobs,
+        None => repo
+            .config()?
+            .get_int("add.jobs")?
+            .map(|jobs| jobs.try_into().context("add.jobs must not be negative"))
+            .transpose()?
+            .unwrap_or(0),
+    };
+
+    let tracked: Vec<_> = index
+        .tracked_paths(Path::new(""))
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .collect();
+    let tracked_set: HashSet<_> = tracked.iter().collect();
+    let mut files = workspace.list_matching_files(&pathspec)?;
+    if args.update {
+        files.retain(|file| tracked_set.contains(file.rel_path()));
+    }
+
+    // Untracked files that are ignored are left out
+    let mut ignores = repo.ignores()?;
+    let mut ignored = Vec::new();
+    let mut unignored = Vec::new();
+    for file in files {
+        if !tracked_set.contains(file.rel_path()) && ignores.is_ignored(file.rel_path())? {
+            ignored.push(file.rel_path().clone());
+        } else {
+            unignored.push(file);
+        }
+    }
+    let files = unignored;
+
+    // Paths of deleted files can still be given, to stage their removal
+    let known = files
         .iter()
-        .map(|path| workspace.list_files(path))
-        .collect::<Result<Vec<_>>>()?;
-    let files = files.iter().flatten();
+        .map(|file| file.rel_path().as_path())
+        .chain(tracked.iter().map(PathBuf::as_path));
+    let ignored_matches = pathspec.item_matches(ignored.iter().map(PathBuf::as_path));
+    let matches = pathspec
+        .item_matches(known)
+        .into_iter()
+        .zip(ignored_matches);
+    for ((item, seen), (_, seen_ignored)) in matches {
+        match (seen, seen_ignored) {
+            (Some(_), _) => {}
+            // Naming an ignored file is an error, but a directory holding only ignored files is not
+            (None, Some(MatchKind::Exact)) => bail!(
+                "The following paths are ignored by one of your .gitignore files:\n{}",
+                item
+            ),
+            (None, Some(MatchKind::Recursive)) => {}
+            (None, _) => bail!("pathspec '{}' did not match any files", item),
+        }
+    }
+
+    for path in &tracked {
+        let is_file = workspace
+            .entry_path(path)
+            .path()
+            .symlink_metadata()
+            .is_ok_and(|metadata| !metadata.is_dir());
+        if !is_file {
+            index.remove(path);
+        }
+    }
 
+    // Files whose stat data shows they are unchanged don't need to be hashed again
+    let mut files_to_store = Vec::new();
     for file in files {
-        let data = file.read()?;
+        if !index.is_unchanged(&file)? {
+            files_to_store.push(file);
+        }
+    }
+    let files = files_to_store;
 
-        let mut blob = Blob::new(data);
-        database.store(&mut blob)?;
-        index.add(&file, blob.oid())?;
+    let oids = store_files(&database, &files, jobs)?;
+
+    for (file, oid) in files.iter().zip(oids) {
+        index.add(file, &oid)?;
     }
 
     index.write_updates()?;
 
     Ok(())
 }
+
+/// Stores the content of every file in the database, spreading the work across `jobs` threads.
+///
+/// The returned oids are in the same order as `files`, regardless of which thread finished
+/// first, so callers can update the index deterministically.
+fn store_files(database: &Database, files: &[WorkspacePath], jobs: usize) -> Result<Vec<ObjectId>> {
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    let jobs = jobs.min(files.len());
+    if jobs <= 1 {
+        return files
+            .iter()
+            .map(|file| store_file(database, file))
+            .collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let file = match files.get(i) {
+                    Some(file) => file,
+                    None => break,
+                };
+                let result = store_file(database, file);
+                let failed = result.is_err();
+                if sender.send((i, result)).is_err() || failed {
+                    break;
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut oids = vec![None; files.len()];
+    let mut first_error = None;
+    for (i, result) in receiver {
+        match result {
+            Ok(oid) => oids[i] = Some(oid),
+            Err(err) => match first_error {
+                Some((first, _)) if first < i => {}
+                _ => first_error = Some((i, err)),
+            },
+        }
+    }
+    if let Some((_, err)) = first_error {
+        return Err(err);
+    }
+
+    Ok(oids
+        .into_iter()
+        .map(|oid| oid.expect("Programmer error: file was not stored"))
+        .collect())
+}
+
+fn store_file(database: &Database, file: &WorkspacePath) -> Result<ObjectId> {
+    let size = file.stat()?.len();
+    if size >= BIG_FILE_THRESHOLD {
+        database.store_blob_stream(file.open()?, size)
+    } else {
+        let mut blob = Blob::new(file.read()?);
+        database.store(&mut blob)?;
+        Ok(*blob.oid())
+    }
+}
@@ -2,11 +2,10 @@ use std::env;
 use std::fs;
 use std::io::{self, Read};
 
-use anyhow::{anyhow, Context, Result};
-use rustc_serialize::hex::ToHex;
+use anyhow::{anyhow, bail, Context, Result};
 use time::OffsetDateTime;
 
-use crate::database::{Author, Commit, Object, Tree, TreeFile};
+use crate::database::{Author, Commit, Object};
 use crate::repository::Repository;
 
 #[derive(clap::Args, Debug)]
@@ -16,32 +15,27 @@ pub fn execute(_args: Args) -> Result<()> {
     let root_path = fs::canonicalize(".")?;
     let repo = Repository::new(root_path);
 
-    let index = repo.index()?;
+    let mut index = repo.index_for_update()?;
     let refs = repo.refs();
     let database = repo.database();
 
-    let entries = index
-        .iter()
-        .map(|entry| TreeFile::new(&entry.path, &entry.oid.to_hex(), entry.mode));
+    if index.has_conflicts() {
+        for path in index.conflicted_paths() {
+            eprintln!("U\t{}", path.display());
+        }
+        bail!("Committing is not possible because you have unmerged files");
+    }
 
-    let mut root = Tree::build(entries)?;
-    root.traverse(&|tree| database.store(tree))?;
+    let root = index.write_tree(&database)?;
+    index.write_updates()?;
 
     let parent = refs.read_head()?;
-    let name = env::var("GIT_AUTHOR_NAME").context("GIT_AUTHOR_NAME")?;
-    let email = env::var("GIT_AUTHOR_EMAIL").context("GIT_AUTHOR_EMAIL")?;
-    let timestamp = OffsetDateTime::try_now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
-    let author = Author::new(&name, &email, timestamp);
+    let author = author_from_env()?;
 
     let mut message = String::new();
     io::stdin().read_to_string(&mut message)?;
 
-    let mut commit = Commit::new(
-        parent.to_owned(),
-        root.oid().to_owned(),
-        author,
-        message.clone(),
-    );
+    let mut commit = Commit::new(parent.into_iter().collect(), root, author, message.clone());
     database.store(&mut commit)?;
 
     let first_line = message.lines().next().ok_or(anyhow!("Empty message"))?;
@@ -58,3 +52,11 @@ pub fn execute(_args: Args) -> Result<()> {
 
     Ok(())
 }
+
+/// The author named by `GIT_AUTHOR_NAME` and `GIT_AUTHOR_EMAIL`, at the current time
+pub(super) fn author_from_env() -> Result<Author> {
+    let name = env::var("GIT_AUTHOR_NAME").context("GIT_AUTHOR_NAME")?;
+    let email = env::var("GIT_AUTHOR_EMAIL").context("GIT_AUTHOR_EMAIL")?;
+    let timestamp = OffsetDateTime::try_now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    Ok(Author::new(&name, &email, timestamp))
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use super::commit::author_from_env;
+use crate::database::{Commit, Object};
+use crate::repository::Repository;
+use crate::revision::Revision;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The tree of the new commit
+    tree: String,
+
+    /// A parent of the new commit, in order
+    #[clap(short, value_name = "parent")]
+    p: Vec<String>,
+
+    /// A paragraph of the commit message
+    #[clap(short, value_name = "message")]
+    m: Vec<String>,
+
+    /// Read the commit message from a file, or stdin for `-`
+    #[clap(short = 'F', value_name = "file", parse(from_os_str))]
+    file: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+
+    let tree = Revision::parse(&args.tree)?.resolve_tree(&repo)?;
+    let mut parents = Vec::new();
+    for expr in &args.p {
+        let oid = Revision::parse(expr)?.resolve(&repo)?;
+        if parents.contains(&oid) {
+            eprintln!("error: duplicate parent {} ignored", oid);
+        } else {
+            parents.push(oid);
+        }
+    }
+
+    let mut message = String::new();
+    for paragraph in &args.m {
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(paragraph);
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
+    }
+    for path in &args.file {
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        if path.as_os_str() == "-" {
+            io::stdin().read_to_string(&mut message)?;
+        } else {
+            let text =
+                fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+            message.push_str(&text);
+        }
+    }
+    if args.m.is_empty() && args.file.is_empty() {
+        io::stdin().read_to_string(&mut message)?;
+    }
+
+    let mut commit = Commit::new(parents, tree, author_from_env()?, message);
+    database.store(&mut commit)?;
+    println!("{}", commit.oid());
+
+    Ok(())
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use regex::bytes::RegexBuilder;
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::database::{hash_blob_stream, Database, TreeFile};
+use crate::diff::{
+    detect_renames_with, diff_file_lists, diff_trees, patch_lines, rename_patch_lines,
+    write_name_status, write_numstat, write_patch_lines, write_shortstat, write_stat, Algorithm,
+    FileStat, MovedMode, OutputOptions, PatchOptions, RenameOptions, Status, Target, WordDiff,
+    WordDiffMode, DEFAULT_CONTEXT, DEFAULT_STAT_WIDTH,
+};
+use crate::index::{Entry, Index};
+use crate::pathspec::Pathspec;
+use crate::repository::Repository;
+use crate::revision::Revision;
+use crate::workspace::Workspace;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Compare the index with HEAD, or with the given commit, rather than the workspace with the
+    /// index
+    #[clap(long, alias = "staged")]
+    cached: bool,
+
+    /// How to choose matching lines: myers (the default), minimal, patience or histogram
+    #[clap(long, value_name = "algorithm")]
+    diff_algorithm: Option<String>,
+
+    /// Show this many unchanged lines around each change
+    #[clap(short = 'U', long, value_name = "n")]
+    unified: Option<usize>,
+
+    /// Color the output: always, never or auto (when writing to a terminal)
+    #[clap(long, value_name = "when", min_values = 0, require_equals = true)]
+    color: Option<Option<String>>,
+
+    /// Diff changed lines word by word, marking words as plain (the default), color or
+    /// porcelain
+    #[clap(long, value_name = "mode", min_values = 0, require_equals = true)]
+    word_diff: Option<Option<String>>,
+
+    /// What a word is, for --word-diff
+    #[clap(long, value_name = "regex")]
+    word_diff_regex: Option<String>,
+
+    /// The same as --word-diff=color --word-diff-regex=<regex>
+    #[clap(long, value_name = "regex", min_values = 0, require_equals = true)]
+    color_words: Option<Option<String>>,
+
+    /// Color moved lines differently: no, default, plain, blocks, zebra or dimmed-zebra
+    #[clap(long, value_name = "mode", min_values = 0, require_equals = true)]
+    color_moved: Option<Option<String>>,
+
+    /// Show the number of lines changed in each file, with a graph of insertions and deletions
+    #[clap(long)]
+    stat: bool,
+
+    /// Show the lines inserted and deleted in each file as tab-separated numbers
+    #[clap(long)]
+    numstat: bool,
+
+    /// Show only the total numbers of files changed, insertions and deletions
+    #[clap(long)]
+    shortstat: bool,
+
+    /// Show only the status letter and path of each changed file
+    #[clap(long)]
+    name_status: bool,
+
+    /// Write patches of binary files that can be applied, rather than just saying they differ
+    #[clap(long)]
+    binary: bool,
+
+    /// Show the patch as well as any summary of it
+    #[clap(short, long)]
+    patch: bool,
+
+    /// Detect renames of files at least this similar (50% by default)
+    #[clap(
+        short = 'M',
+        long,
+        value_name = "n",
+        min_values = 0,
+        require_equals = true
+    )]
+    find_renames: Option<Option<String>>,
+
+    /// Detect copies from changed files as well as renames
+    #[clap(
+        short = 'C',
+        long,
+        value_name = "n",
+        min_values = 0,
+        require_equals = true
+    )]
+    find_copies: Option<Option<String>>,
+
+    /// A commit to compare with the workspace, or with the index if --cached, or two commits to
+    /// compare with each other
+    #[clap(max_values = 2)]
+    commits: Vec<String>,
+
+    /// Only compare the files matching these paths, given after `--`
+    #[clap(last = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+    let workspace = repo.workspace();
+    let mut attributes = repo.attributes()?;
+
+    let config = repo.config()?;
+    let algorithm = match &args.diff_algorithm {
+        Some(name) => name.parse()?,
+        None => match config.get("diff.algorithm") {
+            Some(name) => name.parse()?,
+            None => Algorithm::Myers,
+        },
+    };
+    let options = PatchOptions {
+        algorithm,
+        context: args.unified.unwrap_or(DEFAULT_CONTEXT),
+        binary: args.binary,
+    };
+    let output = output_options(&args, &config)?;
+
+    let mut commits = Vec::new();
+    for expr in &args.commits {
+        commits.push(Revision::parse(expr)?.resolve(&repo)?);
+    }
+    let pathspec = Pathspec::parse(&args.paths, &workspace)?;
+
+    let summary = args.stat || args.numstat || args.shortstat;
+    let show_patch = !args.name_status && (args.patch || !summary);
+
+    let mut out = io::stdout().lock();
+    // Paths whose new side is read from the workspace rather than the database
+    let mut in_workspace = BTreeSet::new();
+    let mut changes = match (&commits[..], args.cached) {
+        ([old, new], false) => {
+            let old = *database.load_commit(old)?.tree();
+            let new = *database.load_commit(new)?.tree();
+            let mut changes = diff_trees(&database, Some(&old), Some(&new), true)?;
+            changes.retain(|change| pathspec.matches(change.path()));
+            changes
+        }
+        ([_, _], true) => bail!("--cached compares the index with a single commit"),
+        (commits, cached) => {
+            let index = repo.index()?;
+            if show_patch {
+                for path in index
+                    .conflicted_paths()
+                    .into_iter()
+                    .filter(|path| pathspec.matches(path))
+                {
+                    out.write_all(b"* Unmerged path ")?;
+                    out.write_all(path.as_os_str().as_bytes())?;
+                    out.write_all(b"\n")?;
+                }
+            }
+
+            let commit = match commits.first() {
+                Some(oid) => Some(*oid),
+                None if cached => repo.refs().read_head()?,
+                None => None,
+            };
+            let old = match commit {
+                Some(oid) => {
+                    let mut files = database.load_tree_list(database.load_commit(&oid)?.tree())?;
+                    files.retain(|path, _| pathspec.matches(path));
+                    files
+                }
+                None if cached => BTreeMap::new(),
+                None => index_files(&index, &pathspec),
+            };
+            let new = if cached {
+                index_files(&index, &pathspec)
+            } else {
+                let files = workspace_files(&workspace, &index, &pathspec)?;
+                in_workspace.extend(files.keys().cloned());
+                files
+            };
+            diff_file_lists(&old, &new)
+        }
+    };
+
+    let renames = match (&args.find_copies, &args.find_renames) {
+        (Some(threshold), _) => Some((threshold, true)),
+        (None, Some(threshold)) => Some((threshold, false)),
+        (None, None) => None,
+    };
+    if let Some((threshold, copies)) = renames {
+        let options = RenameOptions::new(threshold.as_deref().unwrap_or(""), copies)?;
+        changes = detect_renames_with(changes, &options, |file| {
+            let from_workspace = in_workspace.contains(file.rel_path());
+            load_data(&database, &workspace, file, from_workspace)
+        })?;
+    }
+
+    if args.name_status {
+        write_name_status(&mut out, &changes)?;
+        return Ok(());
+    }
+
+    // Moved lines are found across the whole diff, and any summary is written before the
+    // patches, so only then are the patches collected. Otherwise each is written once it is made.
+    let collect = summary || (output.color && output.color_moved.is_some());
+    let mut lines = Vec::new();
+    let mut stats = Vec::new();
+    for change in &changes {
+        let mut load = |file: &Option<TreeFile>, from_workspace: bool| match file {
+            Some(file) => {
+                load_target(&database, &workspace, &mut attributes, file, from_workspace).map(Some)
+            }
+            None => Ok(None),
+        };
+        let old = load(&change.old, false)?;
+        let new = load(&change.new, in_workspace.contains(change.path()))?;
+        if summary {
+            stats.push(FileStat::new(change, old.as_ref(), new.as_ref(), algorithm));
+        }
+        if !show_patch {
+            continue;
+        }
+        let patch = if change.status == Status::TypeChanged {
+            let mut patch = patch_lines(old.as_ref(), None, &options);
+            patch.extend(patch_lines(None, new.as_ref(), &options));
+            patch
+        } else if let (Some(_), Some(old), Some(new)) = (change.similarity, &old, &new) {
+            rename_patch_lines(change, old, new, &options)
+        } else {
+            patch_lines(old.as_ref(), new.as_ref(), &options)
+        };
+        if collect {
+            lines.extend(patch);
+        } else {
+            write_patch_lines(&mut out, &patch, &output)?;
+        }
+    }
+
+    if args.numstat {
+        write_numstat(&mut out, &stats)?;
+    }
+    if args.stat {
+        write_stat(&mut out, &stats, stat_width(), output.color)?;
+    }
+    if args.shortstat {
+        write_shortstat(&mut out, &stats)?;
+    }
+    if summary && !lines.is_empty() {
+        out.write_all(b"\n")?;
+    }
+    write_patch_lines(&mut out, &lines, &output)?;
+
+    Ok(())
+}
+
+/// How to write the patches, from the arguments and the `color.*` and `diff.*` config
+fn output_options(args: &Args, config: &Config) -> Result<OutputOptions> {
+    let mut color = match &args.color {
+        Some(when) => when.as_deref().unwrap_or("always"),
+        None => config
+            .get("color.diff")
+            .or_else(|| config.get("color.ui"))
+            .unwrap_or("auto"),
+    };
+
+    let regex = args
+        .word_diff_regex
+        .as_deref()
+        .or(args.color_words.as_ref().and_then(Option::as_deref))
+        .or_else(|| config.get("diff.wordRegex"));
+    let mode = match (&args.word_diff, &args.color_words) {
+        (Some(Some(mode)), _) if mode == "none" => None,
+        (Some(mode), _) => Some(mode.as_deref().unwrap_or("plain").parse()?),
+        (None, Some(_)) => Some(WordDiffMode::Color),
+        (None, None) if args.word_diff_regex.is_some() => Some(WordDiffMode::Plain),
+        (None, None) => None,
+    };
+    if mode == Some(WordDiffMode::Color) {
+        color = "always";
+    }
+    let word_diff = match mode {
+        Some(mode) => {
+            let regex = match regex {
+                Some(regex) => Some(
+                    RegexBuilder::new(regex)
+                        .multi_line(true)
+                        .build()
+                        .with_context(|| format!("invalid regular expression: {}", regex))?,
+                ),
+                None => None,
+            };
+            Some(WordDiff { mode, regex })
+        }
+        None => None,
+    };
+
+    let color_moved = match &args.color_moved {
+        Some(mode) => mode.as_deref().unwrap_or("default"),
+        None => config.get("diff.colorMoved").unwrap_or("no"),
+    };
+    let color_moved = match color_moved {
+        "no" | "false" => None,
+        "true" => Some(MovedMode::Zebra),
+        mode => Some(mode.parse()?),
+    };
+
+    Ok(OutputOptions {
+        color: match color {
+            "always" | "true" => true,
+            "never" | "false" => false,
+            "auto" => io::stdout().is_terminal(),
+            _ => bail!("invalid color value: {}", color),
+        },
+        word_diff,
+        color_moved,
+    })
+}
+
+/// The width of the terminal, as far as the `COLUMNS` environment variable tells
+fn stat_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_STAT_WIDTH)
+}
+
+/// The merged entries of the index at paths matching `pathspec`
+fn index_files(index: &Index, pathspec: &Pathspec) -> BTreeMap<PathBuf, TreeFile> {
+    index
+        .tracked_paths(Path::new(""))
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .filter_map(|path| {
+            let entry = index.entry(&path)?;
+            let file = TreeFile::new(&path, entry.oid, entry.mode);
+            Some((path, file))
+        })
+        .collect()
+}
+
+/// The files in the workspace at the paths of merged index entries matching `pathspec`. Files
+/// whose stat data matches the index are not read.
+fn workspace_files(
+    workspace: &Workspace,
+    index: &Index,
+    pathspec: &Pathspec,
+) -> Result<BTreeMap<PathBuf, TreeFile>> {
+    let mut files = BTreeMap::new();
+    for (path, file) in index_files(index, pathspec) {
+        let workspace_path = workspace.entry_path(&path);
+        let metadata = match fs::metadata(workspace_path.path()) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        let file = if index.is_unchanged(&workspace_path)? {
+            file
+        } else {
+            let oid = hash_blob_stream(workspace_path.open()?, metadata.len())?;
+            TreeFile::new(&path, oid, Entry::mode_for(&metadata))
+        };
+        files.insert(path, file);
+    }
+    Ok(files)
+}
+
+fn load_target(
+    database: &Database,
+    workspace: &Workspace,
+    attributes: &mut Attributes,
+    file: &TreeFile,
+    from_workspace: bool,
+) -> Result<Target> {
+    let data = load_data(database, workspace, file, from_workspace)?;
+    Target::new(file, data, attributes)
+}
+
+fn load_data(
+    database: &Database,
+    workspace: &Workspace,
+    file: &TreeFile,
+    from_workspace: bool,
+) -> Result<Vec<u8>> {
+    if from_workspace {
+        workspace.entry_path(file.rel_path()).read()
+    } else {
+        Ok(database.load_blob(file.oid())?.data().to_vec())
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+
+use anyhow::{bail, Result};
+
+use crate::database::{ObjectId, TreeFile};
+use crate::diff::{detect_renames, diff_trees, Change, RenameOptions};
+use crate::repository::Repository;
+use crate::revision::Revision;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Compare the files inside changed directories rather than the directories themselves
+    #[clap(short)]
+    r: bool,
+
+    /// Detect renames of files at least this similar (50% by default)
+    #[clap(
+        short = 'M',
+        long,
+        value_name = "n",
+        min_values = 0,
+        require_equals = true
+    )]
+    find_renames: Option<Option<String>>,
+
+    /// Detect copies from changed files as well as renames
+    #[clap(
+        short = 'C',
+        long,
+        value_name = "n",
+        min_values = 0,
+        require_equals = true
+    )]
+    find_copies: Option<Option<String>>,
+
+    /// Show a root commit as adding all of its files
+    #[clap(long)]
+    root: bool,
+
+    /// Two commits to compare, or one to compare with its parent
+    #[clap(required = true, max_values = 2)]
+    commits: Vec<String>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+
+    let mut commits = Vec::new();
+    for expr in &args.commits {
+        commits.push(Revision::parse(expr)?.resolve(&repo)?);
+    }
+    let (old, new) = match commits[..] {
+        [old, new] => {
+            let old = *database.load_commit(&old)?.tree();
+            let new = *database.load_commit(&new)?.tree();
+            (Some(old), new)
+        }
+        [oid] => {
+            let commit = database.load_commit(&oid)?;
+            let parent = match commit.parents().first() {
+                Some(parent) => Some(*database.load_commit(parent)?.tree()),
+                None if args.root => None,
+                None => return Ok(()),
+            };
+            println!("{}", oid);
+            (parent, *commit.tree())
+        }
+        _ => bail!("Expected one or two commits"),
+    };
+
+    let mut changes = diff_trees(&database, old.as_ref(), Some(&new), args.r)?;
+    let renames = match (&args.find_copies, &args.find_renames) {
+        (Some(threshold), _) => Some((threshold, true)),
+        (None, Some(threshold)) => Some((threshold, false)),
+        (None, None) => None,
+    };
+    if let Some((threshold, copies)) = renames {
+        let options = RenameOptions::new(threshold.as_deref().unwrap_or(""), copies)?;
+        changes = detect_renames(&database, changes, &options)?;
+    }
+
+    for change in &changes {
+        println!("{}", raw_line(change));
+    }
+
+    Ok(())
+}
+
+/// Formats a change as in `git diff --raw`
+fn raw_line(change: &Change) -> String {
+    let side = |file: &Option<TreeFile>| match file {
+        Some(file) => (file.file_mode(), file.oid().to_hex()),
+        None => (0, "0".repeat(ObjectId::HEX_SIZE)),
+    };
+    let (old_mode, old_oid) = side(&change.old);
+    let (new_mode, new_oid) = side(&change.new);
+
+    let mut line = format!(
+        ":{:06o} {:06o} {} {} {}",
+        old_mode,
+        new_mode,
+        old_oid,
+        new_oid,
+        change.status.letter()
+    );
+    if let Some(similarity) = change.similarity {
+        line.push_str(&format!("{:03}", similarity));
+    }
+    if let (Some(old), Some(_), Some(_)) = (&change.old, &change.new, change.similarity) {
+        line.push_str(&format!("\t{}", old.rel_path().display()));
+    }
+    line.push_str(&format!("\t{}", change.path().display()));
+    line
+}
@@ -0,0 +1,82 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::database::{
+    compute_oid, hash_blob_stream, Commit, Database, Object, ObjectId, RawObject, Tag, Tree,
+};
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Store the objects in the database rather than only printing their ids
+    #[clap(short)]
+    w: bool,
+
+    /// The type of object to create
+    #[clap(short, value_name = "type", default_value = "blob")]
+    t: String,
+
+    /// Read an object from stdin, before those in any files
+    #[clap(long)]
+    stdin: bool,
+
+    #[clap(parse(from_os_str))]
+    files: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+
+    if !["blob", "tree", "commit", "tag"].contains(&args.t.as_str()) {
+        bail!("invalid object type \"{}\"", args.t);
+    }
+
+    if args.stdin {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        println!("{}", hash_object(&database, &args, data)?);
+    }
+    for path in &args.files {
+        let oid = if args.t == "blob" {
+            // Blobs are streamed, so that large files need not fit in memory
+            let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+            let size = file.metadata()?.len();
+            if args.w {
+                database.store_blob_stream(file, size)?
+            } else {
+                hash_blob_stream(file, size)?
+            }
+        } else {
+            let data = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+            hash_object(&database, &args, data)?
+        };
+        println!("{}", oid);
+    }
+
+    Ok(())
+}
+
+/// Computes the id of an object of the requested type with the given content, storing it with
+/// `-w`. Trees, commits and tags must parse, so that no corrupt objects are written.
+fn hash_object(database: &Database, args: &Args, data: Vec<u8>) -> Result<ObjectId> {
+    match args.t.as_str() {
+        "tree" => Tree::parse(&data).map(drop),
+        "commit" => Commit::parse(&data).map(drop),
+        "tag" => Tag::parse(&data).map(drop),
+        _ => Ok(()),
+    }
+    .with_context(|| format!("Content is not a valid {}", args.t))?;
+
+    let mut object = RawObject::new(&args.t, data);
+    if args.w {
+        database.store(&mut object)?;
+    } else {
+        compute_oid(&mut object);
+    }
+    Ok(*object.oid())
+}
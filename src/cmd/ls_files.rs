@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::ignores::Ignores;
+use crate::pathspec::Pathspec;
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Show the files in the index (the default)
+    #[clap(short, long)]
+    cached: bool,
+
+    /// Show the mode, object and stage of every index entry
+    #[clap(short, long)]
+    stage: bool,
+
+    /// Show files deleted from the workspace
+    #[clap(short, long)]
+    deleted: bool,
+
+    /// Show files changed in the workspace, including deleted ones
+    #[clap(short, long)]
+    modified: bool,
+
+    /// Show untracked files
+    #[clap(short, long)]
+    others: bool,
+
+    /// Only show files matching the exclude patterns
+    #[clap(short, long)]
+    ignored: bool,
+
+    /// Exclude files matching this pattern
+    #[clap(short = 'x', long, value_name = "pattern")]
+    exclude: Vec<String>,
+
+    /// Read exclude patterns from this file
+    #[clap(short = 'X', long, value_name = "file", parse(from_os_str))]
+    exclude_from: Vec<PathBuf>,
+
+    /// Use the usual exclude patterns: `.gitignore` files, `.git/info/exclude` and
+    /// `core.excludesFile`
+    #[clap(long)]
+    exclude_standard: bool,
+
+    /// End each path with a NUL byte rather than a newline
+    #[clap(short)]
+    z: bool,
+
+    #[clap(parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let workspace = repo.workspace();
+    let index = repo.index()?;
+    let pathspec = Pathspec::parse(&args.paths, &workspace)?;
+
+    let has_excludes =
+        args.exclude_standard || !args.exclude.is_empty() || !args.exclude_from.is_empty();
+    if args.ignored && !(args.others || args.cached) {
+        bail!("ls-files -i must be used with either -o or -c");
+    }
+    if args.ignored && !has_excludes {
+        bail!("ls-files --ignored needs some exclude pattern");
+    }
+    let mut ignores = if args.exclude_standard {
+        repo.ignores()?
+    } else {
+        Ignores::new(workspace.root())
+    };
+    for file in &args.exclude_from {
+        ignores.add_file(file)?;
+    }
+    for pattern in &args.exclude {
+        ignores.add_pattern(pattern);
+    }
+
+    let show_cached = args.cached || !(args.stage || args.deleted || args.modified || args.others);
+    let terminator: &[u8] = if args.z { b"\0" } else { b"\n" };
+    let mut out = io::stdout().lock();
+    let mut write_path = |prefix: String, path: &Path| -> io::Result<()> {
+        out.write_all(prefix.as_bytes())?;
+        out.write_all(path.as_os_str().as_bytes())?;
+        out.write_all(terminator)
+    };
+
+    if args.others {
+        let tracked: HashSet<_> = index.iter().map(|entry| entry.path()).collect();
+        let mut others = Vec::new();
+        for file in workspace.list_matching_files(&pathspec)? {
+            let path = file.rel_path();
+            // `-i` shows only the ignored files, which are otherwise left out
+            if !tracked.contains(path.as_path())
+                && (!has_excludes || ignores.is_ignored(path)? == args.ignored)
+            {
+                others.push(path.clone());
+            }
+        }
+        others.sort_by(|a, b| a.as_os_str().as_bytes().cmp(b.as_os_str().as_bytes()));
+        for path in others {
+            write_path(String::new(), &path)?;
+        }
+    }
+
+    for entry in index.iter() {
+        let path = entry.path();
+        if !pathspec.matches(path) || (args.ignored && !ignores.is_ignored(path)?) {
+            continue;
+        }
+        let prefix = if args.stage {
+            format!("{:06o} {} {}\t", entry.mode, entry.oid, entry.stage())
+        } else {
+            String::new()
+        };
+        if show_cached || args.stage {
+            write_path(prefix.clone(), path)?;
+        }
+
+        if !(args.deleted || args.modified) || entry.skip_worktree() {
+            continue;
+        }
+        let file = workspace.entry_path(path);
+        let deleted = file.path().symlink_metadata().is_err();
+        if deleted && args.deleted {
+            write_path(prefix.clone(), path)?;
+        }
+        if args.modified && (deleted || !index.is_unchanged(&file)?) {
+            write_path(prefix, path)?;
+        }
+    }
+
+    Ok(())
+}
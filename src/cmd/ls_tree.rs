@@ -0,0 +1,152 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use anyhow::Result;
+
+use crate::database::{Database, ObjectId, TreeFile};
+use crate::repository::Repository;
+use crate::revision::Revision;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// List the files inside subtrees rather than the subtrees themselves
+    #[clap(short)]
+    r: bool,
+
+    /// Show subtrees as well as their contents when recursing
+    #[clap(short)]
+    t: bool,
+
+    /// Only show subtrees
+    #[clap(short)]
+    d: bool,
+
+    /// Only show the paths of entries
+    #[clap(long, alias = "name-status")]
+    name_only: bool,
+
+    /// Show the size of each blob
+    #[clap(short, long)]
+    long: bool,
+
+    /// The tree, or a commit or tag pointing to it
+    tree: String,
+
+    /// Only show entries at or inside these paths
+    paths: Vec<String>,
+}
+
+struct Lister<'a, W> {
+    args: &'a Args,
+    database: Database,
+    /// Path arguments, with `.` meaning everything. A trailing slash expands the tree it names.
+    specs: Vec<Vec<u8>>,
+    out: W,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let tree = Revision::parse(&args.tree)?.resolve_tree(&repo)?;
+
+    let specs = args
+        .paths
+        .iter()
+        .map(|spec| match spec.as_str() {
+            "." | "./" => Vec::new(),
+            spec => spec.as_bytes().to_vec(),
+        })
+        .collect();
+    let mut lister = Lister {
+        args: &args,
+        database: repo.database(),
+        specs,
+        out: io::stdout().lock(),
+    };
+    lister.list(&tree, b"")
+}
+
+impl<W: Write> Lister<'_, W> {
+    fn list(&mut self, oid: &ObjectId, base: &[u8]) -> Result<()> {
+        let tree = self.database.load_tree(oid)?;
+        for (name, entry) in tree.list_entries() {
+            let path = [base, name.as_bytes()].concat();
+            let is_tree = entry.is_tree();
+            if !self.is_interesting(&path, is_tree) {
+                continue;
+            }
+            if !is_tree {
+                if !self.args.d {
+                    self.show(entry, &path)?;
+                }
+                continue;
+            }
+
+            let recurse = self.recurses_into(&path);
+            // `-d -r` shows the trees it recurses into, as they are all it shows
+            if !recurse || self.args.t || (self.args.d && self.args.r) {
+                self.show(entry, &path)?;
+            }
+            if recurse {
+                self.list(entry.oid(), &[&path[..], b"/"].concat())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries at or inside a path argument are shown, as are the trees leading to one.
+    fn is_interesting(&self, path: &[u8], is_tree: bool) -> bool {
+        self.specs.is_empty()
+            || self.specs.iter().any(|spec| {
+                let end = spec
+                    .iter()
+                    .rposition(|&byte| byte != b'/')
+                    .map_or(0, |n| n + 1);
+                let names_dir = end < spec.len();
+                let spec = &spec[..end];
+                spec.is_empty()
+                    || (path == spec && (is_tree || !names_dir))
+                    || is_inside(path, spec)
+                    || (is_tree && is_inside(spec, path))
+            })
+    }
+
+    /// Trees are expanded with `-r`, or when a path argument names something inside them.
+    fn recurses_into(&self, path: &[u8]) -> bool {
+        self.args.r || self.specs.iter().any(|spec| is_inside(spec, path))
+    }
+
+    fn show(&mut self, entry: &TreeFile, path: &[u8]) -> Result<()> {
+        if !self.args.name_only {
+            let object_type = match entry.file_mode() {
+                _ if entry.is_tree() => "tree",
+                0o160000 => "commit",
+                _ => "blob",
+            };
+            write!(
+                self.out,
+                "{:06o} {} {}",
+                entry.file_mode(),
+                object_type,
+                entry.oid()
+            )?;
+            if self.args.long {
+                if object_type == "blob" {
+                    let size = self.database.load_blob(entry.oid())?.data().len();
+                    write!(self.out, " {:>7}", size)?;
+                } else {
+                    write!(self.out, " {:>7}", "-")?;
+                }
+            }
+            self.out.write_all(b"\t")?;
+        }
+        self.out.write_all(path)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+fn is_inside(path: &[u8], dir: &[u8]) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path[dir.len()] == b'/'
+}
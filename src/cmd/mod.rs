@@ -1,6 +1,22 @@
 mod add;
 mod commit;
+mod commit_tree;
+mod diff;
+mod diff_tree;
+mod hash_object;
 mod init;
+mod ls_files;
+mod ls_tree;
+mod mv;
+mod reset;
+mod restore;
+mod rm;
+mod show;
+mod show_ref;
+mod symbolic_ref;
+mod update_index;
+mod update_ref;
+mod write_tree;
 
 use std::ffi::OsString;
 
@@ -12,6 +28,22 @@ enum Cli {
     Init(init::Args),
     Commit(commit::Args),
     Add(add::Args),
+    UpdateIndex(update_index::Args),
+    Rm(rm::Args),
+    Mv(mv::Args),
+    Reset(reset::Args),
+    Restore(restore::Args),
+    Diff(diff::Args),
+    DiffTree(diff_tree::Args),
+    Show(show::Args),
+    LsTree(ls_tree::Args),
+    LsFiles(ls_files::Args),
+    HashObject(hash_object::Args),
+    WriteTree(write_tree::Args),
+    CommitTree(commit_tree::Args),
+    UpdateRef(update_ref::Args),
+    SymbolicRef(symbolic_ref::Args),
+    ShowRef(show_ref::Args),
 }
 
 pub fn execute<I, T>(args: I) -> Result<()>
@@ -19,10 +51,60 @@ where
     I: IntoIterator<Item = T>,
     T: Into<OsString> + Clone,
 {
-    let opt = Cli::parse_from(args);
+    let opt = Cli::parse_from(attach_optional_values(args));
     match opt {
         Cli::Init(args) => init::execute(args),
         Cli::Commit(args) => commit::execute(args),
         Cli::Add(args) => add::execute(args),
+        Cli::UpdateIndex(args) => update_index::execute(args),
+        Cli::Rm(args) => rm::execute(args),
+        Cli::Mv(args) => mv::execute(args),
+        Cli::Reset(args) => reset::execute(args),
+        Cli::Restore(args) => restore::execute(args),
+        Cli::Diff(args) => diff::execute(args),
+        Cli::DiffTree(args) => diff_tree::execute(args),
+        Cli::Show(args) => show::execute(args),
+        Cli::LsTree(args) => ls_tree::execute(args),
+        Cli::LsFiles(args) => ls_files::execute(args),
+        Cli::HashObject(args) => hash_object::execute(args),
+        Cli::WriteTree(args) => write_tree::execute(args),
+        Cli::CommitTree(args) => commit_tree::execute(args),
+        Cli::UpdateRef(args) => update_ref::execute(args),
+        Cli::SymbolicRef(args) => symbolic_ref::execute(args),
+        Cli::ShowRef(args) => show_ref::execute(args),
     }
 }
+
+/// Short options whose value is optional, and must be attached as in `-M50%`
+const OPTIONAL_VALUE_SHORTS: &[u8] = b"MC";
+
+/// Clap only treats text after a short option as its optional value with an `=` in between,
+/// so `-M50%` is rewritten as `-M=50%`. Arguments after `--` are left alone.
+fn attach_optional_values<I, T>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let mut after_separator = false;
+    args.into_iter()
+        .map(|arg| {
+            let arg: OsString = arg.into();
+            let bytes = arg.as_bytes();
+            after_separator |= bytes == b"--";
+            match bytes {
+                [b'-', short, value @ ..]
+                    if !after_separator
+                        && OPTIONAL_VALUE_SHORTS.contains(short)
+                        && value
+                            .first()
+                            .is_some_and(|b| b.is_ascii_digit() || *b == b'.') =>
+                {
+                    OsString::from_vec([&[b'-', *short, b'='], value].concat())
+                }
+                _ => arg,
+            }
+        })
+        .collect()
+}
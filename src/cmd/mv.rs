@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Overwrite files that already exist at the destination
+    #[clap(short, long)]
+    force: bool,
+
+    /// The paths to move, followed by the destination
+    #[clap(required = true, min_values = 2, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path.clone());
+
+    let workspace = repo.workspace();
+    let mut index = repo.index_for_update()?;
+
+    let (destination, sources) = args.paths.split_last().expect("clap requires paths");
+    let dest_rel_path = workspace.rel_path_of(destination)?;
+    let dest_is_dir = root_path.join(&dest_rel_path).is_dir();
+    if sources.len() > 1 && !dest_is_dir {
+        bail!("destination '{}' is not a directory", destination.display());
+    }
+
+    // Check every move before changing anything, so a bad path leaves the workspace untouched
+    let mut moves = Vec::new();
+    let mut targets = HashSet::new();
+    for source in sources {
+        let source_rel_path = workspace.rel_path_of(source)?;
+        let target = if dest_is_dir {
+            let name = source_rel_path
+                .file_name()
+                .ok_or_else(|| anyhow!("bad source '{}'", source.display()))?;
+            dest_rel_path.join(name)
+        } else {
+            dest_rel_path.clone()
+        };
+        let fail = |reason: &str| {
+            anyhow!(
+                "{}, source={}, destination={}",
+                reason,
+                source_rel_path.display(),
+                target.display()
+            )
+        };
+
+        let metadata = root_path
+            .join(&source_rel_path)
+            .symlink_metadata()
+            .map_err(|_| fail("bad source"))?;
+        let tracked = index.tracked_paths(&source_rel_path);
+        if tracked.is_empty() {
+            return Err(fail("not under version control"));
+        }
+        if tracked.iter().any(|path| index.entry(path).is_none()) {
+            return Err(fail("conflicted"));
+        }
+        if target.starts_with(&source_rel_path) {
+            return Err(fail("can not move directory into itself"));
+        }
+        if !target
+            .parent()
+            .is_none_or(|parent| root_path.join(parent).is_dir())
+        {
+            return Err(fail("destination directory does not exist"));
+        }
+        // A tracked target counts as existing even if it has been deleted from the workspace
+        let target_tracked = index.tracked_paths(&target);
+        let target_metadata = root_path.join(&target).symlink_metadata().ok();
+        if target_metadata.is_some() || !target_tracked.is_empty() {
+            let target_is_dir = target_metadata.is_some_and(|metadata| metadata.is_dir())
+                || target_tracked.iter().any(|path| *path != target);
+            if metadata.is_dir() || target_is_dir {
+                return Err(fail("destination already exists"));
+            }
+            if !args.force {
+                return Err(fail("destination exists"));
+            }
+        }
+        if !targets.insert(target.clone()) {
+            return Err(fail("multiple sources for the same target"));
+        }
+
+        moves.push((source_rel_path, target, tracked));
+    }
+
+    // If a rename fails, the ones already made are undone so the workspace matches the index
+    for (i, (source, target, _)) in moves.iter().enumerate() {
+        let renamed = fs::rename(root_path.join(source), root_path.join(target));
+        if let Err(err) = renamed {
+            for (source, target, _) in moves[..i].iter().rev() {
+                let _ = fs::rename(root_path.join(target), root_path.join(source));
+            }
+            return Err(err).with_context(|| {
+                format!("renaming '{}' to '{}'", source.display(), target.display())
+            });
+        }
+    }
+    for (source, target, tracked) in moves {
+        for path in tracked {
+            index.rename(&path, &moved_path(&path, &source, &target))?;
+        }
+    }
+
+    index.write_updates()?;
+
+    Ok(())
+}
+
+/// Where `path` ends up when `source`, which is either `path` or one of its parents, is moved
+/// to `target`
+fn moved_path(path: &Path, source: &Path, target: &Path) -> PathBuf {
+    match path.strip_prefix(source) {
+        Ok(rest) if rest.as_os_str().is_empty() => target.to_owned(),
+        Ok(rest) => target.join(rest),
+        Err(_) => panic!("Programmer error: {:?} is not inside {:?}", path, source),
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::database::ObjectId;
+use crate::index::Index;
+use crate::pathspec::Pathspec;
+use crate::repository::Repository;
+use crate::revision::Revision;
+use crate::workspace::{check_path, Workspace};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Only move HEAD, leaving the index and workspace alone
+    #[clap(long, group = "mode")]
+    soft: bool,
+
+    /// Move HEAD and reset the index, leaving the workspace alone (the default)
+    #[clap(long, group = "mode")]
+    mixed: bool,
+
+    /// Move HEAD and reset both the index and the workspace, discarding all changes
+    #[clap(long, group = "mode")]
+    hard: bool,
+
+    /// The commit to reset to (HEAD if not given), optionally followed by paths to reset
+    #[clap(parse(from_os_str))]
+    targets: Vec<OsString>,
+
+    /// Paths to reset, when given after `--`
+    #[clap(last = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+
+    let (revision, paths) = split_targets(&repo, args.targets, args.paths)?;
+    let oid = match revision {
+        Some(oid) => Some(oid),
+        None => repo.refs().read_head()?,
+    };
+
+    if !paths.is_empty() {
+        if args.soft || args.hard {
+            bail!(
+                "Cannot do {} reset with paths",
+                if args.soft { "soft" } else { "hard" }
+            );
+        }
+        return reset_paths(&repo, oid, &paths);
+    }
+
+    let oid = oid.ok_or_else(|| anyhow!("Cannot reset: HEAD does not point to a commit yet"))?;
+    let database = repo.database();
+    let refs = repo.refs();
+    let workspace = repo.workspace();
+    let mut index = repo.index_for_update()?;
+
+    let commit = database.load_commit(&oid)?;
+    if args.hard {
+        reset_workspace(&repo, &mut index, &workspace, commit.tree())?;
+    } else if !args.soft {
+        let files = database.load_tree_list(commit.tree())?;
+        index.read_tree(&files, |_| true)?;
+    }
+
+    if let Some(head) = refs.read_head()? {
+        refs.update_ref("ORIG_HEAD", &head)?;
+    }
+    refs.update_head(&oid)?;
+
+    if args.hard {
+        println!(
+            "HEAD is now at {} {}",
+            &oid.to_hex()[..7],
+            commit.title_line()
+        );
+    } else if !args.soft {
+        report_unstaged(&workspace, index.refresh(&workspace)?);
+    }
+
+    index.write_updates()?;
+
+    Ok(())
+}
+
+/// Separates the optional revision at the start of the arguments from the paths after it. An
+/// argument before `--` is a revision; otherwise the first argument is one if it resolves, and a
+/// path if it is not a revision expression or does not resolve but exists in the workspace.
+fn split_targets(
+    repo: &Repository,
+    mut targets: Vec<OsString>,
+    paths_after_separator: Vec<PathBuf>,
+) -> Result<(Option<ObjectId>, Vec<PathBuf>)> {
+    if !paths_after_separator.is_empty() {
+        if targets.len() > 1 {
+            bail!("Only one revision may be given before '--'");
+        }
+        let revision = match targets.pop() {
+            Some(expr) => Some(Revision::parse(&expr.to_string_lossy())?.resolve(repo)?),
+            None => None,
+        };
+        return Ok((revision, paths_after_separator));
+    }
+
+    let workspace = repo.workspace();
+    let exists = |expr: &OsString| workspace.entry_path(expr).path().symlink_metadata().is_ok();
+    let revision = match targets.first() {
+        Some(expr) => match expr.to_str().map(Revision::parse) {
+            Some(Ok(revision)) => match revision.resolve(repo) {
+                Ok(oid) => Some(oid),
+                // A misspelt revision is reported, rather than reset as a path that matches nothing
+                Err(_) if exists(expr) => None,
+                Err(err) => return Err(err),
+            },
+            _ => None,
+        },
+        None => None,
+    };
+    let skip = if revision.is_some() { 1 } else { 0 };
+    let paths = targets.into_iter().skip(skip).map(PathBuf::from).collect();
+    Ok((revision, paths))
+}
+
+/// Unstages `paths` by resetting their index entries to the tree of `oid`.
+fn reset_paths(repo: &Repository, oid: Option<ObjectId>, paths: &[PathBuf]) -> Result<()> {
+    let database = repo.database();
+    let workspace = repo.workspace();
+    let mut index = repo.index_for_update()?;
+
+    let files = match oid {
+        Some(oid) => database.load_tree_list(database.load_commit(&oid)?.tree())?,
+        None => BTreeMap::new(),
+    };
+    let pathspec = Pathspec::parse(paths, &workspace)?;
+    index.read_tree(&files, |path| pathspec.matches(path))?;
+
+    report_unstaged(&workspace, index.refresh(&workspace)?);
+    index.write_updates()?;
+
+    Ok(())
+}
+
+/// Makes the index and the tracked files in the workspace match `tree`, discarding changes.
+fn reset_workspace(
+    repo: &Repository,
+    index: &mut Index,
+    workspace: &Workspace,
+    tree: &ObjectId,
+) -> Result<()> {
+    let database = repo.database();
+    let files = database.load_tree_list(tree)?;
+    // Nothing is touched unless every path in the tree can be checked out
+    for path in files.keys() {
+        check_path(path.as_os_str().as_bytes())?;
+    }
+
+    for path in index.tracked_paths(Path::new("")) {
+        if !files.contains_key(&path) {
+            index.remove(&path);
+            workspace.remove_file(&path)?;
+        }
+    }
+
+    for (path, file) in &files {
+        let workspace_path = workspace.entry_path(path);
+        let is_current = match index.entry(path) {
+            Some(entry) => {
+                entry.oid == *file.oid()
+                    && entry.mode == file.file_mode()
+                    && index.is_unchanged(&workspace_path)?
+            }
+            None => false,
+        };
+        if !is_current {
+            let blob = database.load_blob(file.oid())?;
+            workspace.write_file(path, blob.data(), file.file_mode())?;
+            index.add(&workspace_path, file.oid())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn report_unstaged(workspace: &Workspace, stale: Vec<PathBuf>) {
+    if stale.is_empty() {
+        return;
+    }
+    println!("Unstaged changes after reset:");
+    for path in stale {
+        let status = if workspace.entry_path(&path).path().exists() {
+            "M"
+        } else {
+            "D"
+        };
+        println!("{}\t{}", status, path.display());
+    }
+}
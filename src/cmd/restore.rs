@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::database::{hash_blob_stream, Database, ObjectId, TreeFile};
+use crate::index::Index;
+use crate::pathspec::Pathspec;
+use crate::repository::Repository;
+use crate::revision::Revision;
+use crate::workspace::Workspace;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Restore from this commit instead of the index (or HEAD with --staged)
+    #[clap(short, long)]
+    source: Option<String>,
+
+    /// Restore the index
+    #[clap(short = 'S', long)]
+    staged: bool,
+
+    /// Restore the workspace (the default unless --staged is given)
+    #[clap(short = 'W', long)]
+    worktree: bool,
+
+    #[clap(required = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+
+    let database = repo.database();
+    let workspace = repo.workspace();
+    let mut index = repo.index_for_update()?;
+
+    let worktree = args.worktree || !args.staged;
+    let source = match &args.source {
+        Some(expr) => Some(Revision::parse(expr)?.resolve(&repo)?),
+        None if args.staged => repo.refs().read_head()?,
+        None => None,
+    };
+    // Without a source commit, the workspace is restored from the index
+    let files = match source {
+        Some(oid) => Some(database.load_tree_list(database.load_commit(&oid)?.tree())?),
+        None if args.source.is_none() && args.staged => Some(BTreeMap::new()),
+        None => None,
+    };
+
+    let pathspec = Pathspec::parse(&args.paths, &workspace)?;
+    let tracked: Vec<_> = index
+        .tracked_paths(Path::new(""))
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .collect();
+
+    let source_paths = files.iter().flat_map(|files| files.keys());
+    let known = tracked.iter().chain(source_paths).map(PathBuf::as_path);
+    for (item, seen) in pathspec.item_matches(known) {
+        if seen.is_none() {
+            bail!("pathspec '{}' did not match any file(s) known to jit", item);
+        }
+    }
+
+    match &files {
+        Some(files) => {
+            if args.staged {
+                index.read_tree(files, |path| pathspec.matches(path))?;
+            }
+            if worktree {
+                restore_from_tree(
+                    &database, &workspace, &mut index, files, &pathspec, &tracked,
+                )?;
+            }
+        }
+        None => restore_from_index(&database, &workspace, &mut index, &tracked)?,
+    }
+
+    index.write_updates()?;
+
+    Ok(())
+}
+
+/// Overwrites the files at `paths` with their content in the index. Nothing is written if any
+/// of them is unmerged.
+fn restore_from_index(
+    database: &Database,
+    workspace: &Workspace,
+    index: &mut Index,
+    paths: &[PathBuf],
+) -> Result<()> {
+    if let Some(path) = paths.iter().find(|path| index.entry(path).is_none()) {
+        bail!("path '{}' is unmerged", path.display());
+    }
+
+    for path in paths {
+        let entry = index
+            .entry(path)
+            .expect("Programmer error: unmerged paths were rejected");
+        let (oid, mode) = (entry.oid, entry.mode);
+
+        let file = workspace.entry_path(path);
+        if !index.is_unchanged(&file)? {
+            workspace.write_file(path, database.load_blob(&oid)?.data(), mode)?;
+            index.add(&file, &oid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the files selected by `pathspec` match `files`. Files that were `tracked` but are not
+/// in `files` are deleted.
+fn restore_from_tree(
+    database: &Database,
+    workspace: &Workspace,
+    index: &mut Index,
+    files: &BTreeMap<PathBuf, TreeFile>,
+    pathspec: &Pathspec,
+    tracked: &[PathBuf],
+) -> Result<()> {
+    for path in tracked {
+        if !files.contains_key(path) {
+            workspace.remove_file(path)?;
+        }
+    }
+
+    for (path, file) in files.iter().filter(|(path, _)| pathspec.matches(path)) {
+        let workspace_path = workspace.entry_path(path);
+        let staged = index
+            .entry(path)
+            .is_some_and(|entry| entry.oid == *file.oid() && entry.mode == file.file_mode());
+        let is_current = if staged {
+            index.is_unchanged(&workspace_path)?
+        } else {
+            has_content(&workspace_path.path(), file.oid())?
+        };
+
+        if !is_current {
+            let blob = database.load_blob(file.oid())?;
+            workspace.write_file(path, blob.data(), file.file_mode())?;
+        }
+        // Record the new stat data, but only for entries that already match the file
+        if staged {
+            index.add(&workspace_path, file.oid())?;
+        }
+    }
+    Ok(())
+}
+
+fn has_content(path: &Path, oid: &ObjectId) -> Result<bool> {
+    match fs::File::open(path) {
+        Ok(file) => {
+            let size = file.metadata()?.len();
+            Ok(file.metadata()?.is_file() && hash_blob_stream(file, size)? == *oid)
+        }
+        Err(_) => Ok(false),
+    }
+}
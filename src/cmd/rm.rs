@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::pathspec::{MatchKind, Pathspec};
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Only remove the paths from the index, keeping the files in the workspace
+    #[clap(long)]
+    cached: bool,
+
+    /// Allow removing directories and everything inside them
+    #[clap(short)]
+    r: bool,
+
+    /// Remove files even if they have changes that would be lost
+    #[clap(short, long)]
+    force: bool,
+
+    #[clap(required = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+
+    let workspace = repo.workspace();
+    let database = repo.database();
+    let mut index = repo.index_for_update()?;
+
+    let pathspec = Pathspec::parse(&args.paths, &workspace)?;
+    let tracked = index.tracked_paths(Path::new(""));
+    for (item, seen) in pathspec.item_matches(tracked.iter().map(PathBuf::as_path)) {
+        match seen {
+            None => bail!("pathspec '{}' did not match any files", item),
+            Some(MatchKind::Recursive) if !args.r => {
+                bail!("not removing '{}' recursively without -r", item)
+            }
+            _ => {}
+        }
+    }
+    let paths: Vec<_> = tracked
+        .into_iter()
+        .filter(|path| pathspec.matches(path))
+        .collect();
+
+    if !args.force {
+        let head = match repo.refs().read_head()? {
+            Some(oid) => database.load_tree_list(database.load_commit(&oid)?.tree())?,
+            None => BTreeMap::new(),
+        };
+
+        let mut staged_and_local = Vec::new();
+        let mut staged = Vec::new();
+        let mut local = Vec::new();
+        for path in &paths {
+            // Removing an unmerged path resolves the conflict, so there is nothing to lose
+            let entry = match index.entry(path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let staged_changes = head
+                .get(path)
+                .is_none_or(|file| *file.oid() != entry.oid || file.file_mode() != entry.mode);
+            let file = workspace.entry_path(path);
+            let local_changes =
+                file.path().symlink_metadata().is_ok() && !index.is_unchanged(&file)?;
+
+            if staged_changes && local_changes {
+                staged_and_local.push(path);
+            } else if !args.cached && staged_changes {
+                staged.push(path);
+            } else if !args.cached && local_changes {
+                local.push(path);
+            }
+        }
+
+        let problems = [
+            (
+                staged_and_local,
+                "staged content different from both the file and the HEAD",
+            ),
+            (staged, "changes staged in the index"),
+            (local, "local modifications"),
+        ];
+        let mut refused = false;
+        for (paths, problem) in &problems {
+            if !paths.is_empty() {
+                eprintln!("error: the following files have {}:", problem);
+                for path in paths {
+                    eprintln!("    {}", path.display());
+                }
+                refused = true;
+            }
+        }
+        if refused {
+            bail!("Refusing to remove files with changes (use -f to force removal)");
+        }
+    }
+
+    for path in &paths {
+        index.remove(path);
+        if !args.cached {
+            workspace.remove_file(path)?;
+        }
+        println!("rm '{}'", path.display());
+    }
+
+    index.write_updates()?;
+
+    Ok(())
+}
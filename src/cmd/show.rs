@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::attributes::Attributes;
+use crate::database::{Author, Commit, Database, ObjectId, ParsedObject, TreeFile};
+use crate::diff::{
+    combined_patch, diff_trees, patch_lines, write_patch_lines, OutputOptions, PatchOptions,
+    Status, Target,
+};
+use crate::repository::Repository;
+use crate::revision::Revision;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// The objects to show, such as commits, tags or `<commit>:<path>` (HEAD by default)
+    objects: Vec<String>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+    let mut attributes = repo.attributes()?;
+
+    let exprs = match &args.objects[..] {
+        [] => vec!["HEAD".to_owned()],
+        exprs => exprs.to_vec(),
+    };
+    let mut objects = Vec::new();
+    for expr in exprs {
+        let oid = Revision::parse(&expr)?.resolve_object(&repo)?;
+        objects.push((expr, oid));
+    }
+
+    let mut out = io::stdout().lock();
+    // Everything but blobs is separated from whatever was shown before it by a blank line
+    let mut shown_one = false;
+    // Each commit is only shown once, even if it is asked for again or through a tag
+    let mut shown_commits = HashSet::new();
+    for (expr, oid) in objects {
+        let mut oid = oid;
+        loop {
+            let object = database.load(&oid)?;
+            if matches!(object, ParsedObject::Commit(_)) && !shown_commits.insert(oid) {
+                break;
+            }
+            if shown_one && !matches!(object, ParsedObject::Blob(_)) {
+                writeln!(out)?;
+            }
+            shown_one = true;
+
+            match object {
+                ParsedObject::Blob(blob) => out.write_all(blob.data())?,
+                ParsedObject::Tree(tree) => {
+                    writeln!(out, "tree {}\n", expr)?;
+                    for (name, entry) in tree.list_entries() {
+                        out.write_all(name.as_bytes())?;
+                        out.write_all(if entry.is_tree() { b"/\n" } else { b"\n" })?;
+                    }
+                }
+                ParsedObject::Commit(commit) => {
+                    show_commit(&mut out, &database, &mut attributes, &oid, &commit)?;
+                }
+                ParsedObject::Tag(tag) => {
+                    writeln!(out, "tag {}", tag.name())?;
+                    if let Some(tagger) = tag.tagger() {
+                        write_author(&mut out, "Tagger", tagger)?;
+                    }
+                    writeln!(out)?;
+                    out.write_all(tag.message().as_bytes())?;
+                    if !tag.message().is_empty() && !tag.message().ends_with('\n') {
+                        writeln!(out)?;
+                    }
+                    oid = *tag.object();
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_author<W: Write>(out: &mut W, role: &str, author: &Author) -> io::Result<()> {
+    writeln!(out, "{}: {} <{}>", role, author.name(), author.email())?;
+    writeln!(out, "Date:   {}", author.readable_time())
+}
+
+/// Shows the commit's header and message as in `git log`, then its patch: against its parent,
+/// as a combined diff against all of its parents if it is a merge, or adding all of its files
+/// if it has no parents.
+fn show_commit<W: Write>(
+    out: &mut W,
+    database: &Database,
+    attributes: &mut Attributes,
+    oid: &ObjectId,
+    commit: &Commit,
+) -> Result<()> {
+    writeln!(out, "commit {}", oid)?;
+    let parents = commit.parents();
+    if parents.len() > 1 {
+        let abbrevs: Vec<_> = parents
+            .iter()
+            .map(|oid| oid.to_hex()[..7].to_owned())
+            .collect();
+        writeln!(out, "Merge: {}", abbrevs.join(" "))?;
+    }
+    write_author(out, "Author", commit.author())?;
+    writeln!(out)?;
+
+    let message: Vec<_> = commit.message().lines().map(str::trim_end).collect();
+    let start = message.iter().position(|line| !line.is_empty());
+    let end = message.iter().rposition(|line| !line.is_empty());
+    if let (Some(start), Some(end)) = (start, end) {
+        for line in &message[start..=end] {
+            writeln!(out, "    {}", line)?;
+        }
+    }
+
+    let options = PatchOptions::default();
+    let mut load = |file: &Option<TreeFile>| match file {
+        Some(file) => {
+            let data = database.load_blob(file.oid())?.data().to_vec();
+            Target::new(file, data, attributes).map(Some)
+        }
+        None => Ok(None),
+    };
+
+    if parents.len() > 1 {
+        // Only files that differ from every parent are shown
+        let mut sides: BTreeMap<PathBuf, Vec<Option<TreeFile>>> = BTreeMap::new();
+        let mut result = BTreeMap::new();
+        for parent in parents {
+            let parent_tree = *database.load_commit(parent)?.tree();
+            for change in diff_trees(database, Some(&parent_tree), Some(commit.tree()), true)? {
+                let path = change.path().to_owned();
+                sides.entry(path.clone()).or_default().push(change.old);
+                result.insert(path, change.new);
+            }
+        }
+
+        sides.retain(|_, parent_files| parent_files.len() == parents.len());
+        writeln!(out)?;
+        for (path, parent_files) in sides {
+            let mut parent_targets = Vec::new();
+            for file in &parent_files {
+                parent_targets.push(load(file)?);
+            }
+            let result = load(&result[&path])?;
+            for line in combined_patch(&parent_targets, result.as_ref(), &options) {
+                out.write_all(&line)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        return Ok(());
+    }
+
+    let parent_tree = match parents.first() {
+        Some(parent) => Some(*database.load_commit(parent)?.tree()),
+        None => None,
+    };
+    let mut lines = Vec::new();
+    for change in diff_trees(database, parent_tree.as_ref(), Some(commit.tree()), true)? {
+        let old = load(&change.old)?;
+        let new = load(&change.new)?;
+        if change.status == Status::TypeChanged {
+            lines.extend(patch_lines(old.as_ref(), None, &options));
+            lines.extend(patch_lines(None, new.as_ref(), &options));
+        } else {
+            lines.extend(patch_lines(old.as_ref(), new.as_ref(), &options));
+        }
+    }
+    if !lines.is_empty() {
+        writeln!(out)?;
+    }
+    write_patch_lines(out, &lines, &OutputOptions::default())?;
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::process;
+
+use anyhow::{anyhow, Result};
+
+use crate::refs::is_safe_ref_name;
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Show HEAD before the other refs
+    #[clap(long)]
+    head: bool,
+
+    /// Only show branches
+    #[clap(long)]
+    heads: bool,
+
+    /// Only show tags
+    #[clap(long)]
+    tags: bool,
+
+    /// Only show the object ids
+    #[clap(short = 's', long)]
+    hash: bool,
+
+    /// Look up each pattern as the full name of a ref, failing if one does not exist
+    #[clap(long)]
+    verify: bool,
+
+    /// Print nothing, only setting the exit status
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Only show refs with these names or whose names end with `/` and one of these
+    patterns: Vec<String>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let refs = repo.refs();
+
+    let mut found = Vec::new();
+    if args.verify {
+        for name in &args.patterns {
+            let is_full_name = name == "HEAD" || name.starts_with("refs/");
+            let oid = if is_full_name && is_safe_ref_name(name) {
+                refs.read_exact(name)?
+            } else {
+                None
+            };
+            let oid = oid.ok_or_else(|| anyhow!("'{}' - not a valid ref", name))?;
+            found.push((name.clone(), oid));
+        }
+    } else {
+        if args.head {
+            if let Some(oid) = refs.read_head()? {
+                found.push(("HEAD".to_owned(), oid));
+            }
+        }
+        for (name, oid) in refs.list_refs()? {
+            let kind_matches = !(args.heads || args.tags)
+                || (args.heads && name.starts_with("refs/heads/"))
+                || (args.tags && name.starts_with("refs/tags/"));
+            let pattern_matches = args.patterns.is_empty()
+                || args
+                    .patterns
+                    .iter()
+                    .any(|pattern| name == *pattern || name.ends_with(&format!("/{}", pattern)));
+            if kind_matches && pattern_matches {
+                found.push((name, oid));
+            }
+        }
+    }
+
+    if !args.quiet {
+        for (name, oid) in &found {
+            if args.hash {
+                println!("{}", oid);
+            } else {
+                println!("{} {}", oid, name);
+            }
+        }
+    }
+    if found.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::fs;
+use std::process;
+
+use anyhow::{bail, Result};
+
+use crate::refs::is_safe_ref_name;
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Exit with status 1 and no message if the ref is not symbolic
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Print the target without its `refs/heads/` or similar prefix
+    #[clap(long)]
+    short: bool,
+
+    /// The symbolic ref, such as `HEAD`
+    name: String,
+
+    /// A ref under `refs/` to point it to
+    target: Option<String>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let refs = repo.refs();
+
+    if !(args.name == "HEAD" || args.name.starts_with("refs/")) || !is_safe_ref_name(&args.name) {
+        bail!("'{}' is not a valid symbolic ref name", args.name);
+    }
+    if let Some(target) = &args.target {
+        if !target.starts_with("refs/") || !is_safe_ref_name(target) {
+            bail!("Refusing to point {} outside of refs/", args.name);
+        }
+        return refs.set_symbolic_ref(&args.name, target);
+    }
+
+    match refs.read_symbolic_ref(&args.name)? {
+        Some(target) if args.short => println!("{}", short_name(&target)),
+        Some(target) => println!("{}", target),
+        None if args.quiet => process::exit(1),
+        None => bail!("ref {} is not a symbolic ref", args.name),
+    }
+    Ok(())
+}
+
+fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
@@ -0,0 +1,112 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::database::ObjectId;
+use crate::index::Index;
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Refresh the stat information of entries whose files are unchanged
+    #[clap(long)]
+    refresh: bool,
+
+    /// Write the index in the given format version (2, 3 or 4)
+    #[clap(long)]
+    index_version: Option<u32>,
+
+    /// Mark the given paths so that their files are not checked for changes
+    #[clap(long, conflicts_with = "no-skip-worktree")]
+    skip_worktree: bool,
+
+    /// Clear the skip-worktree bit of the given paths
+    #[clap(long)]
+    no_skip_worktree: bool,
+
+    /// Read entries from stdin as `<mode> <oid> [<stage>]\t<path>`, where stages 1 to 3 record
+    /// the sides of a merge conflict and mode 0 removes the path
+    #[clap(long)]
+    index_info: bool,
+
+    #[clap(parse(from_os_str))]
+    paths: Vec<PathBuf>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+
+    let workspace = repo.workspace();
+    let mut index = repo.index_for_update()?;
+
+    if let Some(version) = args.index_version {
+        index.set_version(version)?;
+    }
+
+    if args.skip_worktree || args.no_skip_worktree {
+        for path in &args.paths {
+            let path = workspace.path(path)?;
+            index.set_skip_worktree(path.rel_path(), args.skip_worktree)?;
+        }
+    } else if !args.paths.is_empty() {
+        bail!("Paths are only accepted with --skip-worktree or --no-skip-worktree");
+    }
+
+    if args.index_info {
+        // Paths are read as raw bytes, as they need not be valid UTF-8
+        for line in io::stdin().lock().split(b'\n') {
+            add_index_info(&mut index, &line?)?;
+        }
+    }
+
+    let mut stale = Vec::new();
+    if args.refresh {
+        stale = index.refresh(&workspace)?;
+        for path in &stale {
+            println!("{}: needs update", path.display());
+        }
+    }
+
+    index.write_updates()?;
+
+    if !stale.is_empty() {
+        bail!("Index is not up to date");
+    }
+
+    Ok(())
+}
+
+fn add_index_info(index: &mut Index, line: &[u8]) -> Result<()> {
+    let text = String::from_utf8_lossy(line);
+    let tab = line
+        .iter()
+        .position(|b| *b == b'\t')
+        .ok_or_else(|| anyhow!("Malformed index info: {}", text))?;
+    let info =
+        str::from_utf8(&line[..tab]).with_context(|| format!("Malformed index info: {}", text))?;
+    let path = Path::new(OsStr::from_bytes(&line[tab + 1..]));
+    let fields: Vec<_> = info.split_whitespace().collect();
+    let (mode, oid, stage) = match fields[..] {
+        [mode, oid] => (mode, oid, "0"),
+        [mode, oid, stage] => (mode, oid, stage),
+        _ => bail!("Malformed index info: {}", text),
+    };
+
+    let mode = u32::from_str_radix(mode, 8).with_context(|| format!("Bad mode in: {}", text))?;
+    if mode == 0 {
+        index.remove(path);
+        return Ok(());
+    }
+    let oid: ObjectId = oid.parse()?;
+    let stage = stage
+        .parse()
+        .with_context(|| format!("Bad stage in: {}", text))?;
+
+    index.add_entry(path, &oid, mode, stage)
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::io::{self, BufRead};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::database::ObjectId;
+use crate::refs::{is_safe_ref_name, RefTransaction};
+use crate::repository::Repository;
+use crate::revision::Revision;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Delete the ref, after checking that it has the old value if one is given
+    #[clap(short)]
+    d: bool,
+
+    /// Read `update`, `create`, `delete` and `verify` commands from stdin, one per line. Every
+    /// ref is locked and checked before any is changed, and the changes already made are undone
+    /// if a later one fails. This is not atomic: readers may briefly see some of the changes
+    /// before the others, and a crash while they are applied can leave only some made.
+    #[clap(long, conflicts_with = "d")]
+    stdin: bool,
+
+    /// The ref to change, such as `refs/heads/main`
+    #[clap(required_unless_present = "stdin")]
+    name: Option<String>,
+
+    /// The new value, or with `-d` the old one
+    #[clap(required_unless_present_any = &["stdin", "d"])]
+    new_value: Option<String>,
+
+    /// The value the ref must have before it is changed, where an all-zero id means that it
+    /// must not exist yet
+    old_value: Option<String>,
+}
+
+pub fn execute(args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let refs = repo.refs();
+    let mut transaction = refs.transaction();
+
+    if args.stdin {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            add_command(&repo, &mut transaction, &line)
+                .with_context(|| format!("Invalid update-ref command: {}", line))?;
+        }
+    } else {
+        let name = check_name(args.name.as_deref().expect("Required by clap"))?;
+        let (new, old) = if args.d {
+            if args.old_value.is_some() {
+                bail!("update-ref -d takes at most the ref and its old value");
+            }
+            (None, args.new_value)
+        } else {
+            (args.new_value, args.old_value)
+        };
+        let new = match new {
+            Some(new) => parse_value(&repo, &new)?,
+            None => None,
+        };
+        let old = old.map(|old| parse_value(&repo, &old)).transpose()?;
+        transaction.update(name, old, new);
+    }
+
+    transaction.commit()
+}
+
+/// Adds a line such as `update <ref> <new> [<old>]` from `--stdin` to the transaction.
+fn add_command(repo: &Repository, transaction: &mut RefTransaction, line: &str) -> Result<()> {
+    let words: Vec<_> = line.split(' ').collect();
+    let value = |word: &str| parse_value(repo, word);
+    match words[..] {
+        ["update", name, new] => transaction.update(check_name(name)?, None, value(new)?),
+        ["update", name, new, old] => {
+            transaction.update(check_name(name)?, Some(value(old)?), value(new)?)
+        }
+        ["create", name, new] => {
+            let new = value(new)?.ok_or_else(|| anyhow!("create needs a new value"))?;
+            transaction.update(check_name(name)?, Some(None), Some(new))
+        }
+        ["delete", name] => transaction.update(check_name(name)?, None, None),
+        ["delete", name, old] => {
+            let old = value(old)?.ok_or_else(|| anyhow!("delete needs an old value"))?;
+            transaction.update(check_name(name)?, Some(Some(old)), None)
+        }
+        ["verify", name] => transaction.verify(check_name(name)?, None),
+        ["verify", name, old] => transaction.verify(check_name(name)?, value(old)?),
+        _ => bail!("Unknown command or wrong number of arguments"),
+    }
+    Ok(())
+}
+
+/// An all-zero id or an empty value means that the ref does not exist, and anything else is
+/// resolved as a revision.
+fn parse_value(repo: &Repository, value: &str) -> Result<Option<ObjectId>> {
+    if value.bytes().all(|b| b == b'0') && (value.is_empty() || value.len() == ObjectId::HEX_SIZE) {
+        return Ok(None);
+    }
+    Revision::parse(value)?.resolve_object(repo).map(Some)
+}
+
+fn check_name(name: &str) -> Result<&str> {
+    if !is_safe_ref_name(name) {
+        bail!("'{}' is not a valid ref name", name);
+    }
+    Ok(name)
+}
@@ -0,0 +1,22 @@
+use std::fs;
+
+use anyhow::Result;
+
+use crate::repository::Repository;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {}
+
+pub fn execute(_args: Args) -> Result<()> {
+    let root_path = fs::canonicalize(".")?;
+    let repo = Repository::new(root_path);
+    let database = repo.database();
+
+    let mut index = repo.index_for_update()?;
+    let oid = index.write_tree(&database)?;
+    // Keeps the trees that were written in the cache tree, for next time
+    index.write_updates()?;
+
+    println!("{}", oid);
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Settings read from a git-style config file.
+///
+/// Keys are looked up as `section.name` or `section.subsection.name`. Section and variable names
+/// are case-insensitive, subsection names are not. When a variable is set more than once, the
+/// last value wins.
+#[derive(Debug, Default)]
+pub struct Config {
+    values: HashMap<String, Option<String>>,
+}
+
+impl Config {
+    #[tracing::instrument(name = "Config::load")]
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("Reading {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => bail!(err),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut values = HashMap::new();
+        let mut section: Option<String> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let end = header
+                    .find(']')
+                    .ok_or_else(|| anyhow!("Bad section header on line {}", number + 1))?;
+                section = Some(Self::parse_section(&header[..end])?);
+                continue;
+            }
+
+            let section = section
+                .as_ref()
+                .ok_or_else(|| anyhow!("Variable outside of a section on line {}", number + 1))?;
+            let (name, value) = match line.find('=') {
+                Some(pos) => (&line[..pos], Some(Self::parse_value(&line[pos + 1..]))),
+                None => (Self::strip_comment(line), None),
+            };
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                bail!("Bad variable name '{}' on line {}", name, number + 1);
+            }
+            values.insert(format!("{}.{}", section, name.to_lowercase()), value);
+        }
+
+        Ok(Self { values })
+    }
+
+    fn parse_section(header: &str) -> Result<String> {
+        let header = header.trim();
+        match header.find(char::is_whitespace) {
+            Some(pos) => {
+                let name = &header[..pos];
+                let subsection = header[pos..].trim();
+                let subsection = subsection
+                    .strip_prefix('"')
+                    .and_then(|s| s.strip_suffix('"'))
+                    .ok_or_else(|| anyhow!("Bad subsection in [{}]", header))?;
+                Ok(format!("{}.{}", name.to_lowercase(), subsection))
+            }
+            None => Ok(header.to_lowercase()),
+        }
+    }
+
+    fn strip_comment(text: &str) -> &str {
+        let mut in_quotes = false;
+        for (pos, c) in text.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                '#' | ';' if !in_quotes => return &text[..pos],
+                _ => {}
+            }
+        }
+        text
+    }
+
+    fn parse_value(text: &str) -> String {
+        let text = Self::strip_comment(text).trim();
+        let mut value = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {}
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(other) => value.push(other),
+                    None => {}
+                },
+                _ => value.push(c),
+            }
+        }
+        value
+    }
+
+    fn normalize_key(key: &str) -> String {
+        match (key.find('.'), key.rfind('.')) {
+            (Some(first), Some(last)) if first != last => format!(
+                "{}{}{}",
+                key[..first].to_lowercase(),
+                &key[first..last],
+                key[last..].to_lowercase()
+            ),
+            _ => key.to_lowercase(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.values.get(&Self::normalize_key(key)) {
+            Some(Some(value)) => Some(value),
+            Some(None) => Some("true"),
+            None => None,
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        let value = match self.get(key) {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+        let (digits, scale) = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+            Some('k') => (&value[..value.len() - 1], 1 << 10),
+            Some('m') => (&value[..value.len() - 1], 1 << 20),
+            Some('g') => (&value[..value.len() - 1], 1 << 30),
+            _ => (value, 1),
+        };
+        let number = digits
+            .parse::<i64>()
+            .ok()
+            .and_then(|number| number.checked_mul(scale))
+            .ok_or_else(|| anyhow!("Bad numeric config value '{}' for '{}'", value, key))?;
+        Ok(Some(number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    const CONFIG: &str = r#"
+# A comment
+[core]
+    bare = false
+    ; another comment
+    filemode
+[Add]
+    Jobs = 4k  # trailing comment
+[remote "Origin"]
+    url = "https://example.com/repo.git"
+"#;
+
+    #[test]
+    fn can_read_values() {
+        let config = Config::parse(CONFIG).expect("Config::parse");
+
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(
+            config.get("remote.Origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get("core.missing"), None);
+    }
+
+    #[test]
+    fn names_are_case_insensitive_but_subsections_are_not() {
+        let config = Config::parse(CONFIG).expect("Config::parse");
+
+        assert_eq!(config.get("add.jobs"), Some("4k"));
+        assert_eq!(config.get("ADD.JOBS"), Some("4k"));
+        assert_eq!(
+            config.get("REMOTE.Origin.URL"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get("remote.origin.url"), None);
+    }
+
+    #[test]
+    fn can_read_typed_values() {
+        let config = Config::parse(CONFIG).expect("Config::parse");
+
+        assert_eq!(config.get_int("add.jobs").expect("get_int"), Some(4096));
+        assert_eq!(config.get("core.filemode"), Some("true"));
+        assert!(config.get_int("remote.Origin.url").is_err());
+
+        let config = Config::parse("[add]\njobs = 9999999999g\n").expect("Config::parse");
+        assert!(config.get_int("add.jobs").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(Config::parse("key = value").is_err());
+        assert!(Config::parse("[core").is_err());
+        assert!(Config::parse("[core]\nbad key = 1").is_err());
+    }
+}
@@ -1,15 +1,20 @@
 use super::object::Object;
+use super::object_id::ObjectId;
 
 #[derive(Debug, Clone)]
 pub struct Blob {
     data: Vec<u8>,
-    oid: Option<String>,
+    oid: Option<ObjectId>,
 }
 
 impl Blob {
     pub fn new(data: Vec<u8>) -> Self {
         Self { data, oid: None }
     }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 impl Object for Blob {
@@ -21,12 +26,12 @@ impl Object for Blob {
         self.data.clone()
     }
 
-    fn set_oid(&mut self, oid: String) {
+    fn set_oid(&mut self, oid: ObjectId) {
         assert!(self.oid.is_none());
         self.oid = Some(oid);
     }
 
-    fn get_oid(&self) -> Option<&str> {
-        self.oid.as_deref()
+    fn get_oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref()
     }
 }
@@ -1,28 +1,91 @@
 use std::fmt;
+use std::str;
 
-use time::OffsetDateTime;
+use anyhow::{anyhow, bail, Context, Result};
+use time::{OffsetDateTime, UtcOffset};
 
 use super::object::Object;
+use super::object_id::ObjectId;
 
 #[derive(Debug, Clone)]
 pub struct Commit {
-    parent: Option<String>,
-    tree: String,
+    parents: Vec<ObjectId>,
+    tree: ObjectId,
     author: Author,
+    committer: Author,
     message: String,
-    oid: Option<String>,
+    oid: Option<ObjectId>,
 }
 
 impl Commit {
-    pub fn new(parent: Option<String>, tree: String, author: Author, message: String) -> Self {
+    pub fn new(parents: Vec<ObjectId>, tree: ObjectId, author: Author, message: String) -> Self {
         Self {
-            parent,
+            parents,
             tree,
+            committer: author.clone(),
             author,
             message,
             oid: None,
         }
     }
+
+    /// Parses the content of a stored commit. Headers other than the tree, parents, author and
+    /// committer are skipped.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text = str::from_utf8(data).context("Commit is not valid UTF-8")?;
+        let (headers, message) = text
+            .split_once("\n\n")
+            .ok_or_else(|| anyhow!("Commit has no message"))?;
+
+        let mut parents = Vec::new();
+        let mut tree = None;
+        let mut author = None;
+        let mut committer = None;
+        for line in headers.lines() {
+            // Continuation lines of multi-line headers such as signatures start with a space
+            let (key, value) = match line.split_once(' ') {
+                Some(("", _)) | None => continue,
+                Some(header) => header,
+            };
+            match key {
+                "tree" => tree = Some(value.parse()?),
+                "parent" => parents.push(value.parse()?),
+                "author" => author = Some(Author::parse(value)?),
+                "committer" => committer = Some(Author::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            parents,
+            tree: tree.ok_or_else(|| anyhow!("Commit has no tree"))?,
+            author: author.ok_or_else(|| anyhow!("Commit has no author"))?,
+            committer: committer.ok_or_else(|| anyhow!("Commit has no committer"))?,
+            message: message.to_owned(),
+            oid: None,
+        })
+    }
+
+    pub fn tree(&self) -> &ObjectId {
+        &self.tree
+    }
+
+    pub fn parents(&self) -> &[ObjectId] {
+        &self.parents
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The first line of the message
+    pub fn title_line(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
 }
 
 impl Object for Commit {
@@ -33,24 +96,24 @@ impl Object for Commit {
     fn content(&self) -> Vec<u8> {
         let mut lines = Vec::new();
         lines.push(format!("tree {}", self.tree));
-        if let Some(ref parent) = self.parent {
+        for parent in &self.parents {
             lines.push(format!("parent {}", parent));
         }
         lines.push(format!("author {}", self.author));
-        lines.push(format!("committer {}", self.author));
+        lines.push(format!("committer {}", self.committer));
         lines.push("".to_owned());
         lines.push(self.message.clone());
 
         lines.join("\n").as_bytes().to_owned()
     }
 
-    fn set_oid(&mut self, oid: String) {
+    fn set_oid(&mut self, oid: ObjectId) {
         assert!(self.oid.is_none());
         self.oid = Some(oid);
     }
 
-    fn get_oid(&self) -> Option<&str> {
-        self.oid.as_deref()
+    fn get_oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref()
     }
 }
 
@@ -69,6 +132,49 @@ impl Author {
             timestamp,
         }
     }
+
+    /// Parses an identity line of the form `name <email> timestamp +hhmm`.
+    pub fn parse(line: &str) -> Result<Self> {
+        let malformed = || anyhow!("Malformed identity '{}'", line);
+
+        let (identity, date) = line.rsplit_once('>').ok_or_else(malformed)?;
+        let (name, email) = identity.split_once('<').ok_or_else(malformed)?;
+        let (timestamp, offset) = date.trim().split_once(' ').ok_or_else(malformed)?;
+
+        let timestamp: i64 = timestamp.parse().map_err(|_| malformed())?;
+        let sign = match offset.as_bytes().first() {
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            _ => bail!(malformed()),
+        };
+        if offset.len() != 5 {
+            bail!(malformed());
+        }
+        let hours: i16 = offset[1..3].parse().map_err(|_| malformed())?;
+        let minutes: i16 = offset[3..5].parse().map_err(|_| malformed())?;
+        let offset = UtcOffset::minutes(sign * (hours * 60 + minutes));
+
+        Ok(Self {
+            name: name.trim().to_owned(),
+            email: email.to_owned(),
+            timestamp: OffsetDateTime::from_unix_timestamp(timestamp).to_offset(offset),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// The time in the format of `git log`, such as `Tue Nov 14 23:13:20 2023 +0100`
+    pub fn readable_time(&self) -> String {
+        self.timestamp
+            .lazy_format("%a %b %-d %H:%M:%S %Y %z")
+            .to_string()
+    }
 }
 
 impl fmt::Display for Author {
@@ -1,25 +1,43 @@
 mod blob;
 mod commit;
 mod object;
+mod object_id;
+mod tag;
 mod tree;
 
-use std::fs::{create_dir_all, rename, File, OpenOptions};
-use std::io::{ErrorKind, Write};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str;
 
-use anyhow::{bail, Result};
-use flate2::{write::ZlibEncoder, Compression};
+use anyhow::{anyhow, bail, Context, Result};
+use crypto::{digest::Digest, sha1::Sha1};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use rand::prelude::*;
 
 pub use blob::*;
 pub use commit::*;
 pub use object::*;
+pub use object_id::*;
+pub use tag::*;
 pub use tree::*;
 
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 pub struct Database {
     root: PathBuf,
 }
 
+/// An object read back from the database
+#[derive(Debug)]
+pub enum ParsedObject {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
 impl Database {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         Self {
@@ -29,20 +47,16 @@ impl Database {
 
     pub fn store<O: Object>(&self, object: &mut O) -> Result<()> {
         compute_oid(object);
-        let oid = object.oid();
         let content = to_bytes(object);
 
-        let object_path = self
-            .root
-            .join(Path::new(&oid[0..2]))
-            .join(Path::new(&oid[2..]));
+        let object_path = self.object_path(object.oid());
 
         if object_path.exists() {
             return Ok(());
         }
 
         let dir = object_path.parent().expect("Path error");
-        let (tempfile, tempfile_name) = self.open_tempfile(&dir)?;
+        let (tempfile, tempfile_name) = self.open_tempfile(dir)?;
         let mut encoder = ZlibEncoder::new(&tempfile, Compression::fast());
         encoder.write_all(&content)?;
         rename(tempfile_name, object_path)?;
@@ -50,6 +64,175 @@ impl Database {
         Ok(())
     }
 
+    pub fn load(&self, oid: &ObjectId) -> Result<ParsedObject> {
+        let (object_type, content) = self
+            .read_object(oid)
+            .with_context(|| format!("Reading object {}", oid))?;
+
+        let mut object = match object_type.as_str() {
+            "blob" => ParsedObject::Blob(Blob::new(content)),
+            "tree" => ParsedObject::Tree(Tree::parse(&content)?),
+            "commit" => ParsedObject::Commit(Commit::parse(&content)?),
+            "tag" => ParsedObject::Tag(Tag::parse(&content)?),
+            _ => bail!("Object {} has unknown type '{}'", oid, object_type),
+        };
+        match object {
+            ParsedObject::Blob(ref mut blob) => blob.set_oid(*oid),
+            ParsedObject::Tree(ref mut tree) => tree.set_oid(*oid),
+            ParsedObject::Commit(ref mut commit) => commit.set_oid(*oid),
+            ParsedObject::Tag(ref mut tag) => tag.set_oid(*oid),
+        }
+
+        Ok(object)
+    }
+
+    pub fn load_blob(&self, oid: &ObjectId) -> Result<Blob> {
+        match self.load(oid)? {
+            ParsedObject::Blob(blob) => Ok(blob),
+            _ => bail!("Object {} is not a blob", oid),
+        }
+    }
+
+    pub fn load_tree(&self, oid: &ObjectId) -> Result<Tree> {
+        match self.load(oid)? {
+            ParsedObject::Tree(tree) => Ok(tree),
+            _ => bail!("Object {} is not a tree", oid),
+        }
+    }
+
+    pub fn load_commit(&self, oid: &ObjectId) -> Result<Commit> {
+        match self.load(oid)? {
+            ParsedObject::Commit(commit) => Ok(commit),
+            _ => bail!("Object {} is not a commit", oid),
+        }
+    }
+
+    /// Lists every file reachable from the tree `oid`, keyed by its path from the root of the
+    /// tree.
+    pub fn load_tree_list(&self, oid: &ObjectId) -> Result<BTreeMap<PathBuf, TreeFile>> {
+        let mut files = BTreeMap::new();
+        self.load_tree_list_into(oid, Path::new(""), &mut files)?;
+        Ok(files)
+    }
+
+    fn load_tree_list_into(
+        &self,
+        oid: &ObjectId,
+        prefix: &Path,
+        files: &mut BTreeMap<PathBuf, TreeFile>,
+    ) -> Result<()> {
+        for (name, entry) in self.load_tree(oid)?.list_entries() {
+            let path = prefix.join(name);
+            if entry.is_tree() {
+                self.load_tree_list_into(entry.oid(), &path, files)?;
+            } else {
+                files.insert(
+                    path.clone(),
+                    TreeFile::new(path, *entry.oid(), entry.file_mode()),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the ids of all stored objects starting with the hex digits in `prefix`.
+    pub fn prefix_match(&self, prefix: &str) -> Result<Vec<ObjectId>> {
+        let prefix = prefix.to_ascii_lowercase();
+        if prefix.len() < 2 {
+            bail!("Object id prefix '{}' is too short", prefix);
+        }
+
+        let dir = match self.root.join(&prefix[..2]).read_dir() {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => bail!(err),
+        };
+        let mut oids = Vec::new();
+        for entry in dir {
+            let hex = format!("{}{}", &prefix[..2], entry?.file_name().to_string_lossy());
+            if hex.starts_with(&prefix) {
+                if let Ok(oid) = hex.parse() {
+                    oids.push(oid);
+                }
+            }
+        }
+        oids.sort();
+        Ok(oids)
+    }
+
+    fn read_object(&self, oid: &ObjectId) -> Result<(String, Vec<u8>)> {
+        let mut data = Vec::new();
+        ZlibDecoder::new(File::open(self.object_path(oid))?).read_to_end(&mut data)?;
+
+        let header_end = data
+            .iter()
+            .position(|b| *b == b'\0')
+            .ok_or_else(|| anyhow!("Object header is not terminated"))?;
+        let header = str::from_utf8(&data[..header_end])?.to_owned();
+        let (object_type, size) = header
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Malformed object header '{}'", header))?;
+        let size: usize = size
+            .parse()
+            .with_context(|| format!("Malformed object header '{}'", header))?;
+
+        let content = data.split_off(header_end + 1);
+        if content.len() != size {
+            bail!(
+                "Object should have {} bytes of content but has {}",
+                size,
+                content.len()
+            );
+        }
+        Ok((object_type.to_owned(), content))
+    }
+
+    /// Stores a blob whose content is read from `reader`, which must yield exactly `size` bytes.
+    ///
+    /// The content is hashed and compressed in fixed-size chunks, so memory use does not depend
+    /// on the size of the blob. Since the oid is only known once all of the content has been
+    /// read, the object is written to a temporary file first and renamed into place afterwards.
+    pub fn store_blob_stream<R: Read>(&self, reader: R, size: u64) -> Result<ObjectId> {
+        let (tempfile, tempfile_name) = self.open_tempfile(&self.root)?;
+
+        let result = self
+            .write_blob_stream(reader, size, &tempfile)
+            .and_then(|oid| {
+                let object_path = self.object_path(&oid);
+                if object_path.exists() {
+                    remove_file(&tempfile_name)?;
+                } else {
+                    create_dir_all(object_path.parent().expect("Path error"))?;
+                    rename(&tempfile_name, object_path)?;
+                }
+                Ok(oid)
+            });
+
+        if result.is_err() {
+            let _ = remove_file(&tempfile_name);
+        }
+        result
+    }
+
+    fn write_blob_stream<R: Read, W: Write>(
+        &self,
+        reader: R,
+        size: u64,
+        out: W,
+    ) -> Result<ObjectId> {
+        let mut encoder = ZlibEncoder::new(out, Compression::fast());
+        let oid = stream_blob(reader, size, |chunk| Ok(encoder.write_all(chunk)?))?;
+        encoder.finish()?;
+        Ok(oid)
+    }
+
+    fn object_path(&self, oid: &ObjectId) -> PathBuf {
+        let oid = oid.to_hex();
+        self.root
+            .join(Path::new(&oid[0..2]))
+            .join(Path::new(&oid[2..]))
+    }
+
     fn open_tempfile<P: AsRef<Path>>(&self, dir: P) -> Result<(File, PathBuf)> {
         let chars = (b'a'..=b'z').chain(b'A'..=b'Z').chain(b'0'..=b'9');
         let mut rng = thread_rng();
@@ -75,3 +258,183 @@ impl Database {
         Ok((file, path))
     }
 }
+
+/// Computes the oid that a blob read from `reader` would be stored under, without storing it.
+pub fn hash_blob_stream<R: Read>(reader: R, size: u64) -> Result<ObjectId> {
+    stream_blob(reader, size, |_| Ok(()))
+}
+
+/// Feeds the serialized form of a blob to `output` chunk by chunk, returning its oid.
+fn stream_blob<R, F>(mut reader: R, size: u64, mut output: F) -> Result<ObjectId>
+where
+    R: Read,
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut hasher = Sha1::new();
+
+    let header = format!("blob {}\0", size);
+    hasher.input(header.as_bytes());
+    output(header.as_bytes())?;
+
+    let mut buf = vec![0; STREAM_CHUNK_SIZE];
+    let mut total = 0;
+    loop {
+        let count = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => bail!(err),
+        };
+        total += count as u64;
+        if total > size {
+            break;
+        }
+        hasher.input(&buf[..count]);
+        output(&buf[..count])?;
+    }
+    if total != size {
+        bail!("Expected {} bytes of content but read {}", size, total);
+    }
+
+    let mut oid = [0; ObjectId::SIZE];
+    hasher.result(&mut oid);
+    Ok(oid.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+    use tempfile::tempdir;
+
+    use super::{hash_blob_stream, Author, Blob, Commit, Database, Object, ParsedObject, Tree};
+    use super::{ObjectId, RawObject, TreeFile};
+
+    #[test]
+    fn stream_matches_in_memory_store() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut blob = Blob::new(data.clone());
+        database.store(&mut blob).expect("Database::store");
+
+        let oid = database
+            .store_blob_stream(&data[..], data.len() as u64)
+            .expect("Database::store_blob_stream");
+        assert_eq!(&oid, blob.oid());
+        assert_eq!(
+            hash_blob_stream(&data[..], data.len() as u64).expect("hash_blob_stream"),
+            oid
+        );
+
+        let compressed = fs::read(database.object_path(&oid)).expect("read");
+        let mut content = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut content)
+            .expect("inflate");
+        let header = format!("blob {}\0", data.len());
+        assert_eq!(&content[..header.len()], header.as_bytes());
+        assert_eq!(&content[header.len()..], &data[..]);
+    }
+
+    #[test]
+    fn can_load_stored_objects() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+
+        let mut blob = Blob::new(b"hello\n".to_vec());
+        database.store(&mut blob).expect("Database::store");
+        let store_tree = |files: Vec<TreeFile>| {
+            let mut tree = Tree::build(files).expect("Tree::build");
+            database.store(&mut tree).expect("Database::store");
+            tree
+        };
+        let sub = store_tree(vec![TreeFile::new("c", *blob.oid(), 0o100644)]);
+        let dir = store_tree(vec![
+            TreeFile::new("b.sh", *blob.oid(), 0o100755),
+            TreeFile::new_tree("sub", *sub.oid()),
+        ]);
+        let tree = store_tree(vec![
+            TreeFile::new("a.txt", *blob.oid(), 0o100644),
+            TreeFile::new_tree("dir", *dir.oid()),
+        ]);
+
+        let timestamp = time::OffsetDateTime::from_unix_timestamp(1_600_000_000)
+            .to_offset(time::UtcOffset::minutes(-90));
+        let author = Author::new("A. U. Thor", "author@example.com", timestamp);
+        let mut commit = Commit::new(vec![], *tree.oid(), author, "Message\n\nBody\n".to_owned());
+        database.store(&mut commit).expect("Database::store");
+
+        match database.load(blob.oid()).expect("Database::load") {
+            ParsedObject::Blob(loaded) => assert_eq!(loaded.content(), b"hello\n"),
+            other => panic!("Expected a blob but found {:?}", other),
+        }
+
+        let loaded = database.load_commit(commit.oid()).expect("load_commit");
+        assert_eq!(loaded.tree(), tree.oid());
+        assert_eq!(loaded.content(), commit.content());
+
+        let list = database.load_tree_list(tree.oid()).expect("load_tree_list");
+        let paths = list
+            .keys()
+            .map(|path| path.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["a.txt", "dir/b.sh", "dir/sub/c"]);
+        assert_eq!(list[std::path::Path::new("dir/b.sh")].mode(), "100755");
+
+        let missing: ObjectId = "0000000000000000000000000000000000000001".parse().unwrap();
+        assert!(database.load(&missing).is_err());
+        assert!(database.load_tree(blob.oid()).is_err());
+    }
+
+    #[test]
+    fn raw_objects_are_stored_like_parsed_ones() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+
+        let mut blob = Blob::new(b"hello\n".to_vec());
+        database.store(&mut blob).expect("Database::store");
+        let mut tree =
+            Tree::build(vec![TreeFile::new("a", *blob.oid(), 0o100644)]).expect("Tree::build");
+        database.store(&mut tree).expect("Database::store");
+
+        let mut raw = RawObject::new("tree", tree.content());
+        database.store(&mut raw).expect("Database::store");
+        assert_eq!(raw.oid(), tree.oid());
+        let list = database.load_tree_list(raw.oid()).expect("load_tree_list");
+        assert_eq!(list[std::path::Path::new("a")].oid(), blob.oid());
+    }
+
+    #[test]
+    fn rejects_unsafe_tree_entry_names() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+        let blob: ObjectId = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391".parse().unwrap();
+
+        for name in ["", ".", "..", ".git", ".GIT", "a/b"] {
+            let content = [format!("100644 {}\0", name).as_bytes(), blob.as_bytes()].concat();
+            assert!(Tree::parse(&content).is_err(), "{:?}", name);
+
+            let mut raw = RawObject::new("tree", content);
+            database.store(&mut raw).expect("Database::store");
+            assert!(database.load_tree_list(raw.oid()).is_err(), "{:?}", name);
+        }
+        let content = [&b"100644 .gitignore\0"[..], blob.as_bytes()].concat();
+        assert!(Tree::parse(&content).is_ok());
+    }
+
+    #[test]
+    fn stream_rejects_size_mismatch() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+
+        assert!(database.store_blob_stream(&b"short"[..], 10).is_err());
+        assert!(database.store_blob_stream(&b"too long"[..], 3).is_err());
+
+        let leftovers = fs::read_dir(tempdir.path()).expect("read_dir").count();
+        assert_eq!(leftovers, 0);
+    }
+}
@@ -1,5 +1,7 @@
 use crypto::{digest::Digest, sha1::Sha1};
 
+use super::object_id::ObjectId;
+
 // TODO: would an enum make more sense since it seems like content is the only real function
 // needing to be overloaded?
 pub trait Object {
@@ -8,14 +10,51 @@ pub trait Object {
 
     // TODO: I don't really like the duplication of implementing these - would prefer a distinct
     // object for things with oids
-    fn set_oid(&mut self, oid: String);
-    fn get_oid(&self) -> Option<&str>;
+    fn set_oid(&mut self, oid: ObjectId);
+    fn get_oid(&self) -> Option<&ObjectId>;
 
-    fn oid(&self) -> &str {
+    fn oid(&self) -> &ObjectId {
         self.get_oid().expect("Oid not computed yet")
     }
 }
 
+/// An object of any type stored with exactly the given content
+#[derive(Debug, Clone)]
+pub struct RawObject {
+    object_type: String,
+    content: Vec<u8>,
+    oid: Option<ObjectId>,
+}
+
+impl RawObject {
+    pub fn new(object_type: &str, content: Vec<u8>) -> Self {
+        Self {
+            object_type: object_type.to_owned(),
+            content,
+            oid: None,
+        }
+    }
+}
+
+impl Object for RawObject {
+    fn object_type(&self) -> &str {
+        &self.object_type
+    }
+
+    fn content(&self) -> Vec<u8> {
+        self.content.clone()
+    }
+
+    fn set_oid(&mut self, oid: ObjectId) {
+        assert!(self.oid.is_none());
+        self.oid = Some(oid);
+    }
+
+    fn get_oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref()
+    }
+}
+
 pub fn to_bytes<O: Object>(object: &O) -> Vec<u8> {
     let object_type = object.object_type();
     let content = object.content();
@@ -36,6 +75,7 @@ pub fn compute_oid<O: Object>(object: &mut O) {
 
     let mut hasher = Sha1::new();
     hasher.input(&to_bytes(object));
-    let oid = hasher.result_str();
-    object.set_oid(oid);
+    let mut oid = [0; ObjectId::SIZE];
+    hasher.result(&mut oid);
+    object.set_oid(oid.into());
 }
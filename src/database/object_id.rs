@@ -0,0 +1,112 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use rustc_serialize::hex::{FromHex, ToHex};
+
+/// The SHA-1 name of an object in the database.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId([u8; ObjectId::SIZE]);
+
+impl ObjectId {
+    /// Length of the raw id in bytes
+    pub const SIZE: usize = 20;
+    /// Length of the id when written as hex
+    pub const HEX_SIZE: usize = ObjectId::SIZE * 2;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().map_err(|_| {
+            anyhow!(
+                "Object id must be {} bytes but found {}",
+                Self::SIZE,
+                bytes.len()
+            )
+        })?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_hex(self) -> String {
+        self.0.to_hex()
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        if hex.len() != Self::HEX_SIZE {
+            bail!(
+                "Invalid object id '{}': expected {} hex digits",
+                hex,
+                Self::HEX_SIZE
+            );
+        }
+        let bytes = hex
+            .from_hex()
+            .map_err(|e| anyhow!("Invalid object id '{}': {}", hex, e))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl From<[u8; ObjectId::SIZE]> for ObjectId {
+    fn from(bytes: [u8; ObjectId::SIZE]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectId;
+
+    const HEX: &str = "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15";
+
+    #[test]
+    fn can_round_trip_hex() {
+        let oid: ObjectId = HEX.parse().expect("parse");
+        assert_eq!(oid.to_hex(), HEX);
+        assert_eq!(oid.to_string(), HEX);
+        assert_eq!(oid.as_bytes()[0], 0xf1);
+    }
+
+    #[test]
+    fn can_round_trip_bytes() {
+        let oid: ObjectId = HEX.parse().expect("parse");
+        let copy = ObjectId::from_bytes(oid.as_bytes()).expect("from_bytes");
+        assert_eq!(oid, copy);
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert!("".parse::<ObjectId>().is_err());
+        assert!(HEX[..39].parse::<ObjectId>().is_err());
+        assert!(format!("{}0", HEX).parse::<ObjectId>().is_err());
+        assert!("z1d2d2f924e986ac86fdf7b36c94bcdf32beec15"
+            .parse::<ObjectId>()
+            .is_err());
+        assert!(ObjectId::from_bytes(&[0; 19]).is_err());
+    }
+
+    #[test]
+    fn orders_by_bytes() {
+        let low: ObjectId = "0000000000000000000000000000000000000001".parse().unwrap();
+        let high: ObjectId = "1000000000000000000000000000000000000000".parse().unwrap();
+        assert!(low < high);
+    }
+}
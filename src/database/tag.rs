@@ -0,0 +1,101 @@
+use std::str;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::commit::Author;
+use super::object::Object;
+use super::object_id::ObjectId;
+
+/// An annotated tag, naming another object with a message
+#[derive(Debug, Clone)]
+pub struct Tag {
+    object: ObjectId,
+    target_type: String,
+    name: String,
+    tagger: Option<Author>,
+    message: String,
+    oid: Option<ObjectId>,
+}
+
+impl Tag {
+    /// Parses the content of a stored tag. Very old tags have no tagger, and headers other
+    /// than these four are skipped.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text = str::from_utf8(data).context("Tag is not valid UTF-8")?;
+        let (headers, message) = text.split_once("\n\n").unwrap_or((text, ""));
+
+        let mut object = None;
+        let mut target_type = None;
+        let mut name = None;
+        let mut tagger = None;
+        for line in headers.lines() {
+            let (key, value) = match line.split_once(' ') {
+                Some(("", _)) | None => continue,
+                Some(header) => header,
+            };
+            match key {
+                "object" => object = Some(value.parse()?),
+                "type" => target_type = Some(value.to_owned()),
+                "tag" => name = Some(value.to_owned()),
+                "tagger" => tagger = Some(Author::parse(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            object: object.ok_or_else(|| anyhow!("Tag has no object"))?,
+            target_type: target_type.ok_or_else(|| anyhow!("Tag has no type"))?,
+            name: name.ok_or_else(|| anyhow!("Tag has no name"))?,
+            tagger,
+            message: message.to_owned(),
+            oid: None,
+        })
+    }
+
+    /// The object the tag points to
+    pub fn object(&self) -> &ObjectId {
+        &self.object
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tagger(&self) -> Option<&Author> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Object for Tag {
+    fn object_type(&self) -> &str {
+        "tag"
+    }
+
+    fn content(&self) -> Vec<u8> {
+        let mut lines = vec![
+            format!("object {}", self.object),
+            format!("type {}", self.target_type),
+            format!("tag {}", self.name),
+        ];
+        if let Some(tagger) = &self.tagger {
+            lines.push(format!("tagger {}", tagger));
+        }
+        lines.push("".to_owned());
+        lines.push(self.message.clone());
+
+        lines.join("\n").as_bytes().to_owned()
+    }
+
+    fn set_oid(&mut self, oid: ObjectId) {
+        assert!(self.oid.is_none());
+        self.oid = Some(oid);
+    }
+
+    fn get_oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref()
+    }
+}
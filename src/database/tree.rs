@@ -1,9 +1,12 @@
 use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use super::object::Object;
+use super::object_id::ObjectId;
 
 #[derive(Debug, Clone)]
 enum TreeEntry {
@@ -13,22 +16,22 @@ enum TreeEntry {
 
 #[derive(Debug, Clone)]
 pub struct Tree {
-    entries: HashMap<String, TreeEntry>,
-    key_order: Vec<String>,
-    oid: Option<String>,
+    entries: HashMap<OsString, TreeEntry>,
+    key_order: Vec<OsString>,
+    oid: Option<ObjectId>,
 }
 
 #[derive(Debug, Clone)]
 pub struct TreeFile {
     rel_path: PathBuf,
-    oid: String,
+    oid: ObjectId,
     mode: u32,
 }
 
 impl TreeEntry {
     const DIRECTORY_MODE: &'static str = "40000";
 
-    fn oid(&self) -> &str {
+    fn oid(&self) -> &ObjectId {
         match self {
             TreeEntry::Tree(tree) => tree.oid(),
             TreeEntry::File(file) => &file.oid,
@@ -41,6 +44,13 @@ impl TreeEntry {
             TreeEntry::File(file) => file.mode(),
         }
     }
+
+    fn is_tree(&self) -> bool {
+        match self {
+            TreeEntry::Tree(_) => true,
+            TreeEntry::File(file) => file.is_tree(),
+        }
+    }
 }
 
 impl Tree {
@@ -62,14 +72,67 @@ impl Tree {
         Ok(root)
     }
 
+    /// Parses the content of a stored tree. Its entries all refer to stored objects, so
+    /// subtrees are represented by `TreeFile`s with the directory mode. Entry names that could
+    /// escape the directory or reach into `.git` when checked out are rejected.
+    pub fn parse(mut data: &[u8]) -> Result<Self> {
+        let mut tree = Self::new();
+        while !data.is_empty() {
+            let mode_end = data
+                .iter()
+                .position(|b| *b == b' ')
+                .ok_or_else(|| anyhow!("Tree entry mode is not terminated"))?;
+            let mode = std::str::from_utf8(&data[..mode_end])?;
+            let mode = u32::from_str_radix(mode, 8)
+                .with_context(|| format!("Bad tree entry mode '{}'", mode))?;
+            data = &data[mode_end + 1..];
+
+            let name_end = data
+                .iter()
+                .position(|b| *b == b'\0')
+                .ok_or_else(|| anyhow!("Tree entry name is not terminated"))?;
+            let name = &data[..name_end];
+            if !is_valid_entry_name(name) {
+                bail!(
+                    "Invalid tree entry name '{}'",
+                    String::from_utf8_lossy(name)
+                );
+            }
+            let name = OsStr::from_bytes(name).to_owned();
+            data = &data[name_end + 1..];
+
+            if data.len() < ObjectId::SIZE {
+                bail!("Tree entry for {:?} is truncated", name);
+            }
+            let oid = ObjectId::from_bytes(&data[..ObjectId::SIZE])?;
+            data = &data[ObjectId::SIZE..];
+
+            tree.key_order.push(name.clone());
+            tree.entries.insert(
+                name.clone(),
+                TreeEntry::File(TreeFile::new(name, oid, mode)),
+            );
+        }
+        Ok(tree)
+    }
+
+    /// The entries of a parsed tree by name, in the order they were stored
+    pub fn list_entries(&self) -> impl Iterator<Item = (&OsStr, &TreeFile)> {
+        self.key_order
+            .iter()
+            .filter_map(move |name| match &self.entries[name] {
+                TreeEntry::File(file) => Some((name.as_os_str(), file)),
+                TreeEntry::Tree(_) => None,
+            })
+    }
+
     fn add_entry<P: AsRef<Path>>(&mut self, parents: &[P], entry: TreeFile) -> Result<()> {
         if parents.is_empty() {
             let name = entry
                 .rel_path
                 .file_name()
                 .ok_or_else(|| anyhow!("Missing filename in {:?}", entry))?
-                .to_string_lossy()
-                .into_owned();
+                .to_owned();
             self.key_order.push(name.clone());
             self.entries.insert(name, TreeEntry::File(entry));
         } else {
@@ -77,8 +140,7 @@ impl Tree {
                 .as_ref()
                 .file_name()
                 .ok_or_else(|| anyhow!("Missing filename in {:?}", parents[0].as_ref()))?
-                .to_string_lossy()
-                .into_owned();
+                .to_owned();
             if !self.entries.contains_key(&first_parent) {
                 self.key_order.push(first_parent.clone());
                 self.entries
@@ -95,16 +157,16 @@ impl Tree {
         }
         Ok(())
     }
+}
 
-    pub fn traverse(&mut self, callback: &dyn Fn(&mut Tree) -> Result<()>) -> Result<()> {
-        for key in &self.key_order {
-            if let TreeEntry::Tree(ref mut tree) = self.entries.get_mut(key).unwrap() {
-                tree.traverse(callback)?;
-            }
-        }
-        callback(self)?;
-        Ok(())
-    }
+/// Whether `name` can be the name of a tree entry: a single path component other than `.`, `..`
+/// and `.git` in any case, as git's fsck requires
+fn is_valid_entry_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name != b"."
+        && name != b".."
+        && !name.eq_ignore_ascii_case(b".git")
+        && !name.contains(&b'/')
 }
 
 impl Object for Tree {
@@ -113,16 +175,20 @@ impl Object for Tree {
     }
 
     fn content(&self) -> Vec<u8> {
-        use rustc_serialize::hex::FromHex;
+        // Git sorts entries by name, comparing subtrees as if their names ended in '/'
+        let mut keys: Vec<_> = self.key_order.iter().collect();
+        keys.sort_by_cached_key(|key| {
+            let mut name = key.as_bytes().to_vec();
+            if self.entries[*key].is_tree() {
+                name.push(b'/');
+            }
+            name
+        });
 
-        self.key_order
-            .iter()
+        keys.into_iter()
             .flat_map(|key| {
                 let entry = &self.entries[key];
-                let oid = entry
-                    .oid()
-                    .from_hex()
-                    .expect("Hash is not a valid hex string");
+                let oid = entry.oid().as_bytes().to_vec();
                 let mode = entry.mode().as_bytes().to_owned();
                 let parts = vec![
                     mode,
@@ -137,39 +203,70 @@ impl Object for Tree {
             .collect() // Vec<u8>
     }
 
-    fn set_oid(&mut self, oid: String) {
+    fn set_oid(&mut self, oid: ObjectId) {
         assert!(self.oid.is_none());
         self.oid = Some(oid);
     }
 
-    fn get_oid(&self) -> Option<&str> {
-        self.oid.as_deref()
+    fn get_oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref()
     }
 }
 
 impl TreeFile {
     const REGULAR_MODE: &'static str = "100644";
     const EXECUTABLE_MODE: &'static str = "100755";
+    const SYMLINK_MODE: &'static str = "120000";
+    const GITLINK_MODE: &'static str = "160000";
+    const TREE_MODE: u32 = 0o40000;
 
-    pub fn new<P: AsRef<Path>>(rel_path: P, oid: &str, mode: u32) -> Self {
+    pub fn new<P: AsRef<Path>>(rel_path: P, oid: ObjectId, mode: u32) -> Self {
         Self {
             rel_path: rel_path.as_ref().to_owned(),
-            oid: oid.to_owned(),
+            oid,
             mode,
         }
     }
 
+    /// Refers to an already stored tree, so that it can be included in its parent without being
+    /// rebuilt.
+    pub fn new_tree<P: AsRef<Path>>(rel_path: P, oid: ObjectId) -> Self {
+        Self::new(rel_path, oid, Self::TREE_MODE)
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.mode == Self::TREE_MODE
+    }
+
+    pub fn rel_path(&self) -> &Path {
+        &self.rel_path
+    }
+
+    pub fn oid(&self) -> &ObjectId {
+        &self.oid
+    }
+
+    pub fn file_mode(&self) -> u32 {
+        self.mode
+    }
+
     pub fn mode(&self) -> &str {
         let is_executable = self.mode & 0o100 != 0;
 
-        if is_executable {
+        if self.is_tree() {
+            TreeEntry::DIRECTORY_MODE
+        } else if self.mode == 0o120000 {
+            Self::SYMLINK_MODE
+        } else if self.mode == 0o160000 {
+            Self::GITLINK_MODE
+        } else if is_executable {
             Self::EXECUTABLE_MODE
         } else {
             Self::REGULAR_MODE
         }
     }
 
-    pub fn ancestors(&self) -> Vec<String> {
+    pub fn ancestors(&self) -> Vec<OsString> {
         use std::path::Component::*;
         let mut ancestors = Vec::new();
         let components = self
@@ -179,7 +276,7 @@ impl TreeFile {
             .components();
         for component in components {
             match component {
-                Normal(s) => ancestors.push(s.to_string_lossy().into_owned()),
+                Normal(s) => ancestors.push(s.to_owned()),
                 _ => panic!("Not properly canonicalized"),
             }
         }
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+
+/// Only this much of a file is searched for the NUL bytes that make it binary, as in git
+const FIRST_FEW_BYTES: usize = 8000;
+/// The bytes of binary patch data encoded on each line
+const BYTES_PER_LINE: usize = 52;
+/// Delta copies are found by matching blocks of this many bytes
+const BLOCK_SIZE: usize = 16;
+/// The most bytes a delta instruction copies, which older readers of deltas require
+const MAX_COPY_SIZE: usize = 0x10000;
+/// The most bytes a delta instruction inserts
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Whether `data` looks binary rather than text, which is when it starts with a NUL byte in
+/// its first few thousand bytes
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(FIRST_FEW_BYTES).any(|&byte| byte == 0)
+}
+
+/// The lines of a `GIT binary patch` from `old` to `new`: how to make the new content and then
+/// how to make the old, each as a compressed delta or the whole content, whichever is smaller.
+pub fn binary_patch(old: &[u8], new: &[u8]) -> Vec<Vec<u8>> {
+    let mut lines = vec![b"GIT binary patch".to_vec()];
+    lines.extend(binary_patch_body(old, new));
+    lines.extend(binary_patch_body(new, old));
+    lines
+}
+
+fn binary_patch_body(source: &[u8], target: &[u8]) -> Vec<Vec<u8>> {
+    let literal = deflate(target);
+    let delta = if source.is_empty() || target.is_empty() {
+        None
+    } else {
+        let delta = make_delta(source, target);
+        Some((delta.len(), deflate(&delta)))
+    };
+
+    let (header, data) = match delta {
+        Some((size, delta)) if delta.len() < literal.len() => (format!("delta {}", size), delta),
+        _ => (format!("literal {}", target.len()), literal),
+    };
+    let mut lines = vec![header.into_bytes()];
+    for chunk in data.chunks(BYTES_PER_LINE) {
+        // The length of each line's data is given by a letter: A-Z for 1-26, a-z for 27-52
+        let length = match chunk.len() {
+            n @ 1..=26 => b'A' + n as u8 - 1,
+            n => b'a' + (n - 26) as u8 - 1,
+        };
+        let mut line = vec![length];
+        line.extend(encode_base85(chunk));
+        lines.push(line);
+    }
+    lines.push(Vec::new());
+    lines
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .expect("Programmer error: writing to a Vec failed")
+}
+
+/// Encodes each group of four bytes, padded with zeros, as five base 85 digits.
+fn encode_base85(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bytes = [0; 4];
+        bytes[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend_from_slice(&digits);
+    }
+    out
+}
+
+/// A delta in git's pack format that makes `target` from `source`, with instructions that copy
+/// ranges of the source and insert new bytes.
+fn make_delta(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, source.len());
+    write_size(&mut delta, target.len());
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for offset in (0..source.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&source[offset..offset + BLOCK_SIZE])
+            .or_default()
+            .push(offset);
+    }
+
+    let mut pending: Vec<u8> = Vec::new();
+    let mut t = 0;
+    while t < target.len() {
+        let longest = target
+            .get(t..t + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block))
+            .into_iter()
+            .flatten()
+            .map(|&offset| {
+                let length = source[offset..]
+                    .iter()
+                    .zip(&target[t..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (offset, length)
+            })
+            .max_by_key(|&(_, length)| length);
+
+        match longest {
+            Some((mut offset, mut length)) => {
+                t += length;
+                // Take back the bytes before the match that the source has too
+                while offset > 0 && pending.last() == Some(&source[offset - 1]) {
+                    pending.pop();
+                    offset -= 1;
+                    length += 1;
+                }
+                write_insert(&mut delta, &pending);
+                pending.clear();
+                write_copy(&mut delta, offset, length);
+            }
+            None => {
+                pending.push(target[t]);
+                t += 1;
+            }
+        }
+    }
+    write_insert(&mut delta, &pending);
+    delta
+}
+
+/// Writes a size as seven bits per byte, lowest first, with the top bit set on all but the last.
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(size as u8 | 0x80);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Writes copy instructions, each with a byte of flags saying which bytes of the offset and
+/// size follow, leaving out those that are zero.
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut length: usize) {
+    while length > 0 {
+        let size = length.min(MAX_COPY_SIZE);
+        let flags_at = out.len();
+        let mut flags = 0x80;
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                flags |= 1 << i;
+                out.push(byte);
+            }
+        }
+        // A size of 0x10000 is written as no size bytes at all
+        for i in 0..3 {
+            let byte = ((size & 0xffff) >> (8 * i)) as u8;
+            if byte != 0 {
+                flags |= 0x10 << i;
+                out.push(byte);
+            }
+        }
+        out.insert(flags_at, flags);
+        offset += size;
+        length -= size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::{binary_patch, encode_base85, is_binary, make_delta, BASE85};
+
+    fn read_size(delta: &[u8], pos: &mut usize) -> usize {
+        let (mut size, mut shift) = (0, 0);
+        loop {
+            let byte = delta[*pos];
+            *pos += 1;
+            size |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return size;
+            }
+        }
+    }
+
+    fn apply_delta(source: &[u8], delta: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        assert_eq!(read_size(delta, &mut pos), source.len());
+        let target_size = read_size(delta, &mut pos);
+        let mut target = Vec::new();
+        while pos < delta.len() {
+            let op = delta[pos];
+            pos += 1;
+            if op & 0x80 == 0 {
+                target.extend_from_slice(&delta[pos..pos + usize::from(op)]);
+                pos += usize::from(op);
+                continue;
+            }
+            let (mut offset, mut size) = (0, 0);
+            for i in 0..7 {
+                if op & (1 << i) != 0 {
+                    let value = usize::from(delta[pos]);
+                    pos += 1;
+                    if i < 4 {
+                        offset |= value << (8 * i);
+                    } else {
+                        size |= value << (8 * (i - 4));
+                    }
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            target.extend_from_slice(&source[offset..offset + size]);
+        }
+        assert_eq!(target.len(), target_size);
+        target
+    }
+
+    fn decode_base85(text: &[u8]) -> Vec<u8> {
+        text.chunks(5)
+            .flat_map(|digits| {
+                let value = digits.iter().fold(0u32, |value, digit| {
+                    value * 85 + BASE85.iter().position(|c| c == digit).unwrap() as u32
+                });
+                value.to_be_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_nul_bytes_near_the_start() {
+        assert!(is_binary(b"PNG\0\x01"));
+        assert!(!is_binary(b"plain text\n"));
+        let mut late = vec![b'a'; 9000];
+        late.push(0);
+        assert!(!is_binary(&late));
+    }
+
+    #[test]
+    fn encodes_base85_like_git() {
+        assert_eq!(encode_base85(&[0, 0, 0, 0]), b"00000");
+        assert_eq!(encode_base85(&[0xff; 4]), b"|NsC0");
+        assert_eq!(decode_base85(&encode_base85(b"abcdefgh")), b"abcdefgh");
+    }
+
+    #[test]
+    fn deltas_rebuild_the_target() {
+        let source: Vec<u8> = (0..5000u32).map(|n| (n * 7 % 251) as u8).collect();
+        let mut target = source[1000..3000].to_vec();
+        target.extend_from_slice(b"inserted\0bytes");
+        target.extend_from_slice(&source[..1500]);
+        target.extend([9; 300]);
+        let delta = make_delta(&source, &target);
+        assert_eq!(apply_delta(&source, &delta), target);
+        assert!(delta.len() < 400);
+
+        assert_eq!(
+            apply_delta(b"short", &make_delta(b"short", b"other")),
+            b"other"
+        );
+    }
+
+    #[test]
+    fn writes_literals_and_deltas() {
+        let source: Vec<u8> = (0..4000u32).map(|n| (n * 13 % 256) as u8).collect();
+        let mut target = source.clone();
+        target[2000] ^= 1;
+        let lines = binary_patch(&source, &target);
+        assert_eq!(lines[0], b"GIT binary patch");
+        assert!(lines[1].starts_with(b"delta "));
+
+        let data: Vec<u8> = lines[2..]
+            .iter()
+            .take_while(|line| !line.is_empty())
+            .flat_map(|line| {
+                let length = match line[0] {
+                    n @ b'A'..=b'Z' => usize::from(n - b'A' + 1),
+                    n => usize::from(n - b'a' + 27),
+                };
+                decode_base85(&line[1..])[..length].to_vec()
+            })
+            .collect();
+        let mut delta = Vec::new();
+        ZlibDecoder::new(&data[..]).read_to_end(&mut delta).unwrap();
+        assert_eq!(apply_delta(&source, &delta), target);
+
+        let lines = binary_patch(b"", b"new\0");
+        assert_eq!(lines[1], b"literal 4");
+        assert_eq!(lines[lines.len() - 3], b"literal 0");
+    }
+}
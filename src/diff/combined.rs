@@ -0,0 +1,573 @@
+use std::os::unix::ffi::OsStrExt;
+
+use super::{diff_lines, split_lines, Algorithm, Edit, PatchOptions, Target};
+
+/// Hex digits shown of the oids on a combined diff's `index` line
+const ABBREV_SIZE: usize = 7;
+/// Hunk headers show at most this many bytes of the function name
+const MAX_COMMENT_SIZE: usize = 40;
+
+/// A line of the result, with the lines of the parents that were deleted just before it
+#[derive(Debug, Default)]
+struct Sline<'a> {
+    /// The line without its newline, or `None` for the end of the file
+    line: Option<&'a [u8]>,
+    lost: Vec<Lost<'a>>,
+    /// The parents the line is not in, one bit each
+    changed: u64,
+    /// Whether the line is shown in a hunk
+    mark: bool,
+    /// Whether the line is only context before a hunk, so its lost lines belong to the hunk
+    /// before and are not shown again
+    no_pre_delete: bool,
+    /// The line number in each parent that the line, or its lost lines, start at
+    p_lno: Vec<usize>,
+}
+
+/// A line deleted from some of the parents
+#[derive(Debug, Clone)]
+struct Lost<'a> {
+    line: &'a [u8],
+    parents: u64,
+}
+
+/// The lines of the dense combined diff between the `parents` of a merge and its `result`,
+/// as in `git show` of a merge commit, with a missing side for a file that did not exist.
+/// Only the hunks where the result differs from every parent in different ways are shown, and
+/// there are no lines at all if there is no such hunk and the file's modes are the same.
+pub fn combined_patch(
+    parents: &[Option<Target>],
+    result: Option<&Target>,
+    options: &PatchOptions,
+) -> Vec<Vec<u8>> {
+    let num_parents = parents.len();
+    let mode = |target: Option<&Target>| target.map_or(0, |target| target.mode);
+    let mode_differs = parents
+        .iter()
+        .any(|parent| mode(parent.as_ref()) != mode(result));
+
+    let binary = result
+        .into_iter()
+        .chain(parents.iter().flatten())
+        .any(Target::is_binary);
+    if binary {
+        let mut lines = combined_header(parents, result, mode_differs, false);
+        lines.push(b"Binary files differ".to_vec());
+        return lines;
+    }
+
+    let result_data = result.map_or(&[][..], |result| &result.data);
+    let result_lines = split_lines(result_data);
+    let cnt = result_lines.len();
+    let mut slines: Vec<Sline> = (0..cnt + 2)
+        .map(|lno| Sline {
+            line: result_lines.get(lno).map(|line| strip_newline(line)),
+            p_lno: vec![0; num_parents],
+            ..Sline::default()
+        })
+        .collect();
+
+    for (n, parent) in parents.iter().enumerate() {
+        let oid = parent.as_ref().map(|parent| parent.oid);
+        let same = parents[..n]
+            .iter()
+            .position(|other| other.as_ref().map(|other| other.oid) == oid);
+        match same {
+            Some(m) => reuse_parent(&mut slines, n, m),
+            None => {
+                let data = parent.as_ref().map_or(&[][..], |parent| &parent.data);
+                combine_parent(&mut slines, &result_lines, data, n, options.algorithm);
+            }
+        }
+    }
+
+    let show_hunks = make_hunks(&mut slines, cnt, num_parents, options.context);
+    if !show_hunks && !mode_differs {
+        return Vec::new();
+    }
+    let mut lines = combined_header(parents, result, mode_differs, true);
+    dump_slines(&mut lines, &slines, cnt, num_parents, options.context);
+    lines
+}
+
+fn strip_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+fn combined_header(
+    parents: &[Option<Target>],
+    result: Option<&Target>,
+    mode_differs: bool,
+    show_file_header: bool,
+) -> Vec<Vec<u8>> {
+    let path = result
+        .or_else(|| parents.iter().flatten().next())
+        .map_or(&[][..], |target| target.path.as_os_str().as_bytes());
+    let oid = |target: Option<&Target>| {
+        target.map_or("0".repeat(ABBREV_SIZE), |target| {
+            target.oid.to_hex()[..ABBREV_SIZE].to_owned()
+        })
+    };
+    let parent_oids: Vec<_> = parents.iter().map(|parent| oid(parent.as_ref())).collect();
+
+    let mut lines = vec![
+        [b"diff --cc ", path].concat(),
+        format!("index {}..{}", parent_oids.join(","), oid(result)).into_bytes(),
+    ];
+
+    let deleted = result.is_none();
+    let added = !deleted && parents.iter().all(Option::is_none);
+    if mode_differs {
+        if let Some(result) = result.filter(|_| added) {
+            lines.push(format!("new file mode {:06o}", result.mode).into_bytes());
+        } else {
+            let modes: Vec<_> = parents
+                .iter()
+                .map(|parent| format!("{:06o}", parent.as_ref().map_or(0, |parent| parent.mode)))
+                .collect();
+            let mut line = format!(
+                "{}mode {}",
+                if deleted { "deleted file " } else { "" },
+                modes.join(",")
+            );
+            if let Some(result) = result {
+                line.push_str(&format!("..{:06o}", result.mode));
+            }
+            lines.push(line.into_bytes());
+        }
+    }
+
+    if show_file_header {
+        lines.push(match added {
+            true => b"--- /dev/null".to_vec(),
+            false => [b"--- a/", path].concat(),
+        });
+        lines.push(match deleted {
+            true => b"+++ /dev/null".to_vec(),
+            false => [b"+++ b/", path].concat(),
+        });
+    }
+    lines
+}
+
+/// Diffs parent `n` with the result, marking the result's lines that the parent does not have
+/// and adding the parent's deleted lines to those lost before the next line of the result.
+fn combine_parent<'a>(
+    slines: &mut [Sline<'a>],
+    result_lines: &[&'a [u8]],
+    parent_data: &'a [u8],
+    n: usize,
+    algorithm: Algorithm,
+) {
+    let mask = 1 << n;
+    let cnt = result_lines.len();
+    let parent_lines = split_lines(parent_data);
+
+    // Deletions come before insertions in each run of changes, so they are all lost before the
+    // first line inserted or the next unchanged line
+    let mut lost = vec![Vec::new(); cnt + 1];
+    let mut lno = 0;
+    for edit in diff_lines(&parent_lines, result_lines, algorithm) {
+        match edit {
+            Edit::Equal(_, j) => lno = j + 1,
+            Edit::Delete(i) => lost[lno].push(Lost {
+                line: strip_newline(parent_lines[i]),
+                parents: mask,
+            }),
+            Edit::Insert(j) => {
+                slines[j].changed |= mask;
+                lno = j + 1;
+            }
+        }
+    }
+
+    let mut p_lno = 1;
+    for (lno, new_lost) in lost.into_iter().enumerate() {
+        let sline = &mut slines[lno];
+        sline.p_lno[n] = p_lno;
+        coalesce_lost(&mut sline.lost, new_lost, mask);
+
+        p_lno += sline
+            .lost
+            .iter()
+            .filter(|lost| lost.parents & mask != 0)
+            .count();
+        if lno < cnt && sline.changed & mask == 0 {
+            p_lno += 1;
+        }
+    }
+    slines[cnt + 1].p_lno[n] = p_lno;
+}
+
+/// Merges the lines a parent lost into those lost from earlier parents, sharing the lines of
+/// their longest common subsequence and otherwise putting the parent's lines after the others.
+fn coalesce_lost<'a>(base: &mut Vec<Lost<'a>>, new: Vec<Lost<'a>>, mask: u64) {
+    if base.is_empty() {
+        *base = new;
+        return;
+    }
+    if new.is_empty() {
+        return;
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Direction {
+        Match,
+        Base,
+        New,
+    }
+    let (m, n) = (base.len(), new.len());
+    let mut lcs = vec![vec![0; n + 1]; m + 1];
+    let mut directions = vec![vec![Direction::Base; n + 1]; m + 1];
+    for direction in &mut directions[0][1..] {
+        *direction = Direction::New;
+    }
+    for i in 1..=m {
+        for j in 1..=n {
+            if base[i - 1].line == new[j - 1].line {
+                lcs[i][j] = lcs[i - 1][j - 1] + 1;
+                directions[i][j] = Direction::Match;
+            } else if lcs[i][j - 1] >= lcs[i - 1][j] {
+                lcs[i][j] = lcs[i][j - 1];
+                directions[i][j] = Direction::New;
+            } else {
+                lcs[i][j] = lcs[i - 1][j];
+                directions[i][j] = Direction::Base;
+            }
+        }
+    }
+
+    let (mut i, mut j) = (m, n);
+    while i != 0 || j != 0 {
+        match directions[i][j] {
+            Direction::Match => {
+                base[i - 1].parents |= mask;
+                i -= 1;
+                j -= 1;
+            }
+            Direction::New => {
+                base.insert(i, new[j - 1].clone());
+                j -= 1;
+            }
+            Direction::Base => i -= 1,
+        }
+    }
+}
+
+/// Copies what was found for parent `m` to parent `n`, which has the same content.
+fn reuse_parent(slines: &mut [Sline], n: usize, m: usize) {
+    let (n_mask, m_mask) = (1 << n, 1 << m);
+    for sline in slines.iter_mut() {
+        sline.p_lno[n] = sline.p_lno[m];
+        for lost in &mut sline.lost {
+            if lost.parents & m_mask != 0 {
+                lost.parents |= n_mask;
+            }
+        }
+        if sline.changed & m_mask != 0 {
+            sline.changed |= n_mask;
+        }
+    }
+}
+
+/// Marks the lines to show. Runs of changes are left out if the result only differs from the
+/// same parents throughout them, unless it differs from all of them.
+fn make_hunks(slines: &mut [Sline], cnt: usize, num_parents: usize, context: usize) -> bool {
+    let all_mask = (1 << num_parents) - 1;
+    for sline in &mut slines[..=cnt] {
+        sline.mark = sline.changed & all_mask != 0 || !sline.lost.is_empty();
+    }
+
+    let mut i = 0;
+    while i <= cnt {
+        while i <= cnt && !slines[i].mark {
+            i += 1;
+        }
+        if cnt < i {
+            break;
+        }
+        let hunk_begin = i;
+        let mut j = i + 1;
+        while j <= cnt {
+            if !slines[j].mark {
+                // Carry on if there is another marked line within the context after this one
+                let tail = adjust_hunk_tail(slines, all_mask, hunk_begin, j);
+                let mut lookahead = (tail + context).min(cnt + 1);
+                let mut continues = false;
+                while lookahead > j {
+                    lookahead -= 1;
+                    if slines[lookahead].mark {
+                        continues = true;
+                        break;
+                    }
+                }
+                if !continues {
+                    break;
+                }
+                j = lookahead;
+            }
+            j += 1;
+        }
+        let hunk_end = j;
+
+        let mut same_diff = 0;
+        let mut has_interesting = false;
+        let diffs = slines[i..hunk_end].iter().flat_map(|sline| {
+            Some(sline.changed & all_mask)
+                .filter(|&diff| diff != 0)
+                .into_iter()
+                .chain(sline.lost.iter().map(|lost| lost.parents))
+        });
+        for diff in diffs {
+            if same_diff == 0 {
+                same_diff = diff;
+            } else if same_diff != diff {
+                has_interesting = true;
+                break;
+            }
+        }
+        if !has_interesting && same_diff != all_mask {
+            for sline in &mut slines[hunk_begin..hunk_end] {
+                sline.mark = false;
+            }
+        }
+        i = hunk_end;
+    }
+
+    give_context(slines, cnt, all_mask, context)
+}
+
+/// `i` is the first unmarked line after a hunk. If the last line of the hunk only has lines
+/// lost before it, it is shown anyway, and so already gives a line of context.
+fn adjust_hunk_tail(slines: &[Sline], all_mask: u64, hunk_begin: usize, i: usize) -> usize {
+    if hunk_begin < i && slines[i - 1].changed & all_mask == 0 {
+        i - 1
+    } else {
+        i
+    }
+}
+
+/// The first line from `i` on that is marked, or unmarked if `unmarked`
+fn find_next(slines: &[Sline], mut i: usize, cnt: usize, unmarked: bool) -> usize {
+    while i <= cnt && slines[i].mark == unmarked {
+        i += 1;
+    }
+    i
+}
+
+/// Marks the context lines around the marked lines, joining hunks with small gaps between them.
+fn give_context(slines: &mut [Sline], cnt: usize, all_mask: u64, context: usize) -> bool {
+    let mut i = find_next(slines, 0, cnt, false);
+    if cnt < i {
+        return false;
+    }
+
+    while i <= cnt {
+        for sline in &mut slines[i.saturating_sub(context)..i] {
+            if !sline.mark {
+                sline.no_pre_delete = true;
+            }
+            sline.mark = true;
+        }
+
+        loop {
+            let j = find_next(slines, i, cnt, true);
+            if cnt < j {
+                return true;
+            }
+            let k = find_next(slines, j, cnt, false);
+            let j = adjust_hunk_tail(slines, all_mask, i, j);
+
+            if k < j + context {
+                for sline in &mut slines[j..k] {
+                    sline.mark = true;
+                }
+                i = k;
+                continue;
+            }
+
+            i = k;
+            for sline in &mut slines[j..(j + context).min(cnt + 1)] {
+                sline.mark = true;
+            }
+            break;
+        }
+    }
+    true
+}
+
+/// Writes the hunks of marked lines, each line prefixed by a column for each parent
+fn dump_slines(
+    lines: &mut Vec<Vec<u8>>,
+    slines: &[Sline],
+    cnt: usize,
+    num_parents: usize,
+    context: usize,
+) {
+    let marker = "@".repeat(num_parents + 1);
+    let mut lno = 0;
+    loop {
+        let mut comment = None;
+        while lno <= cnt && !slines[lno].mark {
+            if let Some(line) = slines[lno].line.filter(|line| is_comment_line(line)) {
+                comment = Some(line);
+            }
+            lno += 1;
+        }
+        if cnt < lno {
+            break;
+        }
+        let hunk_end = find_next(slines, lno + 1, cnt, true);
+
+        let mut rlines = hunk_end - lno;
+        if cnt < hunk_end {
+            rlines -= 1;
+        }
+        // With no context, lines that are only there for the lines lost before them are not
+        // shown, so are not counted
+        let mut null_context = 0;
+        if context == 0 {
+            null_context = slines[lno..hunk_end]
+                .iter()
+                .filter(|sline| sline.changed == 0)
+                .count();
+            rlines = rlines.wrapping_sub(null_context);
+        }
+
+        let mut header = marker.clone();
+        for n in 0..num_parents {
+            let start = slines[lno].p_lno[n];
+            let len = slines[hunk_end].p_lno[n] - start;
+            header.push_str(&format!(" -{},{}", start, len.wrapping_sub(null_context)));
+        }
+        header.push_str(&format!(" +{},{} {}", lno + 1, rlines, marker));
+        let mut header = header.into_bytes();
+        if let Some(comment) = comment {
+            // The last visible character is left out, as git does
+            let comment = &comment[..comment.len().min(MAX_COMMENT_SIZE)];
+            let comment = comment.split(|&byte| byte == 0).next().unwrap_or_default();
+            let end = comment
+                .iter()
+                .rposition(|byte| !byte.is_ascii_whitespace())
+                .unwrap_or(0);
+            if end > 0 {
+                header.push(b' ');
+                header.extend_from_slice(&comment[..end]);
+            }
+        }
+        lines.push(header);
+
+        while lno < hunk_end {
+            let sline = &slines[lno];
+            lno += 1;
+            if !sline.no_pre_delete {
+                for lost in &sline.lost {
+                    let mut line = columns(num_parents, lost.parents, b'-');
+                    line.extend_from_slice(lost.line);
+                    lines.push(line);
+                }
+            }
+            if cnt < lno {
+                break;
+            }
+            if sline.changed == 0 && context == 0 {
+                continue;
+            }
+            let mut line = columns(num_parents, sline.changed, b'+');
+            line.extend_from_slice(sline.line.unwrap_or_default());
+            lines.push(line);
+        }
+    }
+}
+
+/// Whether a line looks like the start of a function, as git's default funcname pattern
+fn is_comment_line(line: &[u8]) -> bool {
+    line.first()
+        .is_some_and(|&byte| byte.is_ascii_alphabetic() || byte == b'_' || byte == b'$')
+}
+
+/// A column for each parent, with `marker` for those in `parents`
+fn columns(num_parents: usize, parents: u64, marker: u8) -> Vec<u8> {
+    (0..num_parents)
+        .map(|n| match parents & (1 << n) {
+            0 => b' ',
+            _ => marker,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::combined_patch;
+    use crate::database::{compute_oid, Blob, Object};
+    use crate::diff::{PatchOptions, Target};
+
+    fn target(data: &str) -> Option<Target> {
+        let mut blob = Blob::new(data.as_bytes().to_vec());
+        compute_oid(&mut blob);
+        Some(Target {
+            path: PathBuf::from("f.c"),
+            oid: *blob.oid(),
+            mode: 0o100644,
+            data: data.as_bytes().to_vec(),
+            binary: None,
+        })
+    }
+
+    fn patch(parents: &[&str], result: &str) -> Vec<String> {
+        let parents: Vec<_> = parents.iter().map(|data| target(data)).collect();
+        combined_patch(&parents, target(result).as_ref(), &PatchOptions::default())
+            .into_iter()
+            .skip(4)
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn shows_lines_changed_from_each_parent() {
+        let ours = "int main(void)\n{\n   one;\n   two;\n}\n";
+        let theirs = "int main(void)\n{\n  two;\n  three;\n}\n";
+        let result = "int main(void)\n{\n   ONE;\n  TWO;\n  THREE;\n}\n";
+        assert_eq!(
+            patch(&[ours, theirs], result),
+            [
+                "@@@ -1,5 -1,5 +1,6 @@@",
+                "  int main(void)",
+                "  {",
+                "-    one;",
+                "-    two;",
+                " -  two;",
+                " -  three;",
+                "++   ONE;",
+                "++  TWO;",
+                "++  THREE;",
+                "  }",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_changes_taken_from_one_parent() {
+        let base = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let ours = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let theirs = "a\nb\nc\nd\ne\nf\ng\nh\nI\nj\n";
+        let result = "a\nB\nc\nd\ne\nf\ng\nh\nI\nj\n";
+        assert!(patch(&[ours, theirs], result).is_empty());
+        assert!(patch(&[base, base], base).is_empty());
+        assert_eq!(
+            patch(&[ours, theirs], "a\nB\nc\nd\ne\nf\ng\nh\nX\nj\n"),
+            [
+                "@@@ -6,5 -6,5 +6,5 @@@",
+                "  f",
+                "  g",
+                "  h",
+                "- i",
+                " -I",
+                "++X",
+                "  j",
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+/// The lines of one side of a diff, with those that are not matched marked as changed
+pub struct Side<'a> {
+    pub lines: &'a [u32],
+    pub changed: Vec<bool>,
+}
+
+/// A run of changed lines, possibly empty, at `start..end`
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Side<'_> {
+    fn is_changed(&self, n: usize) -> bool {
+        self.changed.get(n).copied().unwrap_or(false)
+    }
+
+    fn first_group(&self) -> Group {
+        let mut end = 0;
+        while self.is_changed(end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.lines.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = group.start;
+        while self.is_changed(group.end) {
+            group.end += 1;
+        }
+        true
+    }
+
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = group.end;
+        while group.start > 0 && self.is_changed(group.start - 1) {
+            group.start -= 1;
+        }
+        true
+    }
+
+    /// Moves the group down a line if the line after it equals its first line, joining any
+    /// group it runs into.
+    fn slide_down(&mut self, group: &mut Group) -> bool {
+        if group.end < self.lines.len() && self.lines[group.start] == self.lines[group.end] {
+            self.changed[group.start] = false;
+            self.changed[group.end] = true;
+            group.start += 1;
+            group.end += 1;
+            while self.is_changed(group.end) {
+                group.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, group: &mut Group) -> bool {
+        if group.start > 0 && self.lines[group.start - 1] == self.lines[group.end - 1] {
+            group.start -= 1;
+            group.end -= 1;
+            self.changed[group.start] = true;
+            self.changed[group.end] = false;
+            while group.start > 0 && self.is_changed(group.start - 1) {
+                group.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Shifts each run of changes on `side` as far down as it can go while describing the same
+/// edit, unless it can instead line up with a change on the `other` side, as git does for
+/// every algorithm. Equally short diffs are then shown the same way.
+pub fn compact(side: &mut Side, other: &Side) {
+    let mut group = side.first_group();
+    let mut other_group = other.first_group();
+
+    loop {
+        if group.end != group.start {
+            let mut end_matching_other;
+            let mut earliest_end;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+
+                while side.slide_up(&mut group) {
+                    other.previous_group(&mut other_group);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while side.slide_down(&mut group) {
+                    other.next_group(&mut other_group);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            // Prefer to end the group where it lines up with changes on the other side
+            if group.end != earliest_end && end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    side.slide_up(&mut group);
+                    other.previous_group(&mut other_group);
+                }
+            }
+        }
+
+        if !side.next_group(&mut group) {
+            break;
+        }
+        other.next_group(&mut other_group);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::myers;
+
+/// Lines occurring more often than this in a range make the histogram search give up on it and
+/// fall back to Myers' algorithm, as in git.
+const MAX_CHAIN: usize = 64;
+
+/// Matches the lines of `a[a_range]` and `b[b_range]` with the histogram algorithm: the longest
+/// common region made of the lines that occur least often in `a` is matched, and the ranges on
+/// either side of it are diffed recursively.
+pub fn diff(
+    a: &[u32],
+    b: &[u32],
+    a_range: Range<usize>,
+    b_range: Range<usize>,
+    matches: &mut Vec<(usize, usize)>,
+) {
+    if a_range.is_empty() || b_range.is_empty() {
+        return;
+    }
+
+    match find_region(a, b, a_range.clone(), b_range.clone()) {
+        Search::Found(region) => {
+            diff(
+                a,
+                b,
+                a_range.start..region.a.start,
+                b_range.start..region.b.start,
+                matches,
+            );
+            matches.extend(region.a.clone().zip(region.b.clone()));
+            diff(
+                a,
+                b,
+                region.a.end..a_range.end,
+                region.b.end..b_range.end,
+                matches,
+            );
+        }
+        Search::NoCommonLines => {}
+        Search::TooManyRepeats => myers::diff(a, b, a_range, b_range, matches),
+    }
+}
+
+struct Region {
+    a: Range<usize>,
+    b: Range<usize>,
+}
+
+enum Search {
+    Found(Region),
+    NoCommonLines,
+    TooManyRepeats,
+}
+
+fn find_region(a: &[u32], b: &[u32], a_range: Range<usize>, b_range: Range<usize>) -> Search {
+    // The positions in `a` of each line, in order
+    let mut occurrences: HashMap<u32, Vec<usize>> = HashMap::new();
+    for i in a_range.clone() {
+        let positions = occurrences.entry(a[i]).or_default();
+        if positions.len() == MAX_CHAIN {
+            return Search::TooManyRepeats;
+        }
+        positions.push(i);
+    }
+    let count = |line: u32| occurrences.get(&line).map_or(0, Vec::len);
+
+    let mut best: Option<Region> = None;
+    // Regions are only considered if their rarest line is no more common than the best one's
+    let mut best_count = MAX_CHAIN + 1;
+
+    let mut j = b_range.start;
+    while j < b_range.end {
+        let mut next_j = j + 1;
+        let positions = match occurrences.get(&b[j]) {
+            Some(positions) if positions.len() <= best_count => positions,
+            _ => {
+                j = next_j;
+                continue;
+            }
+        };
+
+        for &i in positions {
+            let mut rarest = positions.len();
+            let (mut a_start, mut b_start) = (i, j);
+            while a_start > a_range.start
+                && b_start > b_range.start
+                && a[a_start - 1] == b[b_start - 1]
+            {
+                a_start -= 1;
+                b_start -= 1;
+                rarest = rarest.min(count(a[a_start]));
+            }
+            let (mut a_end, mut b_end) = (i + 1, j + 1);
+            while a_end < a_range.end && b_end < b_range.end && a[a_end] == b[b_end] {
+                rarest = rarest.min(count(a[a_end]));
+                a_end += 1;
+                b_end += 1;
+            }
+            next_j = next_j.max(b_end);
+
+            let longer = best
+                .as_ref()
+                .is_none_or(|region| region.a.len() < a_end - a_start);
+            if longer || rarest < best_count {
+                best = Some(Region {
+                    a: a_start..a_end,
+                    b: b_start..b_end,
+                });
+                best_count = rarest;
+            }
+        }
+        j = next_j;
+    }
+
+    match best {
+        Some(region) => Search::Found(region),
+        None => Search::NoCommonLines,
+    }
+}
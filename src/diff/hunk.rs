@@ -0,0 +1,153 @@
+use super::Edit;
+
+/// Unchanged lines shown around each change, unless another amount is asked for
+pub const DEFAULT_CONTEXT: usize = 3;
+/// Function names in hunk headers are cut off at this many bytes, as in git
+const MAX_FUNCNAME_SIZE: usize = 80;
+
+/// A run of edits shown together in a unified diff, with the line numbers it starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub a_start: usize,
+    pub b_start: usize,
+    pub edits: Vec<Edit>,
+}
+
+impl Hunk {
+    /// Groups the changes in `edits` into hunks with `context` unchanged lines around them.
+    /// Changes separated by no more than twice that many unchanged lines share a hunk.
+    pub fn build(edits: &[Edit], context: usize) -> Vec<Hunk> {
+        let changes: Vec<usize> = edits
+            .iter()
+            .enumerate()
+            .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+            .map(|(n, _)| n)
+            .collect();
+
+        let mut hunks = Vec::new();
+        let mut n = 0;
+        while n < changes.len() {
+            let start = changes[n].saturating_sub(context);
+            let mut last = changes[n];
+            n += 1;
+            while n < changes.len() && changes[n] - last <= 2 * context + 1 {
+                last = changes[n];
+                n += 1;
+            }
+            let end = (last + context + 1).min(edits.len());
+
+            let (a_start, b_start) =
+                edits[..start]
+                    .iter()
+                    .fold((0, 0), |(a, b), edit| match edit {
+                        Edit::Equal(..) => (a + 1, b + 1),
+                        Edit::Delete(_) => (a + 1, b),
+                        Edit::Insert(_) => (a, b + 1),
+                    });
+            hunks.push(Hunk {
+                a_start,
+                b_start,
+                edits: edits[start..end].to_vec(),
+            });
+        }
+        hunks
+    }
+
+    pub fn a_len(&self) -> usize {
+        self.edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count()
+    }
+
+    pub fn b_len(&self) -> usize {
+        self.edits
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete(_)))
+            .count()
+    }
+
+    /// The `@@ -1,2 +1,3 @@` line introducing the hunk
+    pub fn header(&self) -> Vec<u8> {
+        format!(
+            "@@ -{} +{} @@",
+            range(self.a_start, self.a_len()),
+            range(self.b_start, self.b_len())
+        )
+        .into_bytes()
+    }
+
+    /// The closest line before the hunk in the old file that looks like the start of a
+    /// function, i.e. begins with a letter, `_` or `$`, as git's default funcname pattern.
+    pub fn funcname<'a>(&self, a_lines: &[&'a [u8]]) -> Option<&'a [u8]> {
+        a_lines[..self.a_start.min(a_lines.len())]
+            .iter()
+            .rev()
+            .find(|line| {
+                line.first()
+                    .is_some_and(|&byte| byte.is_ascii_alphabetic() || byte == b'_' || byte == b'$')
+            })
+            .map(|line| {
+                let line = &line[..line.len().min(MAX_FUNCNAME_SIZE)];
+                let end = line
+                    .iter()
+                    .rposition(|byte| !byte.is_ascii_whitespace())
+                    .map_or(0, |n| n + 1);
+                &line[..end]
+            })
+    }
+}
+
+/// A hunk's range of lines on one side. Empty ranges start at the line before them.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hunk;
+    use crate::diff::{diff_lines, split_lines, Algorithm};
+
+    fn hunks(a: &str, b: &str) -> Vec<(String, usize)> {
+        let a_lines = split_lines(a.as_bytes());
+        let b_lines = split_lines(b.as_bytes());
+        let edits = diff_lines(&a_lines, &b_lines, Algorithm::Myers);
+        Hunk::build(&edits, 3)
+            .iter()
+            .map(|hunk| {
+                let mut header = String::from_utf8(hunk.header()).unwrap();
+                if let Some(funcname) = hunk.funcname(&a_lines) {
+                    header = format!("{} {}", header, String::from_utf8_lossy(funcname));
+                }
+                (header, hunk.edits.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_nearby_changes() {
+        let a: String = (1..=20).map(|n| format!("line {}\n", n)).collect();
+        let b = a
+            .replace("line 2\n", "two\n")
+            .replace("line 9\n", "nine\n")
+            .replace("line 17\n", "");
+
+        assert_eq!(
+            hunks(&a, &b),
+            [
+                ("@@ -1,12 +1,12 @@".to_owned(), 14),
+                ("@@ -14,7 +14,6 @@ line 13".to_owned(), 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_empty_ranges_from_the_line_before() {
+        assert_eq!(hunks("", "a\n"), [("@@ -0,0 +1 @@".to_owned(), 1)]);
+        assert_eq!(hunks("a\nb\n", ""), [("@@ -1,2 +0,0 @@".to_owned(), 2)]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::compact::{compact, Side};
+use super::{histogram, myers, patience};
+
+/// How matching lines are chosen when several edit scripts are equally short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Myers,
+    /// Anchors the diff on lines that occur once on each side
+    Patience,
+    /// Anchors the diff on the lines that occur least often
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            // Myers' algorithm here always finds a minimal diff
+            "default" | "myers" | "minimal" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => bail!("unknown diff algorithm '{}'", name),
+        }
+    }
+}
+
+/// One step of an edit script, with the indexes of the lines it refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Splits `data` into lines, each keeping its newline. The last line may have none.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/// Computes an edit script turning `a` into `b`. Runs of changes are shifted down where that
+/// describes the same edit, as git does, and deletions come before insertions within each run.
+pub fn diff_lines<T: Hash + Eq>(a: &[T], b: &[T], algorithm: Algorithm) -> Vec<Edit> {
+    // Equal lines are given the same id, so the algorithms only compare integers
+    let mut ids = HashMap::new();
+    let mut intern = |line| {
+        let next = ids.len() as u32;
+        *ids.entry(line).or_insert(next)
+    };
+    let a_ids: Vec<u32> = a.iter().map(&mut intern).collect();
+    let b_ids: Vec<u32> = b.iter().map(&mut intern).collect();
+
+    let mut matches = Vec::new();
+    let (a_range, b_range) = (0..a.len(), 0..b.len());
+    match algorithm {
+        Algorithm::Myers => myers::diff(&a_ids, &b_ids, a_range, b_range, &mut matches),
+        Algorithm::Patience => patience::diff(&a_ids, &b_ids, a_range, b_range, &mut matches),
+        Algorithm::Histogram => histogram::diff(&a_ids, &b_ids, a_range, b_range, &mut matches),
+    }
+
+    let mut a_side = Side {
+        lines: &a_ids,
+        changed: vec![true; a.len()],
+    };
+    let mut b_side = Side {
+        lines: &b_ids,
+        changed: vec![true; b.len()],
+    };
+    for (i, j) in matches {
+        a_side.changed[i] = false;
+        b_side.changed[j] = false;
+    }
+    compact(&mut a_side, &b_side);
+    compact(&mut b_side, &a_side);
+
+    let mut edits = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && a_side.changed[i] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else if j < b.len() && b_side.changed[j] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(i, j));
+            i += 1;
+            j += 1;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_lines, split_lines, Algorithm, Edit};
+
+    /// Renders an edit script in the style of a unified diff body
+    fn render(a: &str, b: &str, algorithm: Algorithm) -> String {
+        let a = split_lines(a.as_bytes());
+        let b = split_lines(b.as_bytes());
+        diff_lines(&a, &b, algorithm)
+            .into_iter()
+            .map(|edit| {
+                let (sign, line) = match edit {
+                    Edit::Equal(i, _) => (' ', a[i]),
+                    Edit::Delete(i) => ('-', a[i]),
+                    Edit::Insert(j) => ('+', b[j]),
+                };
+                format!("{}{}", sign, String::from_utf8_lossy(line))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_algorithm_finds_a_valid_script() {
+        let a = "a\nb\nc\na\nb\nb\na\n";
+        let b = "c\nb\na\nb\na\nc\n";
+        for algorithm in [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            let script = render(a, b, algorithm);
+            let old: String = script
+                .lines()
+                .filter(|line| !line.starts_with('+'))
+                .map(|line| format!("{}\n", &line[1..]))
+                .collect();
+            let new: String = script
+                .lines()
+                .filter(|line| !line.starts_with('-'))
+                .map(|line| format!("{}\n", &line[1..]))
+                .collect();
+            assert_eq!((old.as_str(), new.as_str()), (a, b), "{:?}", algorithm);
+        }
+        // The shortest script has five changes
+        let changes = render(a, b, Algorithm::Myers)
+            .lines()
+            .filter(|line| !line.starts_with(' '))
+            .count();
+        assert_eq!(changes, 5);
+    }
+
+    #[test]
+    fn anchors_on_unique_lines() {
+        let a = "fn a() {\n    one\n}\n\nfn b() {\n    two\n}\n";
+        let b = "fn a() {\n    one\n}\n\nfn new() {\n    three\n}\n\nfn b() {\n    two\n}\n";
+
+        assert_eq!(
+            render(a, b, Algorithm::Myers),
+            " fn a() {\n     one\n }\n \n+fn new() {\n+    three\n+}\n+\n fn b() {\n     two\n }\n"
+        );
+
+        // Myers matches the bodies and braces of different functions
+        let a = "fn d() {\n    one();\n}\n\nfn b() {\n    two();\n}\n";
+        let b = "fn b() {\n    two();\n}\n\nfn a() {\n    one();\n}\n\nfn d() {\n    two();\n}\n";
+        assert_eq!(
+            render(a, b, Algorithm::Myers),
+            "-fn d() {\n+fn b() {\n+    two();\n+}\n+\n+fn a() {\n     one();\n }\n \n\
+             -fn b() {\n+fn d() {\n     two();\n }\n"
+        );
+        let expected = "-fn d() {\n-    one();\n-}\n-\n fn b() {\n     two();\n }\n\
+                        +\n+fn a() {\n+    one();\n+}\n+\n+fn d() {\n+    two();\n+}\n";
+        assert_eq!(render(a, b, Algorithm::Patience), expected);
+        assert_eq!(render(a, b, Algorithm::Histogram), expected);
+    }
+
+    #[test]
+    fn parses_algorithm_names() {
+        assert_eq!("minimal".parse::<Algorithm>().unwrap(), Algorithm::Myers);
+        assert_eq!(
+            "histogram".parse::<Algorithm>().unwrap(),
+            Algorithm::Histogram
+        );
+        assert!("fast".parse::<Algorithm>().is_err());
+    }
+}
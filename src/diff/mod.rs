@@ -0,0 +1,97 @@
+mod binary;
+mod combined;
+mod compact;
+mod histogram;
+mod hunk;
+mod lines;
+mod moved;
+mod myers;
+mod output;
+mod patch;
+mod patience;
+mod rename;
+mod stat;
+mod tree;
+mod words;
+
+use std::path::Path;
+
+use crate::database::TreeFile;
+
+pub use self::binary::*;
+pub use self::combined::*;
+pub use self::hunk::*;
+pub use self::lines::*;
+pub use self::moved::*;
+pub use self::output::{write_patch_lines, OutputOptions};
+pub use self::patch::*;
+pub use self::rename::*;
+pub use self::stat::*;
+pub use self::tree::*;
+pub use self::words::*;
+
+/// How a path differs between two trees, as shown by the status letters of `git diff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    /// The file changed between a regular file, a symlink and a submodule
+    TypeChanged,
+    Renamed,
+    Copied,
+}
+
+/// One difference between two trees. Added files have no old side and deleted files no new
+/// side; renames and copies have sides with different paths.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub status: Status,
+    pub old: Option<TreeFile>,
+    pub new: Option<TreeFile>,
+    /// How similar the sides of a rename or copy are, as a percentage
+    pub similarity: Option<u32>,
+}
+
+impl Status {
+    pub fn letter(self) -> char {
+        match self {
+            Status::Added => 'A',
+            Status::Deleted => 'D',
+            Status::Modified => 'M',
+            Status::TypeChanged => 'T',
+            Status::Renamed => 'R',
+            Status::Copied => 'C',
+        }
+    }
+}
+
+impl Change {
+    fn new(old: Option<TreeFile>, new: Option<TreeFile>) -> Self {
+        let status = match (&old, &new) {
+            (None, _) => Status::Added,
+            (_, None) => Status::Deleted,
+            (Some(old), Some(new)) if file_type(old) != file_type(new) => Status::TypeChanged,
+            _ => Status::Modified,
+        };
+        Self {
+            status,
+            old,
+            new,
+            similarity: None,
+        }
+    }
+
+    /// The path the change is listed under: the new path, unless the file was deleted
+    pub fn path(&self) -> &Path {
+        match (&self.new, &self.old) {
+            (Some(file), _) | (None, Some(file)) => file.rel_path(),
+            (None, None) => unreachable!("Programmer error: change with no sides"),
+        }
+    }
+}
+
+/// The type bits of a file's mode, which tell regular files, symlinks and submodules apart
+fn file_type(file: &TreeFile) -> u32 {
+    file.file_mode() & 0o170000
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use super::PatchLine;
+
+/// Moved blocks with fewer letters and digits than this are not worth showing as moved
+const MIN_ALNUM_COUNT: usize = 20;
+
+/// How lines that were moved within a diff are shown, as in `git diff --color-moved`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovedMode {
+    /// Every changed line that also appears on the other side of the diff is moved
+    Plain,
+    /// Lines are moved in blocks of enough text, all shown in one color
+    Blocks,
+    /// Blocks alternate between two colors where they are next to each other
+    Zebra,
+    /// Like zebra, but only the lines where adjacent blocks meet are highlighted
+    DimmedZebra,
+}
+
+/// How to color a moved line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Moved {
+    /// The line is in the second of two adjacent blocks
+    pub alternate: bool,
+    /// The line is not at the edge of a block
+    pub dimmed: bool,
+}
+
+impl FromStr for MovedMode {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "plain" => Ok(MovedMode::Plain),
+            "blocks" => Ok(MovedMode::Blocks),
+            "default" | "zebra" => Ok(MovedMode::Zebra),
+            "dimmed-zebra" | "dimmed_zebra" => Ok(MovedMode::DimmedZebra),
+            _ => bail!("color moved setting must be one of 'no', 'default', 'blocks', 'zebra', 'dimmed-zebra', 'plain'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Flags {
+    moved: bool,
+    alternate: bool,
+    dimmed: bool,
+}
+
+/// Finds the deleted and inserted lines in `lines` that were moved from or to elsewhere in the
+/// diff. Blocks of moved lines are followed for as long as they stay together on both sides.
+pub fn mark_moved(lines: &[PatchLine], mode: MovedMode) -> Vec<Option<Moved>> {
+    let mut marker = Marker::new(lines, mode);
+    marker.mark();
+    if mode == MovedMode::DimmedZebra {
+        marker.dim();
+    }
+    marker
+        .flags
+        .iter()
+        .map(|flags| {
+            flags.moved.then_some(Moved {
+                alternate: flags.alternate,
+                dimmed: flags.dimmed,
+            })
+        })
+        .collect()
+}
+
+/// Whether a line was deleted or inserted, with its content
+fn changed_line(line: &PatchLine) -> Option<(bool, &[u8])> {
+    match line {
+        PatchLine::Delete(text) => Some((false, text)),
+        PatchLine::Insert(text) => Some((true, text)),
+        _ => None,
+    }
+}
+
+struct Marker<'a> {
+    lines: &'a [PatchLine],
+    mode: MovedMode,
+    flags: Vec<Flags>,
+    /// Positions of the deleted and inserted lines with each content
+    deleted: HashMap<&'a [u8], Vec<usize>>,
+    inserted: HashMap<&'a [u8], Vec<usize>>,
+}
+
+impl<'a> Marker<'a> {
+    fn new(lines: &'a [PatchLine], mode: MovedMode) -> Self {
+        let mut deleted: HashMap<_, Vec<_>> = HashMap::new();
+        let mut inserted: HashMap<_, Vec<_>> = HashMap::new();
+        for (n, line) in lines.iter().enumerate() {
+            match changed_line(line) {
+                Some((false, text)) => deleted.entry(text).or_default().push(n),
+                Some((true, text)) => inserted.entry(text).or_default().push(n),
+                None => {}
+            }
+        }
+        Self {
+            lines,
+            mode,
+            flags: vec![Flags::default(); lines.len()],
+            deleted,
+            inserted,
+        }
+    }
+
+    /// Lines on the other side of the diff with the same content as line `n`
+    fn matches(&self, n: usize) -> Option<&Vec<usize>> {
+        let (inserted, text) = changed_line(&self.lines[n])?;
+        let matches = if inserted {
+            &self.deleted
+        } else {
+            &self.inserted
+        };
+        matches.get(text)
+    }
+
+    /// The line after `n`, if it is on the same side of the diff
+    fn next_line(&self, n: usize) -> Option<usize> {
+        let (side, _) = changed_line(&self.lines[n])?;
+        let (next_side, _) = changed_line(self.lines.get(n + 1)?)?;
+        (side == next_side).then_some(n + 1)
+    }
+
+    fn mark(&mut self) {
+        // Where each block that could have been moved to the current line is on the other side
+        let mut candidates: Vec<usize> = Vec::new();
+        let mut block_length = 0;
+        let mut alternate = false;
+        // Which side the current block is on
+        let mut block_side = None;
+
+        let mut n = 0;
+        while n < self.lines.len() {
+            let side = changed_line(&self.lines[n]).map(|(side, _)| side);
+            let mut has_matches = self.matches(n).is_some();
+            if side.is_none() {
+                alternate = false;
+            }
+
+            if !candidates.is_empty() && (!has_matches || side != block_side) {
+                if !self.adjust_last_block(n, block_length) && block_length > 1 {
+                    // Look for another block starting at the second line of this one
+                    has_matches = false;
+                    n -= block_length;
+                }
+                candidates.clear();
+                block_length = 0;
+                alternate = false;
+            }
+            if !has_matches {
+                block_side = None;
+                n += 1;
+                continue;
+            }
+
+            if self.mode == MovedMode::Plain {
+                self.flags[n].moved = true;
+                n += 1;
+                continue;
+            }
+
+            let text = changed_line(&self.lines[n]).map(|(_, text)| text);
+            candidates = candidates
+                .iter()
+                .filter_map(|&candidate| self.next_line(candidate))
+                .filter(|&next| changed_line(&self.lines[next]).map(|(_, text)| text) == text)
+                .collect();
+
+            if candidates.is_empty() {
+                let contiguous = self.adjust_last_block(n, block_length);
+                if !contiguous && block_length > 1 {
+                    n -= block_length;
+                } else {
+                    candidates = self.matches(n).cloned().unwrap_or_default();
+                }
+
+                alternate =
+                    contiguous && !candidates.is_empty() && block_side == side && !alternate;
+                block_side = if candidates.is_empty() { None } else { side };
+                block_length = 0;
+            }
+
+            if !candidates.is_empty() {
+                block_length += 1;
+                self.flags[n].moved = true;
+                self.flags[n].alternate = alternate && self.mode != MovedMode::Blocks;
+            }
+            n += 1;
+        }
+        self.adjust_last_block(n, block_length);
+    }
+
+    /// Unmarks the block of `length` lines before line `n` if it has too little text to be
+    /// worth showing as moved. Returns whether the block is still marked.
+    fn adjust_last_block(&mut self, n: usize, length: usize) -> bool {
+        if self.mode == MovedMode::Plain {
+            return length > 0;
+        }
+        let block = n - length..n;
+        let alnum_count: usize = self.lines[block.clone()]
+            .iter()
+            .filter_map(changed_line)
+            .map(|(_, text)| {
+                text.iter()
+                    .filter(|byte| byte.is_ascii_alphanumeric())
+                    .count()
+            })
+            .sum();
+        if alnum_count >= MIN_ALNUM_COUNT {
+            return true;
+        }
+        for flags in &mut self.flags[block] {
+            flags.moved = false;
+        }
+        false
+    }
+
+    /// Dims the moved lines that are not at the edge of a block next to another block.
+    fn dim(&mut self) {
+        let zebra = |flags: &Flags| (flags.moved, flags.alternate);
+        for n in 0..self.lines.len() {
+            let flags = self.flags[n];
+            if !flags.moved {
+                continue;
+            }
+            let neighbor = |m: usize| {
+                changed_line(self.lines.get(m)?)?;
+                Some(self.flags[m])
+            };
+            let prev = n.checked_sub(1).and_then(neighbor);
+            let next = neighbor(n + 1);
+
+            let inside_block = prev.is_some_and(|prev| zebra(&prev) == zebra(&flags))
+                && next.is_some_and(|next| zebra(&next) == zebra(&flags));
+            let at_boundary = |other: Option<Flags>| {
+                other.is_some_and(|other| other.moved && other.alternate != flags.alternate)
+            };
+            if inside_block || !(at_boundary(prev) || at_boundary(next)) {
+                self.flags[n].dimmed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mark_moved, MovedMode};
+    use crate::diff::PatchLine;
+
+    fn delete(text: &str) -> PatchLine {
+        PatchLine::Delete(text.as_bytes().to_vec())
+    }
+
+    fn insert(text: &str) -> PatchLine {
+        PatchLine::Insert(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn marks_blocks_with_enough_text() {
+        let lines = vec![
+            delete("fn moved_function() {\n"),
+            delete("    do_something_useful();\n"),
+            delete("}\n"),
+            delete("x\n"),
+            PatchLine::Context(b"\n".to_vec()),
+            insert("fn moved_function() {\n"),
+            insert("    do_something_useful();\n"),
+            insert("}\n"),
+            insert("y\n"),
+        ];
+        let moved: Vec<_> = mark_moved(&lines, MovedMode::Blocks)
+            .iter()
+            .map(Option::is_some)
+            .collect();
+        assert_eq!(
+            moved,
+            [true, true, true, false, false, true, true, true, false]
+        );
+
+        let short = vec![delete("}\n"), insert("}\n")];
+        assert_eq!(mark_moved(&short, MovedMode::Blocks), [None, None]);
+        assert!(mark_moved(&short, MovedMode::Plain)
+            .iter()
+            .all(Option::is_some));
+    }
+}
@@ -0,0 +1,124 @@
+use std::ops::Range;
+
+/// Finds a longest common subsequence of the lines `a[a_range]` and `b[b_range]` with Myers'
+/// algorithm, appending the indexes of the matched lines to `matches` in order.
+///
+/// The linear space variant is used: the middle snake of the edit path is found by searching
+/// from both ends at once, and the halves on either side of it are diffed recursively.
+pub fn diff(
+    a: &[u32],
+    b: &[u32],
+    a_range: Range<usize>,
+    b_range: Range<usize>,
+    matches: &mut Vec<(usize, usize)>,
+) {
+    let (mut a_lo, mut a_hi, mut b_lo, mut b_hi) =
+        (a_range.start, a_range.end, b_range.start, b_range.end);
+
+    while a_lo < a_hi && b_lo < b_hi && a[a_lo] == b[b_lo] {
+        matches.push((a_lo, b_lo));
+        a_lo += 1;
+        b_lo += 1;
+    }
+    let mut suffix = 0;
+    while a_lo < a_hi && b_lo < b_hi && a[a_hi - 1] == b[b_hi - 1] {
+        a_hi -= 1;
+        b_hi -= 1;
+        suffix += 1;
+    }
+
+    if a_lo < a_hi && b_lo < b_hi {
+        if let Some((x, y)) = middle_snake(&a[a_lo..a_hi], &b[b_lo..b_hi]) {
+            diff(a, b, a_lo..a_lo + x, b_lo..b_lo + y, matches);
+            diff(a, b, a_lo + x..a_hi, b_lo + y..b_hi, matches);
+        }
+    }
+
+    matches.extend((0..suffix).map(|i| (a_hi + i, b_hi + i)));
+}
+
+/// Returns a point on a shortest edit path from the start of `a` and `b` to their end, where
+/// the forward and backward searches meet, or `None` if they have nothing in common.
+fn middle_snake(a: &[u32], b: &[u32]) -> Option<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d + 1;
+    let size = (2 * offset + 1) as usize;
+    // The furthest x reached on each diagonal k = x - y, searching forward and backward
+    let mut forward = vec![-1; size];
+    let mut backward = vec![-1; size];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+
+    let delta = n - m;
+    // With an odd delta, the paths can only meet while extending the forward search
+    let check_forward = delta % 2 != 0;
+    // Diagonals that have run off the edge of the grid are not searched again
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let i = (offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            forward[i] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if check_forward {
+                let j = offset + delta - k1;
+                if j >= 0 && (j as usize) < size && backward[j as usize] != -1 {
+                    // Compare the paths in the same coordinate system
+                    let x2 = n - backward[j as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let i = (offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            backward[i] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !check_forward {
+                let j = offset + delta - k2;
+                if j >= 0 && (j as usize) < size && forward[j as usize] != -1 {
+                    let x1 = forward[j as usize];
+                    let y1 = x1 - (j - offset);
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+
+    None
+}
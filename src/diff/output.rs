@@ -0,0 +1,207 @@
+use std::io::{self, Write};
+
+use super::{mark_moved, Moved, MovedMode, PatchLine, WordDiff};
+
+pub const RESET: &str = "\x1b[m";
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+/// Trailing whitespace on inserted lines is highlighted with a red background
+const WHITESPACE_ERROR: &str = "\x1b[41m";
+const OLD_MOVED: &str = "\x1b[1;35m";
+const OLD_MOVED_ALTERNATE: &str = "\x1b[1;34m";
+const NEW_MOVED: &str = "\x1b[1;36m";
+const NEW_MOVED_ALTERNATE: &str = "\x1b[1;33m";
+const MOVED_DIMMED: &str = "\x1b[2m";
+const MOVED_ALTERNATE_DIMMED: &str = "\x1b[2;3m";
+
+const NO_NEWLINE: &[u8] = b"\\ No newline at end of file";
+
+/// How patch lines are written
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    pub color: bool,
+    /// Show changed lines word by word rather than line by line
+    pub word_diff: Option<WordDiff>,
+    /// Color lines that were moved, which only has an effect if `color` is set
+    pub color_moved: Option<MovedMode>,
+}
+
+/// Writes the lines of one or more patches, colored as in git.
+pub fn write_patch_lines<W: Write>(
+    out: &mut W,
+    lines: &[PatchLine],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let mut colored = Colored {
+        out,
+        color: options.color,
+    };
+    if let Some(word_diff) = &options.word_diff {
+        return write_words(&mut colored, lines, word_diff);
+    }
+
+    let moved = match options.color_moved {
+        Some(mode) if options.color => mark_moved(lines, mode),
+        _ => vec![None; lines.len()],
+    };
+    for (line, moved) in lines.iter().zip(moved) {
+        colored.patch_line(line, moved)?;
+    }
+    Ok(())
+}
+
+fn moved_color(color: &'static str, alternate: &'static str, moved: Moved) -> &'static str {
+    match (moved.alternate, moved.dimmed) {
+        (false, false) => color,
+        (true, false) => alternate,
+        (false, true) => MOVED_DIMMED,
+        (true, true) => MOVED_ALTERNATE_DIMMED,
+    }
+}
+
+/// Writes runs of changed lines word by word, and everything else as usual.
+fn write_words<W: Write>(
+    colored: &mut Colored<W>,
+    lines: &[PatchLine],
+    word_diff: &WordDiff,
+) -> io::Result<()> {
+    let color = colored.color;
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    for line in lines {
+        match line {
+            PatchLine::Delete(text) => {
+                deleted.extend_from_slice(text);
+                continue;
+            }
+            PatchLine::Insert(text) => {
+                inserted.extend_from_slice(text);
+                continue;
+            }
+            _ => {}
+        }
+        if !deleted.is_empty() || !inserted.is_empty() {
+            word_diff.write_change(colored.out, &deleted, &inserted, color)?;
+            deleted.clear();
+            inserted.clear();
+        }
+        match line {
+            PatchLine::Context(text) => word_diff.write_context(colored.out, text, color)?,
+            line => colored.patch_line(line, None)?,
+        }
+    }
+    if !deleted.is_empty() || !inserted.is_empty() {
+        word_diff.write_change(colored.out, &deleted, &inserted, color)?;
+    }
+    Ok(())
+}
+
+struct Colored<'a, W> {
+    out: &'a mut W,
+    color: bool,
+}
+
+impl<W: Write> Colored<'_, W> {
+    fn patch_line(&mut self, line: &PatchLine, moved: Option<Moved>) -> io::Result<()> {
+        match line {
+            PatchLine::Meta(text) => self.line(BOLD, text),
+            PatchLine::Hunk(range, funcname) => {
+                self.write(CYAN, range)?;
+                if let Some(funcname) = funcname {
+                    self.out.write_all(b" ")?;
+                    self.write(RESET, funcname)?;
+                }
+                self.out.write_all(b"\n")
+            }
+            PatchLine::Context(text) => self.content(b' ', "", text),
+            PatchLine::Binary(text) => {
+                self.out.write_all(text)?;
+                self.out.write_all(b"\n")
+            }
+            PatchLine::Delete(text) => {
+                let color = match moved {
+                    Some(moved) => moved_color(OLD_MOVED, OLD_MOVED_ALTERNATE, moved),
+                    None => RED,
+                };
+                self.content(b'-', color, text)
+            }
+            PatchLine::Insert(text) => {
+                let color = match moved {
+                    Some(moved) => moved_color(NEW_MOVED, NEW_MOVED_ALTERNATE, moved),
+                    None => GREEN,
+                };
+                self.inserted(color, text)
+            }
+        }
+    }
+
+    /// Writes `text` in `color`, if colors are enabled.
+    fn write(&mut self, color: &str, text: &[u8]) -> io::Result<()> {
+        if self.color {
+            self.out.write_all(color.as_bytes())?;
+        }
+        self.out.write_all(text)?;
+        if self.color {
+            self.out.write_all(RESET.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, color: &str, text: &[u8]) -> io::Result<()> {
+        self.write(color, text)?;
+        self.out.write_all(b"\n")
+    }
+
+    /// Writes a line of a file after its `sign`. A carriage return at the end of the line is
+    /// kept outside the color.
+    fn content(&mut self, sign: u8, color: &str, text: &[u8]) -> io::Result<()> {
+        let (text, ending) = split_ending(text);
+        self.write(color, &[&[sign], text].concat())?;
+        self.end_line(ending)
+    }
+
+    /// Writes an inserted line, which has its sign colored separately so that whitespace
+    /// errors can be highlighted.
+    fn inserted(&mut self, color: &str, text: &[u8]) -> io::Result<()> {
+        let (text, ending) = split_ending(text);
+        self.write(color, b"+")?;
+        if !self.color {
+            self.out.write_all(text)?;
+            return self.end_line(ending);
+        }
+
+        let trimmed = text
+            .iter()
+            .rposition(|byte| !matches!(byte, b' ' | b'\t'))
+            .map_or(0, |n| n + 1);
+        if trimmed > 0 {
+            self.write(color, &text[..trimmed])?;
+        }
+        if trimmed < text.len() {
+            self.write(WHITESPACE_ERROR, &text[trimmed..])?;
+        }
+        self.end_line(ending)
+    }
+
+    fn end_line(&mut self, ending: &[u8]) -> io::Result<()> {
+        self.out.write_all(ending)?;
+        if !ending.ends_with(b"\n") {
+            self.out.write_all(b"\n")?;
+            self.line("", NO_NEWLINE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a line of a file from its line ending, which may be missing
+fn split_ending(text: &[u8]) -> (&[u8], &[u8]) {
+    let end = if text.ends_with(b"\r\n") {
+        text.len() - 2
+    } else if text.ends_with(b"\n") {
+        text.len() - 1
+    } else {
+        text.len()
+    };
+    text.split_at(end)
+}
@@ -0,0 +1,219 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::{
+    binary_patch, diff_lines, is_binary, split_lines, Algorithm, Change, Edit, Hunk, Status,
+    DEFAULT_CONTEXT,
+};
+use crate::attributes::{AttributeState, Attributes};
+use crate::database::{ObjectId, TreeFile};
+
+/// Hex digits shown of the oids on a patch's `index` line
+const ABBREV_SIZE: usize = 7;
+
+/// One side of a file's patch, with the content to compare
+#[derive(Debug)]
+pub struct Target {
+    pub path: PathBuf,
+    pub oid: ObjectId,
+    pub mode: u32,
+    pub data: Vec<u8>,
+    /// Whether the file's `diff` attribute makes it binary or text, if it is set either way.
+    /// Otherwise the file is binary if its content looks binary.
+    pub binary: Option<bool>,
+}
+
+impl Target {
+    /// The target for `file` with the content `data`, taking whether it is binary from its
+    /// `diff` attribute
+    pub fn new(file: &TreeFile, data: Vec<u8>, attributes: &mut Attributes) -> Result<Self> {
+        Ok(Self {
+            path: file.rel_path().to_owned(),
+            oid: *file.oid(),
+            mode: file.file_mode(),
+            data,
+            binary: match attributes.get(file.rel_path(), "diff")? {
+                Some(AttributeState::Unset) => Some(true),
+                Some(AttributeState::Set) => Some(false),
+                _ => None,
+            },
+        })
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary.unwrap_or_else(|| is_binary(&self.data))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PatchOptions {
+    pub algorithm: Algorithm,
+    /// Unchanged lines shown around each change
+    pub context: usize,
+    /// Write patches of binary files that can be applied, rather than just saying they differ
+    pub binary: bool,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Myers,
+            context: DEFAULT_CONTEXT,
+            binary: false,
+        }
+    }
+}
+
+/// A line of a patch. The lines of the files keep their newlines, so a line without one is the
+/// last line of a file that does not end in a newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchLine {
+    /// A line of the file header, such as `diff --git` or `index`
+    Meta(Vec<u8>),
+    /// A hunk's line ranges, and the name of the function it is in
+    Hunk(Vec<u8>, Option<Vec<u8>>),
+    Context(Vec<u8>),
+    Delete(Vec<u8>),
+    Insert(Vec<u8>),
+    /// A line about a binary file, which is never colored
+    Binary(Vec<u8>),
+}
+
+/// The lines of the patch between `old` and `new`, as in `git diff`. A missing side means the
+/// file was added or deleted.
+pub fn patch_lines(
+    old: Option<&Target>,
+    new: Option<&Target>,
+    options: &PatchOptions,
+) -> Vec<PatchLine> {
+    let (a_path, b_path) = match (old, new) {
+        (Some(old), Some(new)) => (&old.path, &new.path),
+        (Some(file), None) | (None, Some(file)) => (&file.path, &file.path),
+        (None, None) => return Vec::new(),
+    };
+    let mut lines = vec![PatchLine::Meta(
+        [
+            b"diff --git ",
+            &prefixed(b"a/", a_path)[..],
+            b" ",
+            &prefixed(b"b/", b_path),
+        ]
+        .concat(),
+    )];
+    match (old, new) {
+        (None, Some(new)) => lines.push(meta(format!("new file mode {:06o}", new.mode))),
+        (Some(old), None) => lines.push(meta(format!("deleted file mode {:06o}", old.mode))),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            lines.push(meta(format!("old mode {:06o}", old.mode)));
+            lines.push(meta(format!("new mode {:06o}", new.mode)));
+        }
+        _ => {}
+    }
+
+    if old.map(|old| old.oid) == new.map(|new| new.oid) {
+        return lines;
+    }
+    let binary = old.is_some_and(Target::is_binary) || new.is_some_and(Target::is_binary);
+    // Binary patches name their sides in full, so that they can be checked before applying
+    let abbrev = if binary && options.binary {
+        ObjectId::HEX_SIZE
+    } else {
+        ABBREV_SIZE
+    };
+    let oid = |target: Option<&Target>| {
+        target.map_or("0".repeat(abbrev), |target| {
+            target.oid.to_hex()[..abbrev].to_owned()
+        })
+    };
+    let mode = match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => format!(" {:06o}", old.mode),
+        _ => String::new(),
+    };
+    lines.push(meta(format!("index {}..{}{}", oid(old), oid(new), mode)));
+
+    let side = |prefix: &[u8], target: Option<&Target>| match target {
+        Some(target) => prefixed(prefix, &target.path),
+        None => b"/dev/null".to_vec(),
+    };
+    if binary {
+        if options.binary {
+            let old_data = old.map_or(&[][..], |old| &old.data);
+            let new_data = new.map_or(&[][..], |new| &new.data);
+            let patch = binary_patch(old_data, new_data);
+            lines.extend(patch.into_iter().map(PatchLine::Binary));
+        } else {
+            lines.push(PatchLine::Binary(
+                [
+                    b"Binary files ",
+                    &side(b"a/", old)[..],
+                    b" and ",
+                    &side(b"b/", new),
+                    b" differ",
+                ]
+                .concat(),
+            ));
+        }
+        return lines;
+    }
+
+    let a_lines = old.map_or_else(Vec::new, |old| split_lines(&old.data));
+    let b_lines = new.map_or_else(Vec::new, |new| split_lines(&new.data));
+    let edits = diff_lines(&a_lines, &b_lines, options.algorithm);
+    let hunks = Hunk::build(&edits, options.context);
+    if hunks.is_empty() {
+        return lines;
+    }
+
+    lines.push(PatchLine::Meta([b"--- ", &side(b"a/", old)[..]].concat()));
+    lines.push(PatchLine::Meta([b"+++ ", &side(b"b/", new)[..]].concat()));
+
+    for hunk in &hunks {
+        let funcname = hunk.funcname(&a_lines).map(<[u8]>::to_vec);
+        lines.push(PatchLine::Hunk(hunk.header(), funcname));
+        lines.extend(hunk.edits.iter().map(|edit| match *edit {
+            Edit::Equal(i, _) => PatchLine::Context(a_lines[i].to_vec()),
+            Edit::Delete(i) => PatchLine::Delete(a_lines[i].to_vec()),
+            Edit::Insert(j) => PatchLine::Insert(b_lines[j].to_vec()),
+        }));
+    }
+
+    lines
+}
+
+/// The lines of the patch for `change`, a rename or copy from `old` to `new`, which say how
+/// similar the files are after any change of mode
+pub fn rename_patch_lines(
+    change: &Change,
+    old: &Target,
+    new: &Target,
+    options: &PatchOptions,
+) -> Vec<PatchLine> {
+    let mut lines = patch_lines(Some(old), Some(new), options);
+    let kind = if change.status == Status::Copied {
+        "copy"
+    } else {
+        "rename"
+    };
+    let header = [
+        meta(format!(
+            "similarity index {}%",
+            change.similarity.unwrap_or(0)
+        )),
+        PatchLine::Meta(prefixed(format!("{} from ", kind).as_bytes(), &old.path)),
+        PatchLine::Meta(prefixed(format!("{} to ", kind).as_bytes(), &new.path)),
+    ];
+    // After the `diff --git` line and any `old mode` and `new mode` lines
+    let at = if old.mode == new.mode { 1 } else { 3 };
+    lines.splice(at..at, header);
+    lines
+}
+
+fn meta(text: String) -> PatchLine {
+    PatchLine::Meta(text.into_bytes())
+}
+
+fn prefixed(prefix: &[u8], path: &Path) -> Vec<u8> {
+    [prefix, path.as_os_str().as_bytes()].concat()
+}
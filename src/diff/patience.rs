@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::myers;
+
+/// Matches the lines of `a[a_range]` and `b[b_range]` with the patience algorithm: lines that
+/// occur exactly once on each side are matched in order, as anchors around which the gaps are
+/// diffed recursively. Ranges without such lines fall back to Myers' algorithm.
+pub fn diff(
+    a: &[u32],
+    b: &[u32],
+    a_range: Range<usize>,
+    b_range: Range<usize>,
+    matches: &mut Vec<(usize, usize)>,
+) {
+    if a_range.is_empty() || b_range.is_empty() {
+        return;
+    }
+
+    let anchors = unique_common_lines(a, b, a_range.clone(), b_range.clone());
+    if anchors.is_empty() {
+        myers::diff(a, b, a_range, b_range, matches);
+        return;
+    }
+
+    let (mut a_next, mut b_next) = (a_range.start, b_range.start);
+    for (i, j) in anchors {
+        diff(a, b, a_next..i, b_next..j, matches);
+        matches.push((i, j));
+        a_next = i + 1;
+        b_next = j + 1;
+    }
+    diff(a, b, a_next..a_range.end, b_next..b_range.end, matches);
+}
+
+/// The longest sequence of lines that are unique on both sides and appear in the same order
+fn unique_common_lines(
+    a: &[u32],
+    b: &[u32],
+    a_range: Range<usize>,
+    b_range: Range<usize>,
+) -> Vec<(usize, usize)> {
+    // For each line, its last position and number of occurrences on each side
+    let mut positions: HashMap<u32, (Option<usize>, Option<usize>, usize, usize)> = HashMap::new();
+    for i in a_range {
+        let entry = positions.entry(a[i]).or_insert((None, None, 0, 0));
+        entry.0 = Some(i);
+        entry.2 += 1;
+    }
+    for j in b_range {
+        if let Some(entry) = positions.get_mut(&b[j]) {
+            entry.1 = Some(j);
+            entry.3 += 1;
+        }
+    }
+    let mut unique: Vec<(usize, usize)> = positions
+        .values()
+        .filter_map(|&(i, j, a_count, b_count)| match (i, j) {
+            (Some(i), Some(j)) if a_count == 1 && b_count == 1 => Some((i, j)),
+            _ => None,
+        })
+        .collect();
+    unique.sort_unstable();
+
+    longest_increasing(&unique)
+}
+
+/// Patience sorting: the longest subsequence of `pairs`, which are sorted by their first
+/// element, whose second elements are increasing.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // The top of each pile, and for every pair the pair below it in the sequence
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (n, &(_, j)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].1 < j);
+        if pile > 0 {
+            previous[n] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(n);
+        } else {
+            piles[pile] = n;
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(piles.len());
+    let mut next = piles.last().copied();
+    while let Some(n) = next {
+        sequence.push(pairs[n]);
+        next = previous[n];
+    }
+    sequence.reverse();
+    sequence
+}
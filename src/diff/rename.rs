@@ -0,0 +1,402 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use super::{file_type, is_binary, Change, Status};
+use crate::database::{Database, ObjectId, TreeFile};
+
+/// Similarity scores are fractions of this, as in git
+const MAX_SCORE: u64 = 60000;
+/// Files must be at least half the same to be paired by default
+const DEFAULT_SCORE: u64 = MAX_SCORE / 2;
+/// Content is compared in chunks that end at a newline or after this many bytes
+const CHUNK_SIZE: usize = 64;
+/// Chunks are compared by their hash modulo this prime
+const HASH_BASE: u32 = 107927;
+
+/// Which sources are paired with added files, and how similar they must be.
+#[derive(Debug, Clone, Copy)]
+pub struct RenameOptions {
+    min_score: u64,
+    /// Whether modified files are also sources, and sources can be paired more than once
+    copies: bool,
+}
+
+impl RenameOptions {
+    /// Parses a threshold as given to `-M` or `-C`: a percentage such as `50%`, or digits
+    /// standing for a fraction, so that `5` and `50` both mean half. An empty threshold is the
+    /// default of 50%.
+    pub fn new(threshold: &str, copies: bool) -> Result<Self> {
+        let min_score = if threshold.is_empty() {
+            DEFAULT_SCORE
+        } else {
+            parse_score(threshold)?
+        };
+        Ok(Self { min_score, copies })
+    }
+}
+
+fn parse_score(threshold: &str) -> Result<u64> {
+    let (mut num, mut scale, mut dot) = (0, 1, false);
+    for (i, byte) in threshold.bytes().enumerate() {
+        match byte {
+            b'.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            b'%' if i == threshold.len() - 1 => scale = if dot { scale * 100 } else { 100 },
+            b'0'..=b'9' => {
+                // Further digits are too small to matter
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(byte - b'0');
+                }
+            }
+            _ => bail!("Invalid similarity threshold '{}'", threshold),
+        }
+    }
+    Ok(if num >= scale {
+        MAX_SCORE
+    } else {
+        MAX_SCORE * num / scale
+    })
+}
+
+/// Replaces pairs of deleted and added files with renames when their content is similar
+/// enough, and with copies if `options.copies` allows it. Identical files are paired first, then
+/// the most similar pairs.
+pub fn detect_renames(
+    database: &Database,
+    changes: Vec<Change>,
+    options: &RenameOptions,
+) -> Result<Vec<Change>> {
+    detect_renames_with(changes, options, |file| {
+        Ok(database.load_blob(file.oid())?.data().to_vec())
+    })
+}
+
+/// Detects renames as `detect_renames` does, reading the content of files with `load`, for
+/// files that are not in the database such as those in the workspace
+pub fn detect_renames_with(
+    changes: Vec<Change>,
+    options: &RenameOptions,
+    load: impl FnMut(&TreeFile) -> Result<Vec<u8>>,
+) -> Result<Vec<Change>> {
+    let is_source = |change: &Change| match change.status {
+        Status::Deleted => true,
+        Status::Modified | Status::TypeChanged => options.copies,
+        _ => false,
+    };
+    let sources: Vec<usize> = (0..changes.len())
+        .filter(|&i| is_source(&changes[i]))
+        .collect();
+    let destinations: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].status == Status::Added)
+        .collect();
+    if sources.is_empty() || destinations.is_empty() {
+        return Ok(changes);
+    }
+
+    let old = |i: usize| {
+        changes[i]
+            .old
+            .as_ref()
+            .expect("Programmer error: source has no old side")
+    };
+    let new = |i: usize| {
+        changes[i]
+            .new
+            .as_ref()
+            .expect("Programmer error: added file has no new side")
+    };
+
+    // The source and similarity score paired with each destination
+    let mut pairs: HashMap<usize, (usize, u64)> = HashMap::new();
+    let mut used = vec![false; changes.len()];
+
+    for &dst in &destinations {
+        let exact = sources
+            .iter()
+            .filter(|&&src| options.copies || !used[src])
+            .filter(|&&src| {
+                old(src).oid() == new(dst).oid() && file_type(old(src)) == file_type(new(dst))
+            })
+            .max_by_key(|&&src| (same_basename(old(src), new(dst)), cmp::Reverse(src)));
+        if let Some(&src) = exact {
+            pairs.insert(dst, (src, MAX_SCORE));
+            used[src] = true;
+        }
+    }
+
+    let mut signatures = Signatures::new(load);
+    let mut candidates = Vec::new();
+    for &dst in destinations.iter().filter(|dst| !pairs.contains_key(dst)) {
+        for &src in sources.iter().filter(|&&src| options.copies || !used[src]) {
+            let (old, new) = (old(src), new(dst));
+            if !is_regular(old) || !is_regular(new) {
+                continue;
+            }
+            let score = signatures.similarity(old, new, options.min_score)?;
+            if score >= options.min_score {
+                candidates.push((score, same_basename(old, new), dst, src));
+            }
+        }
+    }
+    // Best scores first, preferring files that kept their name; earlier files win ties
+    candidates.sort_by_key(|&(score, same_name, dst, src)| {
+        (cmp::Reverse(score), cmp::Reverse(same_name), dst, src)
+    });
+    for (score, _, dst, src) in candidates {
+        if pairs.contains_key(&dst) || (used[src] && !options.copies) {
+            continue;
+        }
+        pairs.insert(dst, (src, score));
+        used[src] = true;
+    }
+
+    // A deleted file paired more than once was copied to all but its last destination
+    let mut remaining_uses = vec![0; changes.len()];
+    for &(src, _) in pairs.values() {
+        remaining_uses[src] += 1;
+    }
+
+    let mut result = Vec::with_capacity(changes.len());
+    for (i, change) in changes.iter().enumerate() {
+        if let Some(&(src, score)) = pairs.get(&i) {
+            let source = &changes[src];
+            remaining_uses[src] -= 1;
+            let status = if source.status == Status::Deleted && remaining_uses[src] == 0 {
+                Status::Renamed
+            } else {
+                Status::Copied
+            };
+            result.push(Change {
+                status,
+                old: source.old.clone(),
+                new: change.new.clone(),
+                similarity: Some((score * 100 / MAX_SCORE) as u32),
+            });
+        } else if !(change.status == Status::Deleted && used[i]) {
+            result.push(change.clone());
+        }
+    }
+    Ok(result)
+}
+
+fn is_regular(file: &TreeFile) -> bool {
+    file_type(file) == 0o100000
+}
+
+fn same_basename(a: &TreeFile, b: &TreeFile) -> bool {
+    a.rel_path().file_name() == b.rel_path().file_name()
+}
+
+/// The size and chunk hashes of blobs, computed once each as they are compared.
+struct Signatures<F> {
+    load: F,
+    cache: HashMap<ObjectId, Signature>,
+}
+
+struct Signature {
+    size: u64,
+    /// The number of bytes in chunks with each hash
+    chunks: HashMap<u32, u64>,
+}
+
+impl<F: FnMut(&TreeFile) -> Result<Vec<u8>>> Signatures<F> {
+    fn new(load: F) -> Self {
+        Self {
+            load,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, file: &TreeFile) -> Result<&Signature> {
+        let oid = file.oid();
+        if !self.cache.contains_key(oid) {
+            let data = (self.load)(file)?;
+            self.cache.insert(*oid, Signature::new(&data));
+        }
+        Ok(&self.cache[oid])
+    }
+
+    /// Estimates how much of `dst` was copied from `src`, as a fraction of the larger of the two.
+    /// Files whose sizes differ too much to reach `min_score` are not compared.
+    fn similarity(&mut self, src: &TreeFile, dst: &TreeFile, min_score: u64) -> Result<u64> {
+        let src_size = self.load(src)?.size;
+        let dst_size = self.load(dst)?.size;
+        let max_size = cmp::max(src_size, dst_size);
+        let delta_size = max_size - cmp::min(src_size, dst_size);
+        if dst_size == 0 || max_size * (MAX_SCORE - min_score) < delta_size * MAX_SCORE {
+            return Ok(0);
+        }
+
+        let (src, dst) = (&self.cache[src.oid()], &self.cache[dst.oid()]);
+        let copied: u64 = src
+            .chunks
+            .iter()
+            .map(|(hash, count)| cmp::min(*count, dst.chunks.get(hash).copied().unwrap_or(0)))
+            .sum();
+        Ok(copied * MAX_SCORE / max_size)
+    }
+}
+
+impl Signature {
+    fn new(data: &[u8]) -> Self {
+        // The CR of a CRLF is ignored in text, so line ending changes don't affect the score
+        let is_text = !is_binary(data);
+
+        let mut chunks = HashMap::new();
+        let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0);
+        let mut add_chunk = |accum1: u32, accum2: u32, len: usize| {
+            let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+            *chunks.entry(hash).or_insert(0) += len as u64;
+        };
+        for (i, &byte) in data.iter().enumerate() {
+            if is_text && byte == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            let old1 = accum1;
+            accum1 = (accum1 << 7) ^ (accum2 >> 25);
+            accum2 = (accum2 << 7) ^ (old1 >> 25);
+            accum1 = accum1.wrapping_add(u32::from(byte));
+            len += 1;
+            if len == CHUNK_SIZE || byte == b'\n' {
+                add_chunk(accum1, accum2, len);
+                accum1 = 0;
+                accum2 = 0;
+                len = 0;
+            }
+        }
+        if len > 0 {
+            add_chunk(accum1, accum2, len);
+        }
+
+        Self {
+            size: data.len() as u64,
+            chunks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::{detect_renames, parse_score, RenameOptions, MAX_SCORE};
+    use crate::database::{Blob, Database, Object, TreeFile};
+    use crate::diff::{Change, Status};
+
+    fn file(database: &Database, path: &str, content: &str) -> TreeFile {
+        let mut blob = Blob::new(content.as_bytes().to_vec());
+        database.store(&mut blob).expect("Database::store");
+        TreeFile::new(path, *blob.oid(), 0o100644)
+    }
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| {
+                let old = change.old.as_ref().map(|file| file.rel_path().display());
+                let new = change.new.as_ref().map(|file| file.rel_path().display());
+                match (change.similarity, old, new) {
+                    (Some(score), Some(old), Some(new)) => {
+                        format!("{}{:03} {} {}", change.status.letter(), score, old, new)
+                    }
+                    _ => format!("{} {}", change.status.letter(), change.path().display()),
+                }
+            })
+            .collect()
+    }
+
+    fn lines(count: usize) -> String {
+        (0..count).map(|i| format!("line number {}\n", i)).collect()
+    }
+
+    #[test]
+    fn parses_thresholds_like_git() {
+        assert_eq!(parse_score("50%").expect("parse_score"), MAX_SCORE / 2);
+        assert_eq!(parse_score("5").expect("parse_score"), MAX_SCORE / 2);
+        assert_eq!(parse_score("50").expect("parse_score"), MAX_SCORE / 2);
+        assert_eq!(parse_score(".5").expect("parse_score"), MAX_SCORE / 2);
+        assert_eq!(parse_score("12.5%").expect("parse_score"), MAX_SCORE / 8);
+        assert_eq!(parse_score("100%").expect("parse_score"), MAX_SCORE);
+        assert!(parse_score("5%0").is_err());
+        assert!(parse_score("half").is_err());
+    }
+
+    #[test]
+    fn pairs_similar_files() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+        let mut edited = lines(20);
+        edited.push_str("one more line\n");
+        let changes = vec![
+            Change::new(None, Some(file(&database, "a/moved.txt", &lines(10)))),
+            Change::new(None, Some(file(&database, "edited.txt", &edited))),
+            Change::new(None, Some(file(&database, "new.txt", "unrelated\n"))),
+            Change::new(Some(file(&database, "old.txt", &lines(20))), None),
+            Change::new(Some(file(&database, "z/moved.txt", &lines(10))), None),
+        ];
+
+        let options = RenameOptions::new("", false).expect("RenameOptions::new");
+        let renamed = detect_renames(&database, changes.clone(), &options).expect("detect");
+        assert_eq!(
+            summary(&renamed),
+            [
+                "R100 z/moved.txt a/moved.txt",
+                "R095 old.txt edited.txt",
+                "A new.txt"
+            ]
+        );
+
+        let options = RenameOptions::new("96%", false).expect("RenameOptions::new");
+        let renamed = detect_renames(&database, changes, &options).expect("detect");
+        assert_eq!(
+            summary(&renamed),
+            [
+                "R100 z/moved.txt a/moved.txt",
+                "A edited.txt",
+                "A new.txt",
+                "D old.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_from_modified_and_reused_sources() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+        let changes = vec![
+            Change::new(None, Some(file(&database, "a.txt", &lines(10)))),
+            Change::new(None, Some(file(&database, "b.txt", &lines(10)))),
+            Change::new(None, Some(file(&database, "c.txt", &lines(30)))),
+            Change::new(
+                Some(file(&database, "kept.txt", &lines(30))),
+                Some(file(&database, "kept.txt", "rewritten\n")),
+            ),
+            Change::new(Some(file(&database, "old.txt", &lines(10))), None),
+        ];
+
+        let options = RenameOptions::new("", false).expect("RenameOptions::new");
+        let renamed = detect_renames(&database, changes.clone(), &options).expect("detect");
+        assert_eq!(
+            summary(&renamed),
+            ["R100 old.txt a.txt", "A b.txt", "A c.txt", "M kept.txt"]
+        );
+
+        let options = RenameOptions::new("", true).expect("RenameOptions::new");
+        let copied = detect_renames(&database, changes, &options).expect("detect");
+        assert_eq!(
+            summary(&copied),
+            [
+                "C100 old.txt a.txt",
+                "R100 old.txt b.txt",
+                "C100 kept.txt c.txt",
+                "M kept.txt"
+            ]
+        );
+        assert_eq!(copied[1].status, Status::Renamed);
+    }
+}
@@ -0,0 +1,467 @@
+use std::cmp;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use super::output::{GREEN, RED, RESET};
+use super::{diff_lines, split_lines, Algorithm, Change, Edit, Target};
+
+/// The width `--stat` fits its lines into when the terminal's width is unknown
+pub const DEFAULT_STAT_WIDTH: usize = 80;
+
+/// The number of lines inserted into and deleted from one file. For binary files, the counts
+/// are the sizes of the new and old content in bytes, or zero if it did not change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// The path of the file, or both paths of a rename or copy
+    pub name: Vec<u8>,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub binary: bool,
+}
+
+impl FileStat {
+    /// Counts the lines changed between the sides of `change`, either of which may be missing.
+    pub fn new(
+        change: &Change,
+        old: Option<&Target>,
+        new: Option<&Target>,
+        algorithm: Algorithm,
+    ) -> Self {
+        let name = display_name(change);
+        if old.is_some_and(Target::is_binary) || new.is_some_and(Target::is_binary) {
+            let changed = old.map(|old| old.oid) != new.map(|new| new.oid);
+            let size = |target: Option<&Target>| match target {
+                Some(target) if changed => target.data.len(),
+                _ => 0,
+            };
+            return Self {
+                name,
+                insertions: size(new),
+                deletions: size(old),
+                binary: true,
+            };
+        }
+
+        let a_lines = old.map_or_else(Vec::new, |old| split_lines(&old.data));
+        let b_lines = new.map_or_else(Vec::new, |new| split_lines(&new.data));
+        let (mut insertions, mut deletions) = (0, 0);
+        for edit in diff_lines(&a_lines, &b_lines, algorithm) {
+            match edit {
+                Edit::Insert(_) => insertions += 1,
+                Edit::Delete(_) => deletions += 1,
+                Edit::Equal(..) => {}
+            }
+        }
+        Self {
+            name,
+            insertions,
+            deletions,
+            binary: false,
+        }
+    }
+
+    fn changes(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+/// The name a change is listed under. Renames and copies show both paths, with the parts they
+/// have in common written once, as in `dir/{old => new}.rs`.
+pub fn display_name(change: &Change) -> Vec<u8> {
+    match (&change.old, &change.new, change.similarity) {
+        (Some(old), Some(new), Some(_)) => rename_name(
+            old.rel_path().as_os_str().as_bytes(),
+            new.rel_path().as_os_str().as_bytes(),
+        ),
+        _ => change.path().as_os_str().as_bytes().to_vec(),
+    }
+}
+
+fn rename_name(a: &[u8], b: &[u8]) -> Vec<u8> {
+    // The common prefix ends in a slash
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // And so does the common suffix start with one, which may be the prefix's last slash
+    let mut suffix = 0;
+    let stop = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > stop && j > stop && a[i - 1] == b[j - 1] {
+        i -= 1;
+        j -= 1;
+        if a[i] == b'/' {
+            suffix = a.len() - i;
+        }
+    }
+
+    let a_middle = &a[prefix..cmp::max(prefix, a.len().saturating_sub(suffix))];
+    let b_middle = &b[prefix..cmp::max(prefix, b.len().saturating_sub(suffix))];
+    let mut name = Vec::new();
+    if prefix + suffix > 0 {
+        name.extend_from_slice(&a[..prefix]);
+        name.push(b'{');
+    }
+    name.extend_from_slice(a_middle);
+    name.extend_from_slice(b" => ");
+    name.extend_from_slice(b_middle);
+    if prefix + suffix > 0 {
+        name.push(b'}');
+        name.extend_from_slice(&a[a.len() - suffix..]);
+    }
+    name
+}
+
+/// Writes the status letter and paths of each change, as in `git diff --name-status`
+pub fn write_name_status<W: Write>(out: &mut W, changes: &[Change]) -> io::Result<()> {
+    for change in changes {
+        write!(out, "{}", change.status.letter())?;
+        if let Some(similarity) = change.similarity {
+            write!(out, "{:03}", similarity)?;
+        }
+        if let (Some(old), Some(_), Some(_)) = (&change.old, &change.new, change.similarity) {
+            out.write_all(b"\t")?;
+            out.write_all(old.rel_path().as_os_str().as_bytes())?;
+        }
+        out.write_all(b"\t")?;
+        out.write_all(change.path().as_os_str().as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes the insertions and deletions of each file as tab-separated numbers, as in
+/// `git diff --numstat`
+pub fn write_numstat<W: Write>(out: &mut W, stats: &[FileStat]) -> io::Result<()> {
+    for stat in stats {
+        if stat.binary {
+            out.write_all(b"-\t-\t")?;
+        } else {
+            write!(out, "{}\t{}\t", stat.insertions, stat.deletions)?;
+        }
+        out.write_all(&stat.name)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes a line per file with its number of changed lines and a histogram of `+` and `-`,
+/// fitted into `width` columns, followed by the totals, as in `git diff --stat`
+pub fn write_stat<W: Write>(
+    out: &mut W,
+    stats: &[FileStat],
+    width: usize,
+    color: bool,
+) -> io::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let digits = |n: usize| n.to_string().len();
+    let (binary, text): (Vec<&FileStat>, Vec<&FileStat>) =
+        stats.iter().partition(|stat| stat.binary);
+    let max_change = text.iter().map(|stat| stat.changes()).max().unwrap_or(0);
+    let max_name = stats.iter().map(|stat| stat.name.len()).max().unwrap_or(0);
+    // Counts line up with the `Bin` of binary files, whose sizes need room as well
+    let mut number_width = digits(max_change);
+    if !binary.is_empty() {
+        number_width = cmp::max(number_width, 3);
+    }
+    let bin_width = binary
+        .iter()
+        .map(|stat| 14 + digits(stat.insertions) + digits(stat.deletions))
+        .max()
+        .unwrap_or(0);
+
+    // The graph gets at least 6 columns and the names 10
+    let width = cmp::max(width, 16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_name;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width + number_width + 6 > width * 3 / 8 {
+            graph_width = cmp::max((width * 3 / 8).saturating_sub(number_width + 6), 6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for stat in stats {
+        // Names that are too long lose their start, and then the rest of their first directory
+        let (prefix, name) = if stat.name.len() > name_width {
+            let name = &stat.name[stat.name.len() - name_width.saturating_sub(3)..];
+            let name = match name.iter().position(|&byte| byte == b'/') {
+                Some(slash) => &name[slash..],
+                None => name,
+            };
+            ("...", name)
+        } else {
+            ("", &stat.name[..])
+        };
+        let padding = name_width.saturating_sub(prefix.len() + name.len());
+        write!(out, " {}", prefix)?;
+        out.write_all(name)?;
+        write!(out, "{:padding$} | ", "", padding = padding)?;
+
+        if stat.binary {
+            write!(out, "{:>number_width$}", "Bin", number_width = number_width)?;
+            if stat.changes() > 0 {
+                out.write_all(b" ")?;
+                write_colored(out, stat.deletions.to_string().as_bytes(), RED, color)?;
+                out.write_all(b" -> ")?;
+                write_colored(out, stat.insertions.to_string().as_bytes(), GREEN, color)?;
+                out.write_all(b" bytes")?;
+            }
+            out.write_all(b"\n")?;
+            continue;
+        }
+
+        let (mut insertions, mut deletions) = (stat.insertions, stat.deletions);
+        if graph_width <= max_change {
+            let scale = |n: usize| {
+                if n == 0 {
+                    0
+                } else {
+                    1 + n * (graph_width - 1) / max_change
+                }
+            };
+            let mut total = scale(insertions + deletions);
+            if total < 2 && insertions > 0 && deletions > 0 {
+                total = 2;
+            }
+            if insertions < deletions {
+                insertions = scale(insertions);
+                deletions = total - insertions;
+            } else {
+                deletions = scale(deletions);
+                insertions = total - deletions;
+            }
+        }
+
+        write!(
+            out,
+            "{:number_width$}{}",
+            stat.changes(),
+            if stat.changes() > 0 { " " } else { "" },
+            number_width = number_width
+        )?;
+        if insertions > 0 {
+            write_colored(out, &vec![b'+'; insertions], GREEN, color)?;
+        }
+        if deletions > 0 {
+            write_colored(out, &vec![b'-'; deletions], RED, color)?;
+        }
+        out.write_all(b"\n")?;
+    }
+    write_shortstat(out, stats)
+}
+
+fn write_colored<W: Write>(
+    out: &mut W,
+    text: &[u8],
+    color_code: &str,
+    color: bool,
+) -> io::Result<()> {
+    if color {
+        out.write_all(color_code.as_bytes())?;
+    }
+    out.write_all(text)?;
+    if color {
+        out.write_all(RESET.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes the number of files changed and the total insertions and deletions, as in
+/// `git diff --shortstat`
+pub fn write_shortstat<W: Write>(out: &mut W, stats: &[FileStat]) -> io::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let text = stats.iter().filter(|stat| !stat.binary);
+    let insertions: usize = text.clone().map(|stat| stat.insertions).sum();
+    let deletions: usize = text.map(|stat| stat.deletions).sum();
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+
+    let mut line = format!(" {}", plural(stats.len(), "file changed", "files changed"));
+    if insertions > 0 || deletions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        line.push_str(&format!(
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        ));
+    }
+    writeln!(out, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::{rename_name, write_name_status, write_shortstat, write_stat, FileStat};
+    use crate::database::{Blob, Database, Object, TreeFile};
+    use crate::diff::{detect_renames, Algorithm, Change, RenameOptions, Target};
+
+    fn stat(name: &str, insertions: usize, deletions: usize) -> FileStat {
+        FileStat {
+            name: name.as_bytes().to_vec(),
+            insertions,
+            deletions,
+            binary: false,
+        }
+    }
+
+    fn show_stat(stats: &[FileStat], width: usize) -> String {
+        let mut out = Vec::new();
+        write_stat(&mut out, stats, width, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn names_renames_by_what_changed() {
+        let name = |a: &str, b: &str| String::from_utf8(rename_name(a.as_bytes(), b.as_bytes()));
+        assert_eq!(name("old", "new").unwrap(), "old => new");
+        assert_eq!(name("dir/a.rs", "dir/b.rs").unwrap(), "dir/{a.rs => b.rs}");
+        assert_eq!(name("a/b/c.rs", "a/d/c.rs").unwrap(), "a/{b => d}/c.rs");
+        assert_eq!(
+            name("src/x.rs", "lib/src/x.rs").unwrap(),
+            "{src => lib/src}/x.rs"
+        );
+        assert_eq!(name("a/b/c", "a/c").unwrap(), "a/{b => }/c");
+    }
+
+    #[test]
+    fn shows_detected_renames() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path());
+        let file = |path: &str, content: &str| {
+            let mut blob = Blob::new(content.as_bytes().to_vec());
+            database.store(&mut blob).expect("Database::store");
+            TreeFile::new(path, *blob.oid(), 0o100644)
+        };
+        let content: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let changes = vec![
+            Change::new(None, Some(file("dir/b.txt", &format!("{}more\n", content)))),
+            Change::new(Some(file("dir/a.txt", &content)), None),
+        ];
+        let options = RenameOptions::new("", false).expect("RenameOptions::new");
+        let changes = detect_renames(&database, changes, &options).expect("detect_renames");
+
+        let mut out = Vec::new();
+        write_name_status(&mut out, &changes).expect("write_name_status");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "R096\tdir/a.txt\tdir/b.txt\n"
+        );
+
+        let target = |file: &Option<TreeFile>| {
+            file.as_ref().map(|file| Target {
+                path: file.rel_path().to_owned(),
+                oid: *file.oid(),
+                mode: file.file_mode(),
+                data: database
+                    .load_blob(file.oid())
+                    .expect("load_blob")
+                    .data()
+                    .to_vec(),
+                binary: None,
+            })
+        };
+        let (old, new) = (target(&changes[0].old), target(&changes[0].new));
+        let stats = [FileStat::new(
+            &changes[0],
+            old.as_ref(),
+            new.as_ref(),
+            Algorithm::Myers,
+        )];
+        assert_eq!(
+            show_stat(&stats, 80),
+            " dir/{a.txt => b.txt} | 1 +\n 1 file changed, 1 insertion(+)\n"
+        );
+    }
+
+    #[test]
+    fn scales_the_graph_to_the_width() {
+        let stats = [
+            stat("big", 0, 100),
+            stat("dir/some_quite_long_file_name_here.txt", 1, 0),
+            stat("mode", 0, 0),
+        ];
+        assert_eq!(
+            show_stat(&stats, 80),
+            format!(
+                " big                                    | 100 {}\n \
+                 dir/some_quite_long_file_name_here.txt |   1 +\n \
+                 mode                                   |   0\n \
+                 3 files changed, 1 insertion(+), 100 deletions(-)\n",
+                "-".repeat(33)
+            )
+        );
+        assert_eq!(
+            show_stat(&stats, 40),
+            " big                       | 100 ------\n \
+             ...ong_file_name_here.txt |   1 +\n \
+             mode                      |   0\n \
+             3 files changed, 1 insertion(+), 100 deletions(-)\n"
+        );
+        assert_eq!(
+            show_stat(&[stat("file", 2, 3)], 80),
+            " file | 5 ++---\n 1 file changed, 2 insertions(+), 3 deletions(-)\n"
+        );
+    }
+
+    #[test]
+    fn shows_sizes_of_binary_files() {
+        let binary = |name: &str, insertions, deletions| FileStat {
+            binary: true,
+            ..stat(name, insertions, deletions)
+        };
+        let stats = [binary("added", 4, 0), binary("mode", 0, 0), stat("t", 1, 0)];
+        assert_eq!(
+            show_stat(&stats, 80),
+            " added | Bin 0 -> 4 bytes\n \
+             mode  | Bin\n \
+             t     |   1 +\n \
+             3 files changed, 1 insertion(+)\n"
+        );
+    }
+
+    #[test]
+    fn leaves_out_zero_totals_unless_nothing_changed() {
+        let show = |stats: &[FileStat]| {
+            let mut out = Vec::new();
+            write_shortstat(&mut out, stats).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            show(&[stat("a", 1, 0)]),
+            " 1 file changed, 1 insertion(+)\n"
+        );
+        assert_eq!(
+            show(&[stat("a", 0, 2)]),
+            " 1 file changed, 2 deletions(-)\n"
+        );
+        assert_eq!(
+            show(&[stat("a", 0, 0)]),
+            " 1 file changed, 0 insertions(+), 0 deletions(-)\n"
+        );
+        assert_eq!(show(&[]), "");
+    }
+}
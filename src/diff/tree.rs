@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::Change;
+use crate::database::{Database, ObjectId, TreeFile};
+
+/// Lists the differences between the trees `old` and `new`, either of which may be missing, in
+/// path order. Subtrees with the same oid on both sides are skipped without being loaded.
+///
+/// Changed subtrees are compared entry by entry if `recursive`, and otherwise reported as
+/// changes to the subtrees themselves.
+pub fn diff_trees(
+    database: &Database,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    recursive: bool,
+) -> Result<Vec<Change>> {
+    let mut diff = TreeDiff {
+        database,
+        recursive,
+        changes: Vec::new(),
+    };
+    diff.compare(old, new, Path::new(""))?;
+
+    // Subtrees are visited in name order, but their entries sort after any name that extends
+    // the subtree's name with a character before '/'
+    let mut changes = diff.changes;
+    sort_by_path(&mut changes);
+    Ok(changes)
+}
+
+/// Lists the differences between two flat lists of files, such as the contents of a commit and
+/// of the index, in path order.
+pub fn diff_file_lists(
+    old: &BTreeMap<PathBuf, TreeFile>,
+    new: &BTreeMap<PathBuf, TreeFile>,
+) -> Vec<Change> {
+    let paths: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    let mut changes: Vec<Change> = paths
+        .into_iter()
+        .filter_map(|path| match (old.get(path), new.get(path)) {
+            (Some(old), Some(new))
+                if old.oid() == new.oid() && old.file_mode() == new.file_mode() =>
+            {
+                None
+            }
+            (old, new) => Some(Change::new(old.cloned(), new.cloned())),
+        })
+        .collect();
+    sort_by_path(&mut changes);
+    changes
+}
+
+fn sort_by_path(changes: &mut [Change]) {
+    changes.sort_by(|a, b| {
+        a.path()
+            .as_os_str()
+            .as_bytes()
+            .cmp(b.path().as_os_str().as_bytes())
+    });
+}
+
+struct TreeDiff<'a> {
+    database: &'a Database,
+    recursive: bool,
+    changes: Vec<Change>,
+}
+
+impl TreeDiff<'_> {
+    fn compare(
+        &mut self,
+        old: Option<&ObjectId>,
+        new: Option<&ObjectId>,
+        prefix: &Path,
+    ) -> Result<()> {
+        if old == new {
+            return Ok(());
+        }
+        let old_entries = self.load_entries(old, prefix)?;
+        let new_entries = self.load_entries(new, prefix)?;
+        let names: BTreeSet<_> = old_entries.keys().chain(new_entries.keys()).collect();
+
+        for name in names {
+            let old = old_entries.get(name);
+            let new = new_entries.get(name);
+            if let (Some(old), Some(new)) = (old, new) {
+                if old.oid() == new.oid() && old.file_mode() == new.file_mode() {
+                    continue;
+                }
+            }
+
+            let path = prefix.join(name);
+            let (old_tree, old_file) = self.split(old);
+            let (new_tree, new_file) = self.split(new);
+            match (old_file, new_file) {
+                // A tree is not a different type of file, so replacing one with a file is a
+                // deletion and an addition
+                (Some(old), Some(new)) if old.is_tree() != new.is_tree() => {
+                    self.changes.push(Change::new(Some(old), None));
+                    self.changes.push(Change::new(None, Some(new)));
+                }
+                (None, None) => {}
+                (old, new) => self.changes.push(Change::new(old, new)),
+            }
+            if old_tree.is_some() || new_tree.is_some() {
+                self.compare(old_tree.as_ref(), new_tree.as_ref(), &path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Separates a subtree to be compared recursively from a file to be compared directly.
+    fn split(&self, entry: Option<&TreeFile>) -> (Option<ObjectId>, Option<TreeFile>) {
+        match entry {
+            Some(entry) if entry.is_tree() && self.recursive => (Some(*entry.oid()), None),
+            Some(entry) => (None, Some(entry.clone())),
+            None => (None, None),
+        }
+    }
+
+    /// The entries of the tree `oid`, with their paths from the root of the diff
+    fn load_entries(
+        &self,
+        oid: Option<&ObjectId>,
+        prefix: &Path,
+    ) -> Result<BTreeMap<OsString, TreeFile>> {
+        let oid = match oid {
+            Some(oid) => oid,
+            None => return Ok(BTreeMap::new()),
+        };
+        Ok(self
+            .database
+            .load_tree(oid)?
+            .list_entries()
+            .map(|(name, entry)| {
+                let file = TreeFile::new(prefix.join(name), *entry.oid(), entry.file_mode());
+                (name.to_owned(), file)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::diff_trees;
+    use crate::database::{Blob, Database, Object, ObjectId};
+    use crate::diff::{Change, Status};
+    use crate::index::Index;
+
+    /// Stores a tree holding `files`, given as (path, content, mode).
+    fn store_tree(tempdir: &TempDir, database: &Database, files: &[(&str, &str, u32)]) -> ObjectId {
+        let index_path = tempdir.path().join("index");
+        let mut index = Index::load(index_path).expect("Index::load");
+        for (path, content, mode) in files {
+            let mut blob = Blob::new(content.as_bytes().to_vec());
+            database.store(&mut blob).expect("Database::store");
+            index
+                .add_entry(Path::new(path), blob.oid(), *mode, 0)
+                .expect("Index::add_entry");
+        }
+        index.write_tree(database).expect("Index::write_tree")
+    }
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| format!("{} {}", change.status.letter(), change.path().display()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_changes_in_nested_trees() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let old = store_tree(
+            &tempdir,
+            &database,
+            &[
+                ("a.txt", "a", 0o100644),
+                ("a/x", "x", 0o100644),
+                ("same/x", "x", 0o100644),
+                ("dir/b", "b", 0o100644),
+                ("dir/c", "c", 0o100644),
+                ("link", "a.txt", 0o120000),
+                ("file", "f", 0o100644),
+            ],
+        );
+        let new = store_tree(
+            &tempdir,
+            &database,
+            &[
+                ("a.txt", "a", 0o100755),
+                ("a/x", "x2", 0o100644),
+                ("same/x", "x", 0o100644),
+                ("dir/b", "b2", 0o100644),
+                ("dir/d", "d", 0o100644),
+                ("link", "a.txt", 0o100644),
+                ("file/inner", "f", 0o100644),
+            ],
+        );
+
+        let changes = diff_trees(&database, Some(&old), Some(&new), true).expect("diff_trees");
+        assert_eq!(
+            summary(&changes),
+            [
+                "M a.txt",
+                "M a/x",
+                "M dir/b",
+                "D dir/c",
+                "A dir/d",
+                "D file",
+                "A file/inner",
+                "T link"
+            ]
+        );
+        assert!(changes
+            .iter()
+            .all(|change| change.status != Status::Renamed));
+    }
+
+    #[test]
+    fn reports_subtrees_unless_recursive() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let new = store_tree(&tempdir, &database, &[("dir/b", "b", 0o100644)]);
+
+        let changes = diff_trees(&database, None, Some(&new), false).expect("diff_trees");
+        assert_eq!(summary(&changes), ["A dir"]);
+        assert!(changes[0].new.as_ref().expect("new side").is_tree());
+
+        let changes = diff_trees(&database, Some(&new), None, true).expect("diff_trees");
+        assert_eq!(summary(&changes), ["D dir/b"]);
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use regex::bytes::Regex;
+
+use super::output::{GREEN, RED, RESET};
+use super::{diff_lines, Algorithm, Edit};
+
+/// How changed words are marked, as in `git diff --word-diff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordDiffMode {
+    /// `[-deleted-]{+inserted+}`
+    Plain,
+    /// Deleted and inserted words in red and green
+    Color,
+    /// One line per run of words, starting with ` `, `-` or `+`, and `~` for newlines
+    Porcelain,
+}
+
+/// Options for diffing changed lines word by word
+#[derive(Debug, Clone)]
+pub struct WordDiff {
+    pub mode: WordDiffMode,
+    /// What a word is. By default, words are runs of characters other than whitespace.
+    pub regex: Option<Regex>,
+}
+
+impl FromStr for WordDiffMode {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "plain" => Ok(WordDiffMode::Plain),
+            "color" => Ok(WordDiffMode::Color),
+            "porcelain" => Ok(WordDiffMode::Porcelain),
+            _ => bail!("bad --word-diff argument: {}", name),
+        }
+    }
+}
+
+/// How one kind of text is marked
+struct Style {
+    prefix: &'static [u8],
+    suffix: &'static [u8],
+    color: Option<&'static str>,
+}
+
+impl WordDiff {
+    /// Writes an unchanged line of a hunk.
+    pub fn write_context<W: Write>(&self, out: &mut W, line: &[u8], color: bool) -> io::Result<()> {
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        if self.mode == WordDiffMode::Porcelain {
+            out.write_all(b" ")?;
+        }
+        out.write_all(text)?;
+        if color && (!text.is_empty() || self.mode == WordDiffMode::Porcelain) {
+            out.write_all(RESET.as_bytes())?;
+        }
+        out.write_all(b"\n")?;
+        if self.mode == WordDiffMode::Porcelain {
+            out.write_all(b"~\n")?;
+        }
+        Ok(())
+    }
+
+    /// Writes a run of deleted and inserted lines as the new text, with the words that differ
+    /// from the old text marked.
+    pub fn write_change<W: Write>(
+        &self,
+        out: &mut W,
+        deleted: &[u8],
+        inserted: &[u8],
+        color: bool,
+    ) -> io::Result<()> {
+        let (old, new, context, newline) = self.styles(color);
+        if inserted.is_empty() {
+            return write_text(out, &old, newline, deleted);
+        }
+
+        let old_words = self.split_words(deleted);
+        let new_words = self.split_words(inserted);
+        let old_slices: Vec<_> = old_words
+            .iter()
+            .map(|word| &deleted[word.clone()])
+            .collect();
+        let new_slices: Vec<_> = new_words
+            .iter()
+            .map(|word| &inserted[word.clone()])
+            .collect();
+        let edits = diff_lines(&old_slices, &new_slices, Algorithm::Myers);
+
+        // The end of the new text written so far
+        let mut current = 0;
+        let (mut i, mut j) = (0, 0);
+        let mut edits = edits.iter().peekable();
+        while let Some(edit) = edits.next() {
+            if let Edit::Equal(..) = edit {
+                i += 1;
+                j += 1;
+                continue;
+            }
+            let (first_old, first_new) = (i, j);
+            let mut edit = Some(edit);
+            while let Some(change) = edit {
+                match change {
+                    Edit::Delete(_) => i += 1,
+                    Edit::Insert(_) => j += 1,
+                    Edit::Equal(..) => unreachable!(),
+                }
+                edit = edits.next_if(|edit| !matches!(edit, Edit::Equal(..)));
+            }
+
+            let old_span = span(&old_words, first_old..i);
+            let new_span = span(&new_words, first_new..j);
+            if current < new_span.start {
+                write_text(out, &context, newline, &inserted[current..new_span.start])?;
+            }
+            write_text(out, &old, newline, &deleted[old_span])?;
+            write_text(out, &new, newline, &inserted[new_span.clone()])?;
+            current = new_span.end;
+        }
+        write_text(out, &context, newline, &inserted[current..])
+    }
+
+    /// The styles of deleted, inserted and unchanged text, and what newlines are written as
+    fn styles(&self, color: bool) -> (Style, Style, Style, &'static [u8]) {
+        let style = |prefix, suffix, color_code| Style {
+            prefix,
+            suffix,
+            color: color.then_some(color_code),
+        };
+        match self.mode {
+            WordDiffMode::Plain => (
+                style(b"[-", b"-]", RED),
+                style(b"{+", b"+}", GREEN),
+                style(b"", b"", ""),
+                b"\n",
+            ),
+            WordDiffMode::Color => (
+                style(b"", b"", RED),
+                style(b"", b"", GREEN),
+                style(b"", b"", ""),
+                b"\n",
+            ),
+            WordDiffMode::Porcelain => (
+                style(b"-", b"\n", RED),
+                style(b"+", b"\n", GREEN),
+                style(b" ", b"\n", ""),
+                b"~\n",
+            ),
+        }
+    }
+
+    /// The positions of the words in `text`. Words never span lines.
+    fn split_words(&self, text: &[u8]) -> Vec<Range<usize>> {
+        let is_space = |byte: u8| matches!(byte, b' ' | b'\t' | b'\n' | b'\r');
+        let mut words = Vec::new();
+        let mut start = 0;
+        while start < text.len() {
+            // Text the regex does not match is split on whitespace
+            if let Some(found) = self
+                .regex
+                .as_ref()
+                .and_then(|regex| regex.find(&text[start..]))
+            {
+                let word = start + found.start()..start + found.end();
+                let end = text[word.clone()]
+                    .iter()
+                    .position(|&byte| byte == b'\n')
+                    .map_or(word.end, |n| word.start + n);
+                if word.start == end {
+                    start = word.start + 1;
+                } else {
+                    words.push(word.start..end);
+                    start = end;
+                }
+                continue;
+            }
+
+            while start < text.len() && is_space(text[start]) {
+                start += 1;
+            }
+            if start == text.len() {
+                break;
+            }
+            let mut end = start + 1;
+            while end < text.len() && !is_space(text[end]) {
+                end += 1;
+            }
+            words.push(start..end);
+            start = end;
+        }
+        words
+    }
+}
+
+/// The text covered by a run of words. An empty run is placed at the end of the word before it.
+fn span(words: &[Range<usize>], run: Range<usize>) -> Range<usize> {
+    if run.is_empty() {
+        let end = run.start.checked_sub(1).map_or(0, |n| words[n].end);
+        end..end
+    } else {
+        words[run.start].start..words[run.end - 1].end
+    }
+}
+
+/// Writes `text` in `style`, marking each of its lines separately.
+fn write_text<W: Write>(out: &mut W, style: &Style, newline: &[u8], text: &[u8]) -> io::Result<()> {
+    let mut lines = text.split(|&byte| byte == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        if !line.is_empty() {
+            let color = style.color.filter(|color| !color.is_empty());
+            if let Some(color) = color {
+                out.write_all(color.as_bytes())?;
+            }
+            out.write_all(style.prefix)?;
+            out.write_all(line)?;
+            out.write_all(style.suffix)?;
+            if color.is_some() {
+                out.write_all(RESET.as_bytes())?;
+            }
+        }
+        if lines.peek().is_some() {
+            out.write_all(newline)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::bytes::Regex;
+
+    use super::{WordDiff, WordDiffMode};
+
+    fn show(mode: WordDiffMode, regex: Option<&str>, deleted: &str, inserted: &str) -> String {
+        let diff = WordDiff {
+            mode,
+            regex: regex.map(|regex| Regex::new(regex).unwrap()),
+        };
+        let mut out = Vec::new();
+        diff.write_change(&mut out, deleted.as_bytes(), inserted.as_bytes(), false)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn marks_changed_words() {
+        let deleted = "old line here\n";
+        let inserted = "new line there\n";
+        assert_eq!(
+            show(WordDiffMode::Plain, None, deleted, inserted),
+            "[-old-]{+new+} line [-here-]{+there+}\n"
+        );
+        assert_eq!(
+            show(WordDiffMode::Porcelain, None, deleted, inserted),
+            "-old\n+new\n  line \n-here\n+there\n~\n"
+        );
+        assert_eq!(
+            show(WordDiffMode::Plain, None, "gone\nlines\n", ""),
+            "[-gone-]\n[-lines-]\n"
+        );
+    }
+
+    #[test]
+    fn follows_the_new_lines_when_text_is_rewrapped() {
+        let deleted = "The quick brown fox\njumps over the dog.\n";
+        let inserted = "The quick brown\nfox jumps over the lazy\ndog.\n";
+        assert_eq!(
+            show(WordDiffMode::Plain, None, deleted, inserted),
+            "The quick brown\nfox jumps over the {+lazy+}\ndog.\n"
+        );
+    }
+
+    #[test]
+    fn splits_words_with_a_regex() {
+        assert_eq!(
+            show(
+                WordDiffMode::Plain,
+                Some("[a-z]+|[^[:space:]]"),
+                "foo(bar);\n",
+                "foo(baz);\n"
+            ),
+            "foo([-bar-]{+baz+});\n"
+        );
+        assert_eq!(
+            show(WordDiffMode::Plain, None, "foo(bar);\n", "foo(baz);\n"),
+            "[-foo(bar);-]{+foo(baz);+}\n"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::pathspec::{wildmatch, wildmatch_path};
+
+/// Patterns of untracked paths to leave alone, as in `.gitignore` files.
+///
+/// Patterns given on the command line take precedence over those in the `.gitignore` file of
+/// each directory, which are read as paths inside it are looked up and override those of the
+/// directories above, and those override patterns from files such as `.git/info/exclude`.
+/// Within each, the last matching pattern wins.
+#[derive(Debug)]
+pub struct Ignores {
+    root: PathBuf,
+    command_line: Vec<Rule>,
+    /// Whether each directory's `.gitignore` file is read
+    per_directory: bool,
+    dirs: HashMap<PathBuf, Vec<Rule>>,
+    files: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Vec<u8>,
+    /// `!pattern`, which includes paths again
+    negated: bool,
+    /// `pattern/`, which only matches directories
+    dir_only: bool,
+}
+
+impl Ignores {
+    /// An empty set of patterns, ignoring nothing
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            command_line: Vec::new(),
+            per_directory: false,
+            dirs: HashMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn add_pattern(&mut self, pattern: &str) {
+        self.command_line.extend(parse_rules(pattern.as_bytes()));
+    }
+
+    /// Reads patterns from `path`, if it exists
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        match fs::read(path) {
+            Ok(data) => self.files.extend(parse_rules(&data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("Reading {}", path.display())),
+        }
+        Ok(())
+    }
+
+    pub fn read_per_directory(&mut self) {
+        self.per_directory = true;
+    }
+
+    /// Whether the file at `path`, relative to the workspace root, is ignored, either itself or
+    /// because a directory it is in is ignored.
+    pub fn is_ignored(&mut self, path: &Path) -> Result<bool> {
+        let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        dirs.reverse();
+        for (n, dir) in dirs.iter().enumerate() {
+            if self.per_directory && !self.dirs.contains_key(*dir) {
+                let rules = match fs::read(self.root.join(dir).join(".gitignore")) {
+                    Ok(data) => parse_rules(&data),
+                    Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+                    Err(err) => return Err(err).with_context(|| format!("Reading {:?}", dir)),
+                };
+                self.dirs.insert(dir.to_path_buf(), rules);
+            }
+            if n > 0 && self.matches(&dirs[..n], dir, true) {
+                return Ok(true);
+            }
+        }
+        Ok(self.matches(&dirs, path, false))
+    }
+
+    /// Whether the last rule matching `path` in the most important set of rules that has one
+    /// ignores it. `dirs` are the directories containing `path`, from the root down.
+    fn matches(&self, dirs: &[&Path], path: &Path, is_dir: bool) -> bool {
+        let root = Path::new("");
+        let scopes = [(root, &self.command_line)]
+            .into_iter()
+            .chain(
+                dirs.iter()
+                    .rev()
+                    .filter_map(|dir| self.dirs.get(*dir).map(|rules| (*dir, rules))),
+            )
+            .chain([(root, &self.files)]);
+        for (dir, rules) in scopes {
+            let rel_path = path.strip_prefix(dir).unwrap_or(path);
+            let rule = rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(rel_path, is_dir));
+            if let Some(rule) = rule {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+/// Parses a pattern on each line. Blank lines and lines starting with `#` are skipped, as are
+/// trailing spaces, and a leading backslash escapes a `#` or `!`.
+fn parse_rules(data: &[u8]) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in data.split(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let end = line
+            .iter()
+            .rposition(|&byte| byte != b' ')
+            .map_or(0, |n| n + 1);
+        let mut pattern = &line[..end];
+        if pattern.is_empty() || pattern.starts_with(b"#") {
+            continue;
+        }
+
+        let negated = pattern.starts_with(b"!");
+        if negated || pattern.starts_with(b"\\#") || pattern.starts_with(b"\\!") {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with(b"/");
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if !pattern.is_empty() {
+            rules.push(Rule {
+                pattern: pattern.to_vec(),
+                negated,
+                dir_only,
+            });
+        }
+    }
+    rules
+}
+
+impl Rule {
+    /// Patterns without a slash match the name of a file or directory at any depth, and others
+    /// match its path from the directory of the file the pattern is in.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = path.as_os_str().as_bytes();
+        if !self.pattern.contains(&b'/') {
+            let name = path.rsplit(|&byte| byte == b'/').next().unwrap_or(path);
+            return wildmatch(&self.pattern, name, false);
+        }
+        wildmatch_path(&self.pattern, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use super::Ignores;
+
+    #[test]
+    fn deeper_files_and_later_patterns_win() {
+        let tempdir = tempdir().expect("tempdir");
+        let root = tempdir.path();
+        fs::create_dir_all(root.join("src/generated")).unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::write(
+            root.join(".gitignore"),
+            "# build output\n*.o\n/build/\nlogs\n!keep.o\ndocs/**/*.html  \n\\#notes\n",
+        )
+        .unwrap();
+        fs::write(root.join("src/.gitignore"), "!*.o\ngenerated/\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.tmp\nmain.o\n").unwrap();
+
+        let mut ignores = Ignores::new(root);
+        ignores.add_file(&root.join(".git/info/exclude")).unwrap();
+        ignores.read_per_directory();
+        let mut ignored = |path: &str| ignores.is_ignored(Path::new(path)).unwrap();
+
+        assert!(ignored("main.o"));
+        assert!(ignored("lib/x.o"));
+        assert!(!ignored("keep.o"));
+        assert!(!ignored("src/main.o"));
+        assert!(ignored("src/generated/main.c"));
+        assert!(ignored("build/out"));
+        assert!(!ignored("src/build/out"));
+        assert!(!ignored("build"));
+        assert!(ignored("a/logs/today"));
+        assert!(ignored("docs/api/index.html"));
+        assert!(ignored("docs/index.html"));
+        assert!(ignored("#notes"));
+        assert!(ignored("x.tmp"));
+        assert!(!ignored("README"));
+
+        let mut ignores = Ignores::new(root);
+        ignores.add_pattern("*.c");
+        assert!(ignores.is_ignored(Path::new("src/main.c")).unwrap());
+        assert!(!ignores.is_ignored(Path::new("main.o")).unwrap());
+    }
+}
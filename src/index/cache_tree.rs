@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::{self, FromStr};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::Entry;
+use crate::database::{Database, Object, ObjectId, Tree, TreeFile};
+
+/// The cache-tree (`TREE`) index extension, which remembers the tree oid of every directory
+/// whose entries have not changed since the tree was last written.
+#[derive(Debug)]
+pub struct CacheTree {
+    /// Number of index entries covered by this tree, or -1 if it has been invalidated
+    pub entry_count: i32,
+    pub oid: Option<ObjectId>,
+    pub subtrees: BTreeMap<Vec<u8>, CacheTree>,
+}
+
+impl CacheTree {
+    pub const SIGNATURE: &'static [u8] = b"TREE";
+    const MAX_DEPTH: usize = 4096;
+
+    pub fn new() -> Self {
+        Self {
+            entry_count: -1,
+            oid: None,
+            subtrees: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0
+    }
+
+    pub fn oid(&self) -> Option<&ObjectId> {
+        self.oid.as_ref().filter(|_| self.is_valid())
+    }
+
+    /// Marks the trees containing `path` as needing to be rebuilt. Sibling subtrees are left
+    /// intact so they can be reused.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.entry_count = -1;
+
+        let mut components = path.iter();
+        let mut tree = self;
+        // The last component is the entry itself, which only affects its parent
+        let mut next = components.next();
+        while let Some(component) = next {
+            next = components.next();
+            if next.is_none() {
+                break;
+            }
+            match tree.subtrees.get_mut(component.as_bytes()) {
+                Some(subtree) => {
+                    subtree.entry_count = -1;
+                    tree = subtree;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the oid of the tree made up of `entries`, which must be sorted and all lie inside
+    /// the directory `prefix` (empty or ending in '/'). Valid subtrees are reused; the rest are
+    /// rebuilt, stored in `database` and recorded. The flag is set if anything was rebuilt.
+    pub fn update(
+        &mut self,
+        entries: &[&Entry],
+        prefix: &[u8],
+        database: &Database,
+    ) -> Result<(ObjectId, bool)> {
+        if let Some(oid) = self.oid() {
+            return Ok((*oid, false));
+        }
+
+        let mut tree_entries = Vec::new();
+        let mut seen = HashSet::new();
+        let mut has_intent_to_add = false;
+
+        let mut i = 0;
+        while i < entries.len() {
+            let rel_path = entries[i]
+                .path
+                .strip_prefix(prefix)
+                .expect("Programmer error: entry outside of tree");
+            match rel_path.iter().position(|b| *b == b'/') {
+                None => {
+                    if entries[i].intent_to_add() {
+                        has_intent_to_add = true;
+                    } else {
+                        tree_entries.push(TreeFile::new(
+                            OsStr::from_bytes(rel_path),
+                            entries[i].oid,
+                            entries[i].mode,
+                        ));
+                    }
+                    i += 1;
+                }
+                Some(pos) => {
+                    let name = &rel_path[..pos];
+                    let sub_prefix = [prefix, name, b"/"].concat();
+                    let end = i + entries[i..]
+                        .iter()
+                        .take_while(|entry| entry.path.starts_with(&sub_prefix))
+                        .count();
+
+                    let subtree = self
+                        .subtrees
+                        .entry(name.to_owned())
+                        .or_insert_with(CacheTree::new);
+                    let (oid, _) = subtree.update(&entries[i..end], &sub_prefix, database)?;
+                    // A subtree holding nothing but intent-to-add entries is left out entirely
+                    if entries[i..end].iter().any(|entry| !entry.intent_to_add()) {
+                        tree_entries.push(TreeFile::new_tree(OsStr::from_bytes(name), oid));
+                    }
+                    has_intent_to_add |= !subtree.is_valid();
+                    seen.insert(name.to_owned());
+                    i = end;
+                }
+            }
+        }
+        self.subtrees.retain(|name, _| seen.contains(name));
+
+        let mut tree = Tree::build(tree_entries)?;
+        database.store(&mut tree)?;
+        let oid = *tree.oid();
+
+        self.oid = Some(oid);
+        // Trees with intent-to-add entries must be rebuilt once those entries are real
+        self.entry_count = if has_intent_to_add {
+            -1
+        } else {
+            entries.len().try_into().unwrap()
+        };
+
+        Ok((oid, true))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (rest, name, tree) = Self::parse_node(data, 0)?;
+        if !name.is_empty() {
+            bail!("Cache tree does not start at the root");
+        }
+        if !rest.is_empty() {
+            bail!("Unexpected data after cache tree");
+        }
+        Ok(tree)
+    }
+
+    fn parse_node(input: &[u8], depth: usize) -> Result<(&[u8], Vec<u8>, Self)> {
+        if depth > Self::MAX_DEPTH {
+            bail!("Cache tree is nested too deeply");
+        }
+
+        let (name, rest) = split_at_byte(input, b'\0')?;
+        let (entry_count, rest) = split_at_byte(rest, b' ')?;
+        let (subtree_count, rest) = split_at_byte(rest, b'\n')?;
+
+        let entry_count: i32 = parse_number(entry_count)?;
+        let subtree_count: usize = parse_number(subtree_count)?;
+
+        let (oid, mut rest) = if entry_count >= 0 {
+            if rest.len() < ObjectId::SIZE {
+                bail!("Cache tree is truncated");
+            }
+            let (oid, rest) = rest.split_at(ObjectId::SIZE);
+            (Some(ObjectId::from_bytes(oid)?), rest)
+        } else {
+            (None, rest)
+        };
+
+        let mut subtrees = BTreeMap::new();
+        for _ in 0..subtree_count {
+            let (remaining, name, subtree) = Self::parse_node(rest, depth + 1)?;
+            subtrees.insert(name, subtree);
+            rest = remaining;
+        }
+
+        let tree = Self {
+            entry_count,
+            oid,
+            subtrees,
+        };
+        Ok((rest, name.to_vec(), tree))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.serialize_node(b"", &mut data);
+        data
+    }
+
+    fn serialize_node(&self, name: &[u8], data: &mut Vec<u8>) {
+        data.extend_from_slice(name);
+        data.push(b'\0');
+        data.extend_from_slice(
+            format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes(),
+        );
+        if let Some(oid) = self.oid() {
+            data.extend_from_slice(oid.as_bytes());
+        }
+        for (name, subtree) in &self.subtrees {
+            subtree.serialize_node(name, data);
+        }
+    }
+}
+
+fn split_at_byte(input: &[u8], byte: u8) -> Result<(&[u8], &[u8])> {
+    let pos = input
+        .iter()
+        .position(|b| *b == byte)
+        .ok_or_else(|| anyhow!("Cache tree is truncated"))?;
+    Ok((&input[..pos], &input[pos + 1..]))
+}
+
+fn parse_number<T: FromStr>(digits: &[u8]) -> Result<T> {
+    str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| anyhow!("Bad number in cache tree: {:?}", digits))
+}
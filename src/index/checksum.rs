@@ -23,9 +23,7 @@ impl<I, D: Digest> ChecksummedFile<I, D> {
     }
 
     pub fn hash(&mut self) -> Vec<u8> {
-        let mut hash: Vec<u8> = iter::repeat(0)
-            .take((self.hasher.output_bits() + 7) / 8)
-            .collect();
+        let mut hash: Vec<u8> = iter::repeat_n(0, self.hasher.output_bits().div_ceil(8)).collect();
         self.hasher.result(&mut hash);
         hash
     }
@@ -41,7 +39,7 @@ impl<I: Write, D: Digest> ChecksummedFile<I, D> {
 impl<I: Read, D: Digest> ChecksummedFile<I, D> {
     pub fn verify_checksum(&mut self) -> Result<bool, Error> {
         let computed = self.hash();
-        let mut read: Vec<u8> = iter::repeat(0).take(computed.len()).collect();
+        let mut read: Vec<u8> = iter::repeat_n(0, computed.len()).collect();
         tracing::debug!(bytes = read.len(), "About to read from checksummed file");
         self.inner
             .read_exact(&mut read)
@@ -65,7 +63,7 @@ impl<I: Write, D: Digest> Write for ChecksummedFile<I, D> {
 impl<I: Read, D: Digest> Read for ChecksummedFile<I, D> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
         let out = self.inner.read(buf)?;
-        self.hasher.input(buf);
+        self.hasher.input(&buf[..out]);
         Ok(out)
     }
 }
@@ -123,7 +121,7 @@ mod tests {
         }
 
         let expected = b"test_contents\x57\xc5\x84\x76\x41\xe1\xac\xef\xc8\xf9\xeb\xe8\x1d\x21\x13\x0b\xfa\x0c\x75\x54";
-        let actual = std::fs::read(&fixture.get_filename()).expect("read");
+        let actual = std::fs::read(fixture.get_filename()).expect("read");
         assert_eq!(actual, expected);
     }
 
@@ -143,4 +141,15 @@ mod tests {
         assert_eq!(&data, b"test_contents");
         assert!(file.verify_checksum().expect("verify_checksum"));
     }
+
+    #[test]
+    fn short_reads_only_hash_what_was_read() {
+        let mut file = ChecksummedFile::new(&b"test_contents"[..], Sha1::new());
+        let mut buf = [0xff; 64];
+        assert_eq!(file.read(&mut buf).expect("read"), 13);
+
+        let expected =
+            b"\x57\xc5\x84\x76\x41\xe1\xac\xef\xc8\xf9\xeb\xe8\x1d\x21\x13\x0b\xfa\x0c\x75\x54";
+        assert_eq!(file.hash(), expected);
+    }
 }
@@ -1,34 +1,71 @@
+mod cache_tree;
 mod checksum;
 
 use std::cmp;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::default::Default;
-use std::fs::{File, Metadata};
-use std::io::{Read, Write};
+use std::ffi::OsStr;
+use std::fs::{self, File, Metadata};
+use std::io::{self, ErrorKind, Read, Write};
 use std::iter;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail, Result};
 use cookie_factory as cf;
 use crypto::sha1::Sha1;
 use derivative::Derivative;
 
+use self::cache_tree::*;
 use self::checksum::*;
+use crate::database::{hash_blob_stream, Database, ObjectId, TreeFile};
 use crate::lockfile::*;
 use crate::workspace::*;
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Index {
-    entries: BTreeMap<PathBuf, Entry>,
+    entries: BTreeMap<EntryKey, Entry>,
     parents: HashMap<PathBuf, HashSet<PathBuf>>,
     #[derivative(Debug = "ignore")]
     file: Option<ChecksummedFile<Lockfile, Sha1>>,
+    /// Modification time of the index file when it was loaded, as (seconds, nanoseconds)
+    file_mtime: Option<(u32, u32)>,
+    version: u32,
+    cache_tree: Option<CacheTree>,
+    /// Optional extensions that are not understood, kept when the index is rewritten only as
+    /// long as no entry has been added, removed or replaced, since they may describe the entries
+    extensions: Vec<Extension>,
     changed: bool,
 }
 
 #[derive(Debug)]
+struct Extension {
+    signature: [u8; 4],
+    data: Vec<u8>,
+}
+
+/// Entries are ordered by the bytes of their path and then by stage, as git expects.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct EntryKey {
+    path: Vec<u8>,
+    stage: u16,
+}
+
+impl EntryKey {
+    fn new(path: &Path, stage: u16) -> Self {
+        use std::os::unix::ffi::OsStrExt;
+
+        Self {
+            path: path.as_os_str().as_bytes().to_vec(),
+            stage,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub ctime: u32,
     pub ctime_nsec: u32,
@@ -40,9 +77,12 @@ pub struct Entry {
     pub uid: u32,
     pub gid: u32,
     pub size: u32,
-    pub oid: Vec<u8>,
+    pub oid: ObjectId,
     pub flags: u16,
-    pub path: String,
+    /// Only present on disk in index version 3 and later
+    pub extended_flags: u16,
+    /// The path relative to the workspace root, as raw bytes so that any file name round-trips
+    pub path: Vec<u8>,
 }
 
 type EntryData<'a> = (
@@ -58,19 +98,28 @@ type EntryData<'a> = (
     u32,
     &'a [u8],
     u16,
-    &'a [u8],
 );
 
 impl Index {
     const HEADER_SIZE: usize = 12;
+    const CHECKSUM_SIZE: usize = 20;
     const SIGNATURE: &'static [u8] = b"DIRC";
-    const VERSION: u32 = 2;
+    const DEFAULT_VERSION: u32 = 2;
+    const MIN_VERSION: u32 = 2;
+    const MAX_VERSION: u32 = 4;
+    /// Extensions describing the layout of the file itself, which become wrong once it is
+    /// rewritten
+    const DISCARDED_EXTENSIONS: &'static [&'static [u8]] = &[b"EOIE", b"IEOT"];
 
     fn new() -> Self {
         Self {
             entries: Default::default(),
             parents: Default::default(),
             file: None,
+            file_mtime: None,
+            version: Self::DEFAULT_VERSION,
+            cache_tree: None,
+            extensions: Vec::new(),
             changed: false,
         }
     }
@@ -90,25 +139,75 @@ impl Index {
         let mut index = Self::new();
 
         if let Ok(indexfile) = File::open(&path) {
+            let metadata = indexfile.metadata()?;
+            index.file_mtime = Some(Entry::mtime_of(&metadata));
+
+            let content_len = metadata
+                .len()
+                .checked_sub(Self::CHECKSUM_SIZE as u64)
+                .ok_or_else(|| anyhow!("Index file is too short"))?;
             let mut indexfile = ChecksummedFile::new(indexfile, Sha1::new());
 
-            let count = Self::read_header(&mut indexfile)?;
-            let entries = Self::read_entries(&mut indexfile, count)?;
+            let mut data = Vec::new();
+            tracing::debug!(bytes = content_len, "About to read index content");
+            (&mut indexfile).take(content_len).read_to_end(&mut data)?;
 
             if !indexfile.verify_checksum()? {
                 bail!("Checksum validation failed!");
             }
 
-            for (path, entry) in entries.into_iter() {
+            let (version, count) = Self::read_header(&data)?;
+            let (entries, extensions) =
+                Self::read_entries(&data[Self::HEADER_SIZE..], version, count)?;
+
+            index.version = version;
+            for entry in entries.into_iter() {
+                let path = entry.path().to_owned();
                 index.store_entry(&path, entry);
             }
+            index.read_extensions(extensions)?;
         }
 
         Ok(index)
     }
 
+    /// Whether the index file did not exist when this index was loaded
+    pub fn is_new(&self) -> bool {
+        self.file_mtime.is_none()
+    }
+
+    /// Selects the on-disk format used the next time the index is written.
+    pub fn set_version(&mut self, version: u32) -> Result<()> {
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&version) {
+            bail!(
+                "Index version {} is not supported (must be {} to {})",
+                version,
+                Self::MIN_VERSION,
+                Self::MAX_VERSION
+            );
+        }
+        if version != self.version {
+            self.version = version;
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn set_skip_worktree(&mut self, path: &Path, skip_worktree: bool) -> Result<()> {
+        let entry = self
+            .entries
+            .get_mut(&EntryKey::new(path, 0))
+            .ok_or_else(|| anyhow!("{} is not in the index", path.display()))?;
+        if entry.skip_worktree() != skip_worktree {
+            entry.set_extended_flag(Entry::SKIP_WORKTREE_FLAG, skip_worktree);
+            self.extensions.clear();
+            self.changed = true;
+        }
+        Ok(())
+    }
+
     #[tracing::instrument]
-    pub fn add(&mut self, file: &WorkspacePath, oid: &str) -> Result<()> {
+    pub fn add(&mut self, file: &WorkspacePath, oid: &ObjectId) -> Result<()> {
         let metadata = file.stat()?;
         let entry = Entry::new(file, oid, &metadata);
         self.discard_conflicts(file.rel_path());
@@ -118,19 +217,244 @@ impl Index {
         Ok(())
     }
 
+    /// Records `oid` at `path` in the given stage without reading the workspace. Stage 0 resolves
+    /// any conflict at `path`; stages 1 to 3 hold the base, ours and theirs sides of a conflict
+    /// and replace a resolved entry.
+    #[tracing::instrument]
+    pub fn add_entry(&mut self, path: &Path, oid: &ObjectId, mode: u32, stage: u16) -> Result<()> {
+        if stage > Entry::MAX_STAGE {
+            bail!("Invalid stage {} for {}", stage, path.display());
+        }
+        check_path(path.as_os_str().as_bytes())?;
+
+        if stage == 0 {
+            // Keep the stat data of an identical entry so its file is not hashed again
+            if let Some(entry) = self.entry(path) {
+                if entry.oid == *oid && entry.mode == mode {
+                    return Ok(());
+                }
+            }
+            self.discard_conflicts(path);
+        } else {
+            for parent in path.ancestors().skip(1) {
+                self.remove_entry(parent);
+            }
+            self.remove_children(path);
+            self.entries.remove(&EntryKey::new(path, 0));
+        }
+        self.store_entry(path, Entry::from_oid(path, oid, mode, stage));
+        self.changed = true;
+
+        Ok(())
+    }
+
+    /// Removes the entry at `path`, in every stage, along with any entries inside it.
+    #[tracing::instrument]
+    pub fn remove(&mut self, path: &Path) {
+        self.remove_entry(path);
+        self.remove_children(path);
+        self.changed = true;
+    }
+
+    /// Moves the merged entry at `from` to `to`, replacing anything already at `to`. The stat
+    /// data is kept, so the file is still checked for changes made before it was moved.
+    #[tracing::instrument]
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let mut entry = self
+            .entry(from)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} is not in the index", from.display()))?;
+        entry.set_path(to);
+
+        self.remove_entry(from);
+        self.discard_conflicts(to);
+        self.store_entry(to, entry);
+        self.changed = true;
+
+        Ok(())
+    }
+
+    /// Sets the entries whose paths are selected by `filter` to match `files`, a tree listing as
+    /// returned by `Database::load_tree_list`, leaving the rest of the index alone. The index is
+    /// not changed if any of the selected paths is invalid.
+    pub fn read_tree<F>(&mut self, files: &BTreeMap<PathBuf, TreeFile>, filter: F) -> Result<()>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        for path in files.keys().filter(|path| filter(path)) {
+            check_path(path.as_os_str().as_bytes())?;
+        }
+
+        for path in self.tracked_paths(Path::new("")) {
+            if filter(&path) && !files.contains_key(&path) {
+                self.remove(&path);
+            }
+        }
+        for (path, file) in files {
+            if filter(path) {
+                self.add_entry(path, file.oid(), file.file_mode(), 0)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The merged entry at `path`, if there is one
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.entries.get(&EntryKey::new(path, 0))
+    }
+
+    /// Every entry, including those in conflict stages, in index order
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    /// Paths of the entries at or inside `path`, in index order
+    pub fn tracked_paths(&self, path: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = self
+            .parents
+            .get(path)
+            .map(|paths| paths.iter().cloned().collect())
+            .unwrap_or_default();
+        paths.sort_by_cached_key(|path| EntryKey::new(path, 0));
+        paths
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.keys().any(|key| key.stage != 0)
+    }
+
+    /// Paths with unresolved conflicts, in index order
+    pub fn conflicted_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .entries
+            .values()
+            .filter(|entry| entry.stage() != 0)
+            .map(|entry| entry.path().to_owned())
+            .collect();
+        paths.dedup();
+        paths
+    }
+
+    /// Decides whether `file` still has the content recorded in its index entry.
+    ///
+    /// Matching stat data is trusted unless the entry is racily clean, i.e. the file was modified
+    /// no earlier than the index was written, in which case it could have changed again within
+    /// the timestamp granularity and the content is re-hashed.
+    #[tracing::instrument]
+    pub fn is_unchanged(&self, file: &WorkspacePath) -> Result<bool> {
+        let entry = match self.entries.get(&EntryKey::new(file.rel_path(), 0)) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let metadata = match fs::metadata(file.path()) {
+            Ok(metadata) => metadata,
+            Err(err) if is_missing(&err) => return Ok(false),
+            Err(err) => bail!(err),
+        };
+
+        if !entry.stat_match(&metadata) {
+            return Ok(false);
+        }
+        if entry.times_match(&metadata)
+            && !entry.is_racily_clean(self.file_mtime)
+            && !entry.is_smudged(&metadata)
+        {
+            return Ok(true);
+        }
+        Ok(hash_blob_stream(file.open()?, metadata.len())? == entry.oid)
+    }
+
+    /// Updates the stat data of every entry whose file is unchanged, as `update-index --refresh`.
+    ///
+    /// Returns the paths of entries whose files were modified or deleted.
+    #[tracing::instrument(skip(workspace))]
+    pub fn refresh(&mut self, workspace: &Workspace) -> Result<Vec<PathBuf>> {
+        let mut stale = Vec::new();
+
+        for entry in self.entries.values_mut() {
+            if entry.stage() != 0 {
+                continue;
+            }
+            let path = entry.path().to_owned();
+            let file = workspace.entry_path(&path);
+            let metadata = match fs::metadata(file.path()) {
+                Ok(metadata) => metadata,
+                Err(err) if is_missing(&err) => {
+                    stale.push(path);
+                    continue;
+                }
+                Err(err) => bail!(err),
+            };
+
+            if !entry.stat_match(&metadata) {
+                stale.push(path);
+                continue;
+            }
+            if entry.times_match(&metadata)
+                && !entry.is_racily_clean(self.file_mtime)
+                && !entry.is_smudged(&metadata)
+            {
+                continue;
+            }
+            if hash_blob_stream(file.open()?, metadata.len())? != entry.oid {
+                stale.push(path);
+                continue;
+            }
+            if !entry.times_match(&metadata) || entry.is_smudged(&metadata) {
+                entry.update_stat(&metadata);
+                self.changed = true;
+            }
+        }
+
+        Ok(stale)
+    }
+
+    /// Returns the oid of the tree for the current entries, writing any trees that are not
+    /// already recorded in the cache tree to `database`.
+    pub fn write_tree(&mut self, database: &Database) -> Result<ObjectId> {
+        if self.has_conflicts() {
+            bail!("Cannot write a tree while the index has unmerged entries");
+        }
+
+        let entries: Vec<_> = self.entries.values().collect();
+        let cache_tree = self.cache_tree.get_or_insert_with(CacheTree::new);
+        let (oid, rebuilt) = cache_tree.update(&entries, b"", database)?;
+        if rebuilt {
+            self.changed = true;
+        }
+        Ok(oid)
+    }
+
     #[tracing::instrument]
     fn store_entry(&mut self, entry_path: &Path, entry: Entry) {
+        if let Some(cache_tree) = self.cache_tree.as_mut() {
+            cache_tree.invalidate(entry_path);
+        }
+        self.extensions.clear();
         for path in entry_path.ancestors() {
             let paths_for_parent = self.parents.entry(path.to_owned()).or_default();
             paths_for_parent.insert(entry_path.to_owned());
         }
-        self.entries.insert(entry_path.to_owned(), entry);
+        self.entries
+            .insert(EntryKey::new(entry_path, entry.stage()), entry);
         tracing::debug!(?entry_path, entries = ?self.entries, parents = ?self.parents, "entry stored");
     }
 
     #[tracing::instrument]
     fn remove_entry(&mut self, entry_path: &Path) {
-        if self.entries.remove(entry_path).is_some() {
+        let mut removed = false;
+        for stage in 0..=Entry::MAX_STAGE {
+            removed |= self
+                .entries
+                .remove(&EntryKey::new(entry_path, stage))
+                .is_some();
+        }
+        if removed {
+            if let Some(cache_tree) = self.cache_tree.as_mut() {
+                cache_tree.invalidate(entry_path);
+            }
+            self.extensions.clear();
             for parent in entry_path.ancestors() {
                 if let Some(paths_for_parent) = self.parents.get_mut(parent) {
                     paths_for_parent.remove(entry_path);
@@ -152,8 +476,9 @@ impl Index {
     fn remove_children(&mut self, entry_path: &Path) {
         if let Some(children) = self.parents.get(entry_path) {
             let children = children.clone();
-            for child in children {
-                self.remove_entry(&child);
+            // An entry is recorded under its own path too, but is not its own child
+            for child in children.iter().filter(|child| *child != entry_path) {
+                self.remove_entry(child);
             }
             tracing::debug!(?entry_path, entries = ?self.entries, "children removed");
         }
@@ -168,21 +493,27 @@ impl Index {
     }
 
     fn serialize_entries<'a, W: Write + 'a>(
-        entries: &'a BTreeMap<PathBuf, Entry>,
+        entries: &'a BTreeMap<EntryKey, Entry>,
+        version: u32,
     ) -> impl cf::SerializeFn<W> + 'a {
         use cf::{bytes::be_u32, combinator::slice, multi::all, sequence::tuple};
 
+        let serialized_entries = entries.values().scan(None, move |previous, entry| {
+            let previous_path = previous.replace(entry.path.as_slice());
+            Some(entry.serialize(version, previous_path))
+        });
+
         tuple((
             slice(Self::SIGNATURE),
-            be_u32(Self::VERSION),
+            be_u32(version),
             be_u32(entries.len().try_into().unwrap()),
-            all(entries.values().map(Entry::serialize)),
+            all(serialized_entries),
         ))
     }
 
-    #[tracing::instrument(skip(indexfile))]
-    fn read_header<R: Read>(mut indexfile: R) -> Result<usize> {
-        use nom::{bytes::streaming::take, number::streaming::be_u32, sequence::tuple, IResult};
+    #[tracing::instrument(skip(data))]
+    fn read_header(data: &[u8]) -> Result<(u32, usize)> {
+        use nom::{bytes::complete::take, number::complete::be_u32, sequence::tuple, IResult};
 
         fn parse_header(input: &[u8]) -> IResult<&[u8], (&[u8], u32, u32)> {
             tuple((
@@ -192,12 +523,8 @@ impl Index {
             ))(input)
         }
 
-        let mut data = [0; Self::HEADER_SIZE];
-        tracing::debug!(bytes = data.len(), "About to read_exact from index");
-        indexfile.read_exact(&mut data)?;
-
-        let (extra, (signature, version, count)) =
-            parse_header(&data).map_err(|e| anyhow!("{:?}", e))?;
+        let (_, (signature, version, count)) =
+            parse_header(data).map_err(|_| anyhow!("Index header is truncated"))?;
         if signature != Self::SIGNATURE {
             bail!(
                 "Signature: expected '{:?}' but found '{:?}'",
@@ -205,90 +532,189 @@ impl Index {
                 signature
             );
         }
-        if version != Self::VERSION {
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&version) {
             bail!(
-                "Version: expected '{}' but found '{}'",
-                Self::VERSION,
+                "Version: expected {} to {} but found '{}'",
+                Self::MIN_VERSION,
+                Self::MAX_VERSION,
                 version
             );
         }
-        if !extra.is_empty() {
-            bail!("Programmer error: Unexpected extra data: {:?}", extra);
-        }
 
-        Ok(count as usize)
+        Ok((version, count as usize))
     }
 
-    #[tracing::instrument(skip(indexfile))]
-    fn read_entries<R: Read>(mut indexfile: R, count: usize) -> Result<BTreeMap<PathBuf, Entry>> {
+    /// Parses `count` entries from the start of `data`, returning them along with any data
+    /// following the last entry.
+    #[tracing::instrument(skip(data))]
+    fn read_entries(data: &[u8], version: u32, count: usize) -> Result<(Vec<Entry>, &[u8])> {
         use nom::{
-            bytes::complete::tag,
-            bytes::streaming::{take, take_until},
-            multi::many_m_n,
-            number::streaming::{be_u16, be_u32},
-            sequence::{terminated, tuple},
-            Err, IResult,
+            bytes::complete::{take, take_until},
+            number::complete::{be_u16, be_u32},
+            sequence::tuple,
+            IResult,
         };
 
-        fn parse_entry(input: &[u8]) -> IResult<&[u8], EntryData> {
-            terminated(
-                tuple((
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    be_u32,
-                    take(20u8),
-                    be_u16,
-                    take_until("\0"),
-                )),
-                many_m_n(1, 8, tag("\0")),
-            )(input)
-        }
-
-        let mut entries: BTreeMap<PathBuf, Entry> = BTreeMap::new();
-        let mut data = Vec::new();
+        fn parse_entry(input: &[u8]) -> IResult<&[u8], EntryData<'_>> {
+            tuple((
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                be_u32,
+                take(ObjectId::SIZE),
+                be_u16,
+            ))(input)
+        }
+
+        fn parse_extended_flags(input: &[u8]) -> IResult<&[u8], u16> {
+            be_u16(input)
+        }
+
+        fn parse_path(input: &[u8]) -> IResult<&[u8], &[u8]> {
+            take_until("\0")(input)
+        }
+
+        fn parse_padding(input: &[u8], len: usize) -> IResult<&[u8], &[u8]> {
+            take(len)(input)
+        }
+
+        // The count comes from the file, so it can't be trusted to size the allocation
+        let mut entries: Vec<Entry> =
+            Vec::with_capacity(cmp::min(count, data.len() / Entry::FIXED_SIZE));
+        let mut input = data;
+        let mut previous_path: Vec<u8> = Vec::new();
 
         for _ in 0..count {
-            data.resize(Entry::ENTRY_MIN_SIZE, 0);
-            tracing::debug!(
-                bytes = Entry::ENTRY_MIN_SIZE,
-                "About to read_exact min entry from index"
-            );
-            indexfile.read_exact(&mut data)?;
-
-            loop {
-                match parse_entry(&data) {
-                    Ok((extra, entrydata)) => {
-                        if !extra.is_empty() {
-                            bail!("Programmer error: Unexpected extra data: {:?}", extra);
-                        }
-
-                        let entry = Entry::load(entrydata);
-                        let path = PathBuf::from(&entry.path);
-                        entries.insert(path, entry);
-                        break;
-                    }
-                    Err(Err::Incomplete(_)) => {
-                        let current_len = data.len();
-                        data.resize(current_len + Entry::ENTRY_BLOCK, 0);
-                        tracing::debug!(
-                            bytes = Entry::ENTRY_BLOCK,
-                            "Incomplete, reading more from index"
-                        );
-                        indexfile.read_exact(&mut data[current_len..])?;
-                    }
-                    Err(_) => bail!("Index parse error"),
+            let entry_start = input;
+            let (rest, entrydata) =
+                parse_entry(input).map_err(|_| anyhow!("Index entry is truncated"))?;
+            let flags = entrydata.11;
+
+            let (rest, extended_flags) = if flags & Entry::EXTENDED_FLAG != 0 {
+                if version < 3 {
+                    bail!(
+                        "Extended flags are not allowed in index version {}",
+                        version
+                    );
+                }
+                parse_extended_flags(rest).map_err(|_| anyhow!("Index entry is truncated"))?
+            } else {
+                (rest, 0)
+            };
+
+            let (rest, path) = if version >= 4 {
+                let (rest, strip) = read_varint(rest)?;
+                let keep = previous_path.len().checked_sub(strip).ok_or_else(|| {
+                    anyhow!("Index entry removes more of the previous path than exists")
+                })?;
+                let (rest, suffix) =
+                    parse_path(rest).map_err(|_| anyhow!("Index entry path is not terminated"))?;
+                let mut path = previous_path[..keep].to_vec();
+                path.extend_from_slice(suffix);
+                Entry::check_path_length(flags, &path)?;
+                (&rest[1..], path)
+            } else {
+                let (rest, path) =
+                    parse_path(rest).map_err(|_| anyhow!("Index entry path is not terminated"))?;
+                Entry::check_path_length(flags, path)?;
+                let len = entry_start.len() - rest.len();
+                let padding = Entry::ENTRY_BLOCK - len % Entry::ENTRY_BLOCK;
+                let (rest, padding) = parse_padding(rest, padding)
+                    .map_err(|_| anyhow!("Index entry padding is truncated"))?;
+                if padding.iter().any(|b| *b != 0) {
+                    bail!("Index entry padding is not zeroed");
+                }
+                (rest, path.to_vec())
+            };
+
+            check_path(&path)?;
+
+            let entry = Entry::load(entrydata, extended_flags, &path)?;
+            if let Some(previous) = entries.last() {
+                if (previous.path.as_slice(), previous.stage()) >= (path.as_slice(), entry.stage())
+                {
+                    bail!(
+                        "Index entries are not sorted at '{}'",
+                        String::from_utf8_lossy(&path)
+                    );
+                }
+                if previous.path == path && previous.stage() == 0 {
+                    bail!(
+                        "Index has both merged and unmerged entries for '{}'",
+                        String::from_utf8_lossy(&path)
+                    );
                 }
             }
+            entries.push(entry);
+            previous_path = path;
+            input = rest;
         }
 
-        Ok(entries)
+        Ok((entries, input))
+    }
+
+    #[tracing::instrument(skip(data))]
+    fn read_extensions(&mut self, mut data: &[u8]) -> Result<()> {
+        use nom::{bytes::complete::take, number::complete::be_u32, IResult};
+
+        fn parse_extension(input: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
+            let (input, signature) = take(4usize)(input)?;
+            let (input, size) = be_u32(input)?;
+            let (input, data) = take(size)(input)?;
+            Ok((input, (signature, data)))
+        }
+
+        while !data.is_empty() {
+            let (rest, (signature, extension)) =
+                parse_extension(data).map_err(|_| anyhow!("Index extension is truncated"))?;
+            tracing::debug!(signature = ?String::from_utf8_lossy(signature), bytes = extension.len(), "Read index extension");
+
+            if signature == CacheTree::SIGNATURE {
+                self.cache_tree = Some(CacheTree::parse(extension)?);
+            } else if !signature[0].is_ascii_uppercase() {
+                bail!(
+                    "Index uses extension '{}', which is not supported",
+                    String::from_utf8_lossy(signature)
+                );
+            } else if !Self::DISCARDED_EXTENSIONS.contains(&signature) {
+                self.extensions.push(Extension {
+                    signature: signature.try_into().unwrap(),
+                    data: extension.to_vec(),
+                });
+            }
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    fn serialize_extensions<'a, W: Write + 'a>(
+        cache_tree: Option<Vec<u8>>,
+        extensions: &'a [Extension],
+    ) -> impl cf::SerializeFn<W> + 'a {
+        use cf::{bytes::be_u32, combinator::slice, multi::all, sequence::tuple};
+
+        let cache_tree = cache_tree.map(|data| (CacheTree::SIGNATURE, data));
+        let extensions = extensions
+            .iter()
+            .map(|extension| (&extension.signature[..], extension.data.clone()));
+
+        all(cache_tree
+            .into_iter()
+            .chain(extensions)
+            .map(|(signature, data)| {
+                tuple((
+                    slice(signature),
+                    be_u32(data.len().try_into().unwrap()),
+                    slice(data),
+                ))
+            }))
     }
 
     pub fn write_updates(mut self) -> Result<()> {
@@ -302,37 +728,58 @@ impl Index {
             .take()
             .expect("Programmer error: index was not locked for writing");
 
-        tracing::debug!(entries = ?self.entries, "About to write index");
-        cf::gen_simple(Self::serialize_entries(&self.entries), &mut file)?;
+        // Extended flags need at least version 3, so upgrade the file rather than lose them
+        let version = if self.version < 3 && self.entries.values().any(Entry::is_extended) {
+            3
+        } else {
+            self.version
+        };
+
+        // Entries modified no earlier than now would be racily clean against the file written
+        // below, and could be changed again within the same tick without it being noticed once
+        // the next write makes them look older than the index. Their size is zeroed so that
+        // their content is always checked, as git does.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        for entry in self.entries.values_mut() {
+            if entry.stage() == 0 && entry.is_racily_clean(Some((now, 0))) {
+                entry.size = 0;
+            }
+        }
+
+        tracing::debug!(entries = ?self.entries, version, "About to write index");
+        cf::gen_simple(Self::serialize_entries(&self.entries, version), &mut file)?;
+        let cache_tree = self.cache_tree.as_ref().map(CacheTree::serialize);
+        cf::gen_simple(
+            Self::serialize_extensions(cache_tree, &self.extensions),
+            &mut file,
+        )?;
 
         file.write_hash()?;
         file.into_inner().commit()?;
 
         Ok(())
     }
-
-    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
-        self.entries.values()
-    }
 }
 
 impl Entry {
     const REGULAR_MODE: u32 = 0o100644;
     const EXECUTABLE_MODE: u32 = 0o100755;
+    /// Longer paths are stored with this length in their flags and found by their terminator
     const MAX_PATH_SIZE: usize = 0xfff;
+    /// Size of the fields before the path, without extended flags
+    const FIXED_SIZE: usize = 62;
     const ENTRY_BLOCK: usize = 8;
-    const ENTRY_MIN_SIZE: usize = 64;
-
-    fn new(file: &WorkspacePath, oid: &str, metadata: &Metadata) -> Self {
-        use rustc_serialize::hex::FromHex;
+    const EXTENDED_FLAG: u16 = 0x4000;
+    const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+    const INTENT_TO_ADD_FLAG: u16 = 0x2000;
+    const STAGE_SHIFT: u16 = 12;
+    const STAGE_MASK: u16 = 0x3000;
+    const MAX_STAGE: u16 = 3;
+
+    fn new(file: &WorkspacePath, oid: &ObjectId, metadata: &Metadata) -> Self {
         use std::os::unix::fs::MetadataExt;
 
-        let mode = if metadata.mode() & 0o100 == 0 {
-            Entry::REGULAR_MODE
-        } else {
-            Entry::EXECUTABLE_MODE
-        };
-        let path = file.rel_path().to_string_lossy().into_owned();
+        let path = file.rel_path().as_os_str().as_bytes().to_vec();
         let flags = cmp::min(path.len(), Entry::MAX_PATH_SIZE) as u16;
 
         Self {
@@ -342,34 +789,109 @@ impl Entry {
             mtime_nsec: metadata.mtime_nsec().try_into().unwrap(),
             dev: metadata.dev() as u32,
             ino: metadata.ino() as u32,
-            mode,
+            mode: Self::mode_for(metadata),
             uid: metadata.uid(),
             gid: metadata.gid(),
             size: metadata.size() as u32,
-            oid: oid.from_hex().expect("oid is not a valid hex string"),
+            oid: *oid,
             flags,
+            extended_flags: 0,
             path,
         }
     }
 
-    fn load(loaded_data: EntryData) -> Self {
-        let (
-            ctime,
-            ctime_nsec,
-            mtime,
-            mtime_nsec,
-            dev,
-            ino,
+    /// An entry with no stat data. Its size of zero is not trusted and its timestamps never
+    /// match, so `Index::is_unchanged` always checks it against its file's content.
+    fn from_oid(path: &Path, oid: &ObjectId, mode: u32, stage: u16) -> Self {
+        let path = path.as_os_str().as_bytes().to_vec();
+        let flags = cmp::min(path.len(), Entry::MAX_PATH_SIZE) as u16 | stage << Entry::STAGE_SHIFT;
+
+        Self {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
             mode,
-            uid,
-            gid,
-            size,
-            oid,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            oid: *oid,
             flags,
+            extended_flags: 0,
             path,
-        ) = loaded_data;
+        }
+    }
 
-        Self {
+    /// The mode a file with this metadata is recorded with
+    pub fn mode_for(metadata: &Metadata) -> u32 {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.mode() & 0o100 == 0 {
+            Entry::REGULAR_MODE
+        } else {
+            Entry::EXECUTABLE_MODE
+        }
+    }
+
+    fn mtime_of(metadata: &Metadata) -> (u32, u32) {
+        use std::os::unix::fs::MetadataExt;
+
+        (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+    }
+
+    /// Whether the file's size and mode still match this entry. A mismatch means the file has
+    /// definitely changed. Entries created without stat data have a size of zero, which is not
+    /// trusted.
+    pub fn stat_match(&self, metadata: &Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        (self.size == 0 || self.size == metadata.size() as u32)
+            && self.mode == Self::mode_for(metadata)
+    }
+
+    /// Whether the file's timestamps still match this entry. A match means the file has not
+    /// changed, unless the entry is racily clean.
+    pub fn times_match(&self, metadata: &Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        self.ctime == metadata.ctime() as u32
+            && self.ctime_nsec == metadata.ctime_nsec() as u32
+            && (self.mtime, self.mtime_nsec) == Self::mtime_of(metadata)
+    }
+
+    /// An entry is racily clean if its file was last modified no earlier than the index was
+    /// written. Entries of an index that has never been written are always treated as racy.
+    fn is_racily_clean(&self, index_mtime: Option<(u32, u32)>) -> bool {
+        index_mtime.is_none_or(|index_mtime| (self.mtime, self.mtime_nsec) >= index_mtime)
+    }
+
+    /// Whether this entry's size was zeroed when the index was written because it was racily
+    /// clean, so its timestamps cannot be trusted until its content has been checked
+    fn is_smudged(&self, metadata: &Metadata) -> bool {
+        self.size == 0 && metadata.len() != 0
+    }
+
+    fn update_stat(&mut self, metadata: &Metadata) {
+        use std::os::unix::fs::MetadataExt;
+
+        self.ctime = metadata.ctime() as u32;
+        self.ctime_nsec = metadata.ctime_nsec() as u32;
+        self.mtime = metadata.mtime() as u32;
+        self.mtime_nsec = metadata.mtime_nsec() as u32;
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    fn load(loaded_data: EntryData, extended_flags: u16, path: &[u8]) -> Result<Self> {
+        let (ctime, ctime_nsec, mtime, mtime_nsec, dev, ino, mode, uid, gid, size, oid, flags) =
+            loaded_data;
+
+        Ok(Self {
             ctime,
             ctime_nsec,
             mtime,
@@ -380,21 +902,45 @@ impl Entry {
             uid,
             gid,
             size,
-            oid: oid.to_vec(),
+            oid: ObjectId::from_bytes(oid)?,
             flags,
-            path: String::from_utf8_lossy(path).into_owned(),
-        }
+            extended_flags,
+            path: path.to_vec(),
+        })
     }
 
-    fn serialize<'a, W: Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
+    /// Serializes the entry in the given index format version. Version 4 stores the path
+    /// relative to `previous_path` and leaves out the padding.
+    fn serialize<'a, W: Write + 'a>(
+        &'a self,
+        version: u32,
+        previous_path: Option<&[u8]>,
+    ) -> impl cf::SerializeFn<W> + 'a {
         use cf::{
             bytes::{be_u16, be_u32},
-            combinator::{slice, string},
+            combinator::{cond, slice},
             sequence::tuple,
         };
 
+        let path = self.path.as_slice();
+        let (prefix, path, block) = if version >= 4 {
+            let previous_path = previous_path.unwrap_or_default();
+            let common = previous_path
+                .iter()
+                .zip(path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            (
+                write_varint(previous_path.len() - common),
+                &path[common..],
+                1,
+            )
+        } else {
+            (Vec::new(), path, Entry::ENTRY_BLOCK)
+        };
+
         align(
-            Entry::ENTRY_BLOCK,
+            block,
             tuple((
                 be_u32(self.ctime),
                 be_u32(self.ctime_nsec),
@@ -406,13 +952,80 @@ impl Entry {
                 be_u32(self.uid),
                 be_u32(self.gid),
                 be_u32(self.size),
-                slice(&self.oid),
+                slice(self.oid.as_bytes()),
                 be_u16(self.flags),
-                string(&self.path),
+                cond(self.is_extended(), be_u16(self.extended_flags)),
+                slice(prefix),
+                slice(path),
                 slice(b"\0"),
             )),
         )
     }
+
+    pub fn path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.path))
+    }
+
+    fn set_path(&mut self, path: &Path) {
+        self.path = path.as_os_str().as_bytes().to_vec();
+        self.flags = (self.flags & !(Entry::MAX_PATH_SIZE as u16))
+            | cmp::min(self.path.len(), Entry::MAX_PATH_SIZE) as u16;
+    }
+
+    /// Checks the length recorded in an entry's flags against its path, which is found by its
+    /// terminator.
+    fn check_path_length(flags: u16, path: &[u8]) -> Result<()> {
+        let name_len = (flags & Entry::MAX_PATH_SIZE as u16) as usize;
+        let matches = if name_len < Entry::MAX_PATH_SIZE {
+            path.len() == name_len
+        } else {
+            path.len() >= Entry::MAX_PATH_SIZE
+        };
+        if !matches {
+            bail!(
+                "Index entry for '{}' has a path length of {} in its flags",
+                String::from_utf8_lossy(path),
+                name_len
+            );
+        }
+        Ok(())
+    }
+
+    /// 0 for a merged entry, otherwise 1 (base), 2 (ours) or 3 (theirs)
+    pub fn stage(&self) -> u16 {
+        (self.flags & Entry::STAGE_MASK) >> Entry::STAGE_SHIFT
+    }
+
+    fn is_extended(&self) -> bool {
+        self.flags & Entry::EXTENDED_FLAG != 0
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & Entry::SKIP_WORKTREE_FLAG != 0
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & Entry::INTENT_TO_ADD_FLAG != 0
+    }
+
+    fn set_extended_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.extended_flags |= flag;
+        } else {
+            self.extended_flags &= !flag;
+        }
+        if self.extended_flags == 0 {
+            self.flags &= !Entry::EXTENDED_FLAG;
+        } else {
+            self.flags |= Entry::EXTENDED_FLAG;
+        }
+    }
+}
+
+/// Whether a stat failed because the file is gone, including when a parent directory has been
+/// replaced by a file
+fn is_missing(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory)
 }
 
 fn align<W: Write, F>(amount: usize, f: F) -> impl cf::SerializeFn<W>
@@ -426,19 +1039,73 @@ where
         let out = f(out)?;
         let end: usize = out.position.try_into().unwrap();
         let missing = (amount - ((end - start) % amount)) % amount;
-        all(iter::repeat(b'\0').take(missing).map(be_u8))(out)
+        all(iter::repeat_n(b'\0', missing).map(be_u8))(out)
     }
 }
 
+/// Reads the variable-length integer used for path prefixes in index version 4.
+fn read_varint(input: &[u8]) -> Result<(&[u8], usize)> {
+    let mut bytes = input.iter();
+    let mut byte = *bytes
+        .next()
+        .ok_or_else(|| anyhow!("Index entry is truncated"))?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = *bytes
+            .next()
+            .ok_or_else(|| anyhow!("Index entry is truncated"))?;
+        value = value
+            .checked_add(1)
+            .and_then(|value| value.checked_mul(1 << 7))
+            .ok_or_else(|| anyhow!("Index entry path prefix is too large"))?
+            | (byte & 0x7f) as usize;
+    }
+    Ok((bytes.as_slice(), value))
+}
+
+fn write_varint(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
 
     use tempfile::tempdir;
 
-    use super::Index;
+    use super::{read_varint, write_varint, EntryKey, Extension, Index};
+    use crate::database::{hash_blob_stream, Database, ObjectId};
     use crate::workspace::Workspace;
 
+    fn test_oid() -> ObjectId {
+        "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15"
+            .parse()
+            .expect("ObjectId::parse")
+    }
+
+    fn write_file(path: &Path, data: &[u8], mtime: SystemTime) {
+        fs::write(path, data).expect("fs::write");
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(mtime))
+            .expect("File::set_modified");
+    }
+
+    fn oid_of(data: &[u8]) -> ObjectId {
+        hash_blob_stream(data, data.len() as u64).expect("hash_blob_stream")
+    }
+
     #[test]
     fn can_add_file_to_index() {
         let tempdir = tempdir().expect("tempdir");
@@ -453,11 +1120,12 @@ mod tests {
             let mut index = Index::load_for_update(tempdir.path().join("index"))
                 .expect("Index::load_for_update");
 
-            index
-                .add(&workspace_path, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-                .expect("Index::add");
+            index.add(&workspace_path, &test_oid()).expect("Index::add");
 
-            let index_paths = index.iter().map(|entry| &entry.path).collect::<Vec<_>>();
+            let index_paths = index
+                .iter()
+                .map(|entry| entry.path().as_os_str())
+                .collect::<Vec<_>>();
             assert_eq!(index_paths, ["testfile"]);
         }
     }
@@ -476,9 +1144,7 @@ mod tests {
             let mut index = Index::load_for_update(tempdir.path().join("index"))
                 .expect("Index::load_for_update while empty");
 
-            index
-                .add(&workspace_path, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-                .expect("Index::add");
+            index.add(&workspace_path, &test_oid()).expect("Index::add");
             index.write_updates().expect("Index::write_updates");
         }
 
@@ -486,7 +1152,10 @@ mod tests {
             let index = Index::load(tempdir.path().join("index"))
                 .expect("Index::load_for_update after write");
 
-            let index_paths = index.iter().map(|entry| &entry.path).collect::<Vec<_>>();
+            let index_paths = index
+                .iter()
+                .map(|entry| entry.path().as_os_str())
+                .collect::<Vec<_>>();
             assert_eq!(index_paths, ["testfile"]);
         }
     }
@@ -510,12 +1179,8 @@ mod tests {
         let mut index =
             Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
 
-        index
-            .add(&alice, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
-        index
-            .add(&bob, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&alice, &test_oid()).expect("Index::add");
+        index.add(&bob, &test_oid()).expect("Index::add");
 
         // Act
         fs::remove_file(&alice_filepath).expect("fs::remove_file");
@@ -525,12 +1190,13 @@ mod tests {
         let nested = workspace
             .path(&nested_alice_filepath)
             .expect("Workspace::path");
-        index
-            .add(&nested, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&nested, &test_oid()).expect("Index::add");
 
         // Assert
-        let index_paths = index.iter().map(|entry| &entry.path).collect::<Vec<_>>();
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
         assert_eq!(index_paths, ["alice.txt/nested.txt", "bob.txt"]);
     }
 
@@ -554,24 +1220,21 @@ mod tests {
         let mut index =
             Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
 
-        index
-            .add(&alice, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
-        index
-            .add(&bob, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&alice, &test_oid()).expect("Index::add");
+        index.add(&bob, &test_oid()).expect("Index::add");
 
         // Act
         fs::remove_dir_all(&nested_filepath).expect("fs::remove_dir_all");
         File::create(&nested_filepath).expect("File::create");
 
         let nested = workspace.path(&nested_filepath).expect("Workspace::path");
-        index
-            .add(&nested, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&nested, &test_oid()).expect("Index::add");
 
         // Assert
-        let index_paths = index.iter().map(|entry| &entry.path).collect::<Vec<_>>();
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
         assert_eq!(index_paths, ["alice.txt", "nested"]);
     }
 
@@ -600,27 +1263,666 @@ mod tests {
         let mut index =
             Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
 
-        index
-            .add(&alice, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
-        index
-            .add(&bob, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
-        index
-            .add(&claire, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&alice, &test_oid()).expect("Index::add");
+        index.add(&bob, &test_oid()).expect("Index::add");
+        index.add(&claire, &test_oid()).expect("Index::add");
 
         // Act
         fs::remove_dir_all(&nested_filepath).expect("fs::remove_dir_all");
         File::create(&nested_filepath).expect("File::create");
 
         let nested = workspace.path(&nested_filepath).expect("Workspace::path");
-        index
-            .add(&nested, "f1d2d2f924e986ac86fdf7b36c94bcdf32beec15")
-            .expect("Index::add");
+        index.add(&nested, &test_oid()).expect("Index::add");
 
         // Assert
-        let index_paths = index.iter().map(|entry| &entry.path).collect::<Vec<_>>();
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
         assert_eq!(index_paths, ["alice.txt", "nested"]);
     }
+
+    #[test]
+    fn unchanged_file_is_detected() {
+        let tempdir = tempdir().expect("tempdir");
+        let filepath = tempdir.path().join("testfile");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        write_file(&filepath, b"hello", an_hour_ago);
+
+        let workspace = Workspace::new(tempdir.path());
+        let file = workspace.path(&filepath).expect("Workspace::path");
+        {
+            let mut index = Index::load_for_update(tempdir.path().join("index"))
+                .expect("Index::load_for_update");
+            index.add(&file, &oid_of(b"hello")).expect("Index::add");
+            index.write_updates().expect("Index::write_updates");
+        }
+
+        let index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        assert!(index.is_unchanged(&file).expect("is_unchanged"));
+
+        write_file(&filepath, b"world", an_hour_ago);
+        assert!(!index.is_unchanged(&file).expect("is_unchanged"));
+
+        fs::remove_file(&filepath).expect("fs::remove_file");
+        assert!(!index.is_unchanged(&file).expect("is_unchanged"));
+    }
+
+    #[test]
+    fn entry_without_stat_data_is_checked_by_content() {
+        let tempdir = tempdir().expect("tempdir");
+        let filepath = tempdir.path().join("testfile");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        write_file(&filepath, b"world", an_hour_ago);
+
+        let workspace = Workspace::new(tempdir.path());
+        let file = workspace.path(&filepath).expect("Workspace::path");
+        {
+            let mut index = Index::load_for_update(tempdir.path().join("index"))
+                .expect("Index::load_for_update");
+            index
+                .add_entry(file.rel_path(), &oid_of(b"hello"), 0o100644, 0)
+                .expect("Index::add_entry");
+            index.write_updates().expect("Index::write_updates");
+        }
+
+        // The file is older than the index and the same size as the entry's content
+        let index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        assert!(!index.is_unchanged(&file).expect("is_unchanged"));
+
+        write_file(&filepath, b"hello", an_hour_ago);
+        assert!(index.is_unchanged(&file).expect("is_unchanged"));
+    }
+
+    #[test]
+    fn racily_clean_entry_is_rehashed() {
+        let tempdir = tempdir().expect("tempdir");
+        let filepath = tempdir.path().join("testfile");
+        let now = SystemTime::now();
+        write_file(&filepath, b"hello", now);
+
+        let workspace = Workspace::new(tempdir.path());
+        let file = workspace.path(&filepath).expect("Workspace::path");
+        let mut index =
+            Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
+        index.add(&file, &oid_of(b"hello")).expect("Index::add");
+
+        // Change the content without changing the size, then make the entry's stat data match
+        // the modified file as if both had happened within the same timestamp tick
+        write_file(&filepath, b"world", now);
+        let metadata = file.stat().expect("stat");
+        let entry = index
+            .entries
+            .get_mut(&EntryKey::new(file.rel_path(), 0))
+            .expect("entry");
+        entry.update_stat(&metadata);
+        let entry_mtime = (entry.mtime, entry.mtime_nsec);
+
+        index.file_mtime = Some(entry_mtime);
+        assert!(!index.is_unchanged(&file).expect("is_unchanged"));
+
+        index.file_mtime = Some((entry_mtime.0 + 1, 0));
+        assert!(index.is_unchanged(&file).expect("is_unchanged"));
+    }
+
+    #[test]
+    fn racily_clean_entries_are_smudged_when_written() {
+        let tempdir = tempdir().expect("tempdir");
+        let new_filepath = tempdir.path().join("new.txt");
+        let old_filepath = tempdir.path().join("old.txt");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        write_file(
+            &new_filepath,
+            b"hello",
+            SystemTime::now() + Duration::from_secs(5),
+        );
+        write_file(&old_filepath, b"hello", an_hour_ago);
+
+        let workspace = Workspace::new(tempdir.path());
+        let new_file = workspace.path(&new_filepath).expect("Workspace::path");
+        let old_file = workspace.path(&old_filepath).expect("Workspace::path");
+        let mut index =
+            Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
+        index.add(&new_file, &oid_of(b"hello")).expect("Index::add");
+        index.add(&old_file, &oid_of(b"hello")).expect("Index::add");
+        index.write_updates().expect("write_updates");
+
+        let mut index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        let size_of = |index: &Index, path| index.entry(Path::new(path)).expect("entry").size;
+        assert_eq!(size_of(&index, "new.txt"), 0);
+        assert_eq!(size_of(&index, "old.txt"), 5);
+
+        // Once the file is no longer racy, its stat data is trusted again only after a refresh
+        // has checked its content
+        index.file_mtime = Some((u32::MAX, 0));
+        assert!(index.is_unchanged(&new_file).expect("is_unchanged"));
+        write_file(
+            &new_filepath,
+            b"world",
+            SystemTime::now() + Duration::from_secs(5),
+        );
+        let entry = index
+            .entries
+            .get_mut(&EntryKey::new(new_file.rel_path(), 0))
+            .expect("entry");
+        entry.update_stat(&new_file.stat().expect("stat"));
+        entry.size = 0;
+        assert!(!index.is_unchanged(&new_file).expect("is_unchanged"));
+    }
+
+    #[test]
+    fn refresh_updates_stat_data_of_unchanged_files() {
+        let tempdir = tempdir().expect("tempdir");
+        let alice_filepath = tempdir.path().join("alice.txt");
+        let bob_filepath = tempdir.path().join("bob.txt");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        write_file(&alice_filepath, b"alice", an_hour_ago);
+        write_file(&bob_filepath, b"bob", an_hour_ago);
+
+        let workspace = Workspace::new(tempdir.path());
+        let alice = workspace.path(&alice_filepath).expect("Workspace::path");
+        let bob = workspace.path(&bob_filepath).expect("Workspace::path");
+        let mut index =
+            Index::load_for_update(tempdir.path().join("index")).expect("Index::load_for_update");
+        index.add(&alice, &oid_of(b"alice")).expect("Index::add");
+        index.add(&bob, &oid_of(b"bob")).expect("Index::add");
+
+        let later = an_hour_ago + Duration::from_secs(60);
+        write_file(&alice_filepath, b"alice", later);
+        write_file(&bob_filepath, b"BOB", later);
+
+        let stale = index.refresh(&workspace).expect("Index::refresh");
+        assert_eq!(stale, [bob.rel_path().clone()]);
+
+        let metadata = alice.stat().expect("stat");
+        let entry = &index.entries[&EntryKey::new(alice.rel_path(), 0)];
+        assert!(entry.times_match(&metadata));
+    }
+
+    fn save_and_load_with_version(version: u32) {
+        let tempdir = tempdir().expect("tempdir");
+        let paths = ["alpha.txt", "nested/alpha.txt", "nested/beta.txt", "zeta"];
+        fs::create_dir(tempdir.path().join("nested")).expect("fs::create_dir");
+        for path in &paths {
+            File::create(tempdir.path().join(path)).expect("File::create");
+        }
+
+        let workspace = Workspace::new(tempdir.path());
+        {
+            let mut index = Index::load_for_update(tempdir.path().join("index"))
+                .expect("Index::load_for_update");
+            index.set_version(version).expect("Index::set_version");
+            for path in &paths {
+                let file = workspace
+                    .path(tempdir.path().join(path))
+                    .expect("Workspace::path");
+                index.add(&file, &test_oid()).expect("Index::add");
+            }
+            index
+                .set_skip_worktree(Path::new("nested/beta.txt"), true)
+                .expect("Index::set_skip_worktree");
+            index.write_updates().expect("Index::write_updates");
+        }
+
+        let index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        assert_eq!(index.version, version.max(3));
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
+        assert_eq!(index_paths, paths);
+        let skipped = index
+            .iter()
+            .filter(|entry| entry.skip_worktree())
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
+        assert_eq!(skipped, ["nested/beta.txt"]);
+        assert!(index.iter().all(|entry| entry.oid == test_oid()));
+    }
+
+    #[test]
+    fn extended_flags_upgrade_version_2_to_3() {
+        save_and_load_with_version(2);
+    }
+
+    #[test]
+    fn can_save_and_load_version_3() {
+        save_and_load_with_version(3);
+    }
+
+    #[test]
+    fn can_save_and_load_version_4() {
+        save_and_load_with_version(4);
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let mut index = Index::new();
+        assert!(index.set_version(1).is_err());
+        assert!(index.set_version(5).is_err());
+    }
+
+    #[test]
+    fn varint_round_trips() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20, usize::MAX >> 8] {
+            let bytes = write_varint(value);
+            let (rest, decoded) = read_varint(&bytes).expect("read_varint");
+            assert!(rest.is_empty());
+            assert_eq!(decoded, value);
+        }
+        assert_eq!(write_varint(128), [0x80, 0x00]);
+        assert!(read_varint(&[0x80]).is_err());
+    }
+
+    #[test]
+    fn entries_are_sorted_by_path_bytes() {
+        let tempdir = tempdir().expect("tempdir");
+        let index = create_tree_fixture(tempdir.path());
+
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
+        assert_eq!(index_paths, ["a.txt", "a/b", "a/sub/c", "z"]);
+    }
+
+    #[test]
+    fn can_save_and_load_conflicts() {
+        let tempdir = tempdir().expect("tempdir");
+        let path = Path::new("conflict.txt");
+        {
+            let mut index = create_tree_fixture(tempdir.path());
+            for stage in [3, 1, 2] {
+                index
+                    .add_entry(path, &test_oid(), 0o100644, stage)
+                    .expect("Index::add_entry");
+            }
+            assert!(index.has_conflicts());
+            index.write_updates().expect("Index::write_updates");
+        }
+
+        let index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        let stages = index
+            .iter()
+            .filter(|entry| entry.path() == Path::new("conflict.txt"))
+            .map(|entry| entry.stage())
+            .collect::<Vec<_>>();
+        assert_eq!(stages, [1, 2, 3]);
+        assert_eq!(index.conflicted_paths(), [Path::new("conflict.txt")]);
+    }
+
+    #[test]
+    fn adding_a_file_resolves_its_conflict() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let mut index = create_tree_fixture(tempdir.path());
+        let path = Path::new("a.txt");
+
+        index
+            .add_entry(path, &test_oid(), 0o100644, 2)
+            .expect("Index::add_entry");
+        index
+            .add_entry(path, &test_oid(), 0o100644, 3)
+            .expect("Index::add_entry");
+        assert!(!index.entries.contains_key(&EntryKey::new(path, 0)));
+        assert!(index.write_tree(&database).is_err());
+
+        let workspace = Workspace::new(tempdir.path());
+        let file = workspace
+            .path(tempdir.path().join(path))
+            .expect("Workspace::path");
+        index.add(&file, &test_oid()).expect("Index::add");
+
+        assert!(!index.has_conflicts());
+        assert_eq!(
+            index
+                .write_tree(&database)
+                .expect("Index::write_tree")
+                .to_hex(),
+            "c8351edb46f953c71fbab622accf7e919e643a68"
+        );
+    }
+
+    #[test]
+    fn can_remove_entries() {
+        let tempdir = tempdir().expect("tempdir");
+        let mut index = create_tree_fixture(tempdir.path());
+        index
+            .add_entry(Path::new("z"), &test_oid(), 0o100644, 1)
+            .expect("Index::add_entry");
+
+        assert_eq!(
+            index.tracked_paths(Path::new("a")),
+            [Path::new("a/b"), Path::new("a/sub/c")]
+        );
+        index.remove(Path::new("a"));
+        index.remove(Path::new("z"));
+
+        assert!(!index.has_conflicts());
+        assert!(index.tracked_paths(Path::new("a")).is_empty());
+        assert!(index.entry(Path::new("a.txt")).is_some());
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
+        assert_eq!(index_paths, ["a.txt"]);
+    }
+
+    #[test]
+    fn can_rename_entries() {
+        let tempdir = tempdir().expect("tempdir");
+        let mut index = create_tree_fixture(tempdir.path());
+        let mtime = index.iter().next().expect("entry").mtime;
+
+        index
+            .rename(Path::new("a/sub/c"), Path::new("b/c"))
+            .expect("Index::rename");
+        index
+            .rename(Path::new("a.txt"), Path::new("z"))
+            .expect("Index::rename");
+        assert!(index.rename(Path::new("a.txt"), Path::new("y")).is_err());
+
+        let index_paths = index
+            .iter()
+            .map(|entry| entry.path().as_os_str())
+            .collect::<Vec<_>>();
+        assert_eq!(index_paths, ["a/b", "b/c", "z"]);
+        let entry = index.entry(Path::new("z")).expect("entry");
+        assert_eq!(entry.mtime, mtime);
+        assert_eq!(entry.flags, 1);
+        assert_eq!(index.entry(Path::new("b/c")).expect("entry").mode, 0o100755);
+        assert!(index.tracked_paths(Path::new("a/sub")).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_stages() {
+        let mut index = Index::new();
+        assert!(index
+            .add_entry(Path::new("file"), &test_oid(), 0o100644, 4)
+            .is_err());
+    }
+
+    fn create_tree_fixture(root: &Path) -> Index {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::create_dir_all(root.join("a/sub")).expect("fs::create_dir_all");
+        for path in &["a.txt", "a/b", "a/sub/c", "z"] {
+            File::create(root.join(path)).expect("File::create");
+        }
+        fs::set_permissions(root.join("a/sub/c"), fs::Permissions::from_mode(0o755))
+            .expect("fs::set_permissions");
+
+        let workspace = Workspace::new(root);
+        let mut index = Index::load_for_update(root.join("index")).expect("Index::load_for_update");
+        for path in &["a.txt", "a/b", "a/sub/c", "z"] {
+            let file = workspace.path(root.join(path)).expect("Workspace::path");
+            index.add(&file, &test_oid()).expect("Index::add");
+        }
+        index
+    }
+
+    #[test]
+    fn can_write_tree() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let mut index = create_tree_fixture(tempdir.path());
+
+        let oid = index.write_tree(&database).expect("Index::write_tree");
+
+        // As computed by `git write-tree` for the same entries
+        assert_eq!(oid.to_hex(), "c8351edb46f953c71fbab622accf7e919e643a68");
+        let cache_tree = index.cache_tree.as_ref().expect("cache tree");
+        assert_eq!(cache_tree.oid(), Some(&oid));
+        assert!(cache_tree.subtrees[b"a".as_slice()].subtrees[b"sub".as_slice()].is_valid());
+    }
+
+    #[test]
+    fn adding_a_file_only_invalidates_its_ancestors() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let mut index = create_tree_fixture(tempdir.path());
+        let oid = index.write_tree(&database).expect("Index::write_tree");
+
+        let workspace = Workspace::new(tempdir.path());
+        let file = workspace
+            .path(tempdir.path().join("a/b"))
+            .expect("Workspace::path");
+        index.add(&file, &test_oid()).expect("Index::add");
+
+        let cache_tree = index.cache_tree.as_ref().expect("cache tree");
+        assert!(!cache_tree.is_valid());
+        assert!(!cache_tree.subtrees[b"a".as_slice()].is_valid());
+        assert!(cache_tree.subtrees[b"a".as_slice()].subtrees[b"sub".as_slice()].is_valid());
+
+        assert_eq!(index.write_tree(&database).expect("Index::write_tree"), oid);
+    }
+
+    #[test]
+    fn can_save_and_load_extensions() {
+        let tempdir = tempdir().expect("tempdir");
+        let database = Database::new(tempdir.path().join("objects"));
+        let oid = {
+            let mut index = create_tree_fixture(tempdir.path());
+            let oid = index.write_tree(&database).expect("Index::write_tree");
+            index.extensions.push(Extension {
+                signature: *b"ABCD",
+                data: b"opaque".to_vec(),
+            });
+            index.write_updates().expect("Index::write_updates");
+            oid
+        };
+
+        let index = Index::load(tempdir.path().join("index")).expect("Index::load");
+        let cache_tree = index.cache_tree.as_ref().expect("cache tree");
+        assert_eq!(cache_tree.oid(), Some(&oid));
+        assert_eq!(cache_tree.entry_count, 4);
+        assert_eq!(
+            cache_tree.subtrees[b"a".as_slice()].subtrees[b"sub".as_slice()].entry_count,
+            1
+        );
+        assert_eq!(index.extensions.len(), 1);
+        assert_eq!(&index.extensions[0].signature, b"ABCD");
+        assert_eq!(index.extensions[0].data, b"opaque");
+    }
+
+    /// Edits the content of the index file at `index_path` and recomputes its checksum.
+    fn rewrite_index<F: FnOnce(&mut Vec<u8>)>(index_path: &Path, rewrite: F) {
+        use crypto::{digest::Digest, sha1::Sha1};
+
+        let mut data = fs::read(index_path).expect("fs::read");
+        data.truncate(data.len() - Index::CHECKSUM_SIZE);
+        rewrite(&mut data);
+        let mut hasher = Sha1::new();
+        hasher.input(&data);
+        let mut checksum = [0; Index::CHECKSUM_SIZE];
+        hasher.result(&mut checksum);
+        data.extend_from_slice(&checksum);
+        fs::write(index_path, data).expect("fs::write");
+    }
+
+    /// Writes an index holding `paths`, then replaces the bytes of `from` with `to`, which must
+    /// have the same length, and returns the error from loading it.
+    fn load_tampered_index(paths: &[&str], from: &[u8], to: &[u8]) -> String {
+        let tempdir = tempdir().expect("tempdir");
+        let index_path = tempdir.path().join("index");
+        let mut index = Index::load_for_update(index_path.clone()).expect("Index::load_for_update");
+        for path in paths {
+            index
+                .add_entry(Path::new(path), &test_oid(), 0o100644, 0)
+                .expect("Index::add_entry");
+        }
+        index.write_updates().expect("Index::write_updates");
+
+        rewrite_index(&index_path, |data| {
+            let start = data
+                .windows(from.len())
+                .position(|window| window == from)
+                .expect("path in index");
+            data[start..start + to.len()].copy_from_slice(to);
+        });
+        Index::load(index_path)
+            .expect_err("Index::load")
+            .to_string()
+    }
+
+    #[test]
+    fn unknown_extensions_are_dropped_once_entries_change() {
+        let tempdir = tempdir().expect("tempdir");
+        let index_path = tempdir.path().join("index");
+        create_tree_fixture(tempdir.path())
+            .write_updates()
+            .expect("Index::write_updates");
+        rewrite_index(&index_path, |data| {
+            data.extend_from_slice(b"ABCD\0\0\0\x06opaque")
+        });
+
+        let mut index = Index::load_for_update(index_path.clone()).expect("Index::load_for_update");
+        index.set_version(4).expect("Index::set_version");
+        index.write_updates().expect("Index::write_updates");
+        let index = Index::load(index_path.clone()).expect("Index::load");
+        assert_eq!(index.extensions.len(), 1);
+
+        let mut index = Index::load_for_update(index_path.clone()).expect("Index::load_for_update");
+        index
+            .add_entry(Path::new("new.txt"), &test_oid(), 0o100644, 0)
+            .expect("Index::add_entry");
+        index.write_updates().expect("Index::write_updates");
+        let index = Index::load(index_path.clone()).expect("Index::load");
+        assert!(index.extensions.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_required_extensions() {
+        let tempdir = tempdir().expect("tempdir");
+        let index_path = tempdir.path().join("index");
+        create_tree_fixture(tempdir.path())
+            .write_updates()
+            .expect("Index::write_updates");
+
+        rewrite_index(&index_path, |data| {
+            data.extend_from_slice(b"link\0\0\0\x01x")
+        });
+
+        let err = Index::load(index_path).expect_err("Index::load");
+        assert!(err.to_string().contains("link"));
+    }
+
+    #[test]
+    fn long_paths_round_trip() {
+        let short = "a".repeat(0xffe);
+        let limit = format!("b/{}", "b".repeat(0xffd));
+        let long = format!("c/{}/c", "c".repeat(5000));
+        for version in [2, 4] {
+            let tempdir = tempdir().expect("tempdir");
+            let index_path = tempdir.path().join("index");
+            {
+                let mut index =
+                    Index::load_for_update(index_path.clone()).expect("Index::load_for_update");
+                index.set_version(version).expect("Index::set_version");
+                for path in [&short, &limit, &long] {
+                    index
+                        .add_entry(Path::new(path), &test_oid(), 0o100644, 0)
+                        .expect("Index::add_entry");
+                }
+                index.write_updates().expect("Index::write_updates");
+            }
+
+            let index = Index::load(index_path).expect("Index::load");
+            let index_paths = index
+                .iter()
+                .map(|entry| (entry.path.as_slice(), entry.flags & 0xfff))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                index_paths,
+                [
+                    (short.as_bytes(), 0xffe),
+                    (limit.as_bytes(), 0xfff),
+                    (long.as_bytes(), 0xfff)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_workspace() {
+        for (from, to) in [
+            (b"xx/evil", b"../evil"),
+            (b"xx/evil", b"./x/evi"),
+            (b"xx/evil", b"x//evil"),
+            (b"xx/evil", b"xx/evi/"),
+            (b"xetc/pw", b"/etc/pw"),
+            (b"xgit/hk", b".git/hk"),
+            (b"a/xgit/", b"a/.GIT/"),
+        ] {
+            let path = std::str::from_utf8(from).expect("from_utf8");
+            let path = path.trim_end_matches('/').to_owned() + "/file";
+            let err = load_tampered_index(&[&path], from, to);
+            assert!(err.contains("Invalid path"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let err = load_tampered_index(&["xx/evil"], b"xx/evil", b"xx\0evil");
+        assert!(err.contains("path length"), "{}", err);
+
+        let err = load_tampered_index(&["a1", "b1"], b"a1", b"c1");
+        assert!(err.contains("not sorted"), "{}", err);
+
+        let err = load_tampered_index(&["a1", "b1"], b"a1", b"b1");
+        assert!(err.contains("not sorted"), "{}", err);
+    }
+
+    #[test]
+    fn add_entry_rejects_invalid_paths() {
+        let mut index = Index::new();
+        for path in ["../x", "/x", "a/.git/x", "a//x", "./x", ""] {
+            assert!(index
+                .add_entry(Path::new(path), &test_oid(), 0o100644, 0)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn non_utf8_names_round_trip() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        use crate::pathspec::Pathspec;
+
+        let tempdir = tempdir().expect("tempdir");
+        let root = tempdir.path();
+        let dir = OsStr::from_bytes(b"caf\xe9");
+        let path = Path::new(dir).join(OsStr::from_bytes(b"\xff.txt"));
+        fs::create_dir(root.join(dir)).expect("fs::create_dir");
+        File::create(root.join(&path)).expect("File::create");
+
+        let workspace = Workspace::new(root);
+        let no_paths: &[&str] = &[];
+        let pathspec = Pathspec::parse(no_paths, &workspace).expect("Pathspec::parse");
+        let files = workspace
+            .list_matching_files(&pathspec)
+            .expect("Workspace::list_matching_files");
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].rel_path(), &path);
+
+        let database = Database::new(root.join("objects"));
+        let tree_oid = {
+            let mut index =
+                Index::load_for_update(root.join("index")).expect("Index::load_for_update");
+            index.add(&files[0], &test_oid()).expect("Index::add");
+            let oid = index.write_tree(&database).expect("Index::write_tree");
+            index.write_updates().expect("Index::write_updates");
+            oid
+        };
+
+        let index = Index::load(root.join("index")).expect("Index::load");
+        let entry = index.entry(&path).expect("entry");
+        assert_eq!(entry.path, b"caf\xe9/\xff.txt");
+        let cache_tree = index.cache_tree.as_ref().expect("cache tree");
+        assert!(cache_tree.subtrees[b"caf\xe9".as_slice()].is_valid());
+
+        let tree_files = database.load_tree_list(&tree_oid).expect("load_tree_list");
+        assert_eq!(tree_files.keys().collect::<Vec<_>>(), [&path]);
+    }
 }
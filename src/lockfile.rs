@@ -13,8 +13,12 @@ pub struct Lockfile {
 }
 
 impl Lockfile {
+    /// Creates `<path>.lock`, or returns `None` if another process already holds it.
     pub fn hold_for_update(path: PathBuf) -> Result<Option<Self>> {
-        let lock_path = path.with_extension("lock");
+        // Appended rather than replacing an extension, so `v1.0` and `v1.1` get different locks
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
 
         let file = OpenOptions::new()
             .read(true)
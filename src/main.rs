@@ -1,9 +1,15 @@
+mod attributes;
 mod cmd;
+mod config;
 mod database;
+mod diff;
+mod ignores;
 mod index;
 mod lockfile;
+mod pathspec;
 mod refs;
 mod repository;
+mod revision;
 mod telemetry;
 mod workspace;
 
@@ -0,0 +1,442 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::workspace::Workspace;
+
+/// A set of patterns selecting paths in the workspace or index, as given on the command line.
+///
+/// Each item is a path relative to the current directory, which matches itself and everything
+/// inside it, or a glob in which `*` also matches `/`. Items can be prefixed with magic, either
+/// in short form (`:!`, `:^`, `:/`) or long form (`:(exclude,icase)`). A path matches if it
+/// matches any item that is not an exclusion and no exclusion.
+#[derive(Debug)]
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+#[derive(Debug)]
+struct Item {
+    original: String,
+    /// The pattern relative to the workspace root, as raw bytes like the paths it matches
+    pattern: Vec<u8>,
+    exclude: bool,
+    icase: bool,
+    literal: bool,
+}
+
+/// How an item matched a path, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    Exact,
+    Wildcard,
+    /// The path is inside a directory named by the item
+    Recursive,
+}
+
+impl Pathspec {
+    pub fn parse<S: AsRef<Path>>(specs: &[S], workspace: &Workspace) -> Result<Self> {
+        let items = specs
+            .iter()
+            .map(|spec| Item::parse(spec.as_ref().as_os_str(), workspace))
+            .collect::<Result<_>>()?;
+        Ok(Self { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Whether `path`, relative to the workspace root, is selected. An empty pathspec, or one
+    /// with only exclusions, selects everything else.
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.as_os_str().as_bytes();
+        let mut included = self.items.iter().all(|item| item.exclude);
+        for item in &self.items {
+            if item.match_kind(path).is_some() {
+                if item.exclude {
+                    return false;
+                }
+                included = true;
+            }
+        }
+        included
+    }
+
+    /// For each item that is not an exclusion, its text as given and the best way it matched
+    /// any of `paths`, so that callers can report items that matched nothing.
+    pub fn item_matches<'a, I>(&self, paths: I) -> Vec<(&str, Option<MatchKind>)>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        let mut seen: Vec<Option<MatchKind>> = vec![None; self.items.len()];
+        for path in paths {
+            let path = path.as_os_str().as_bytes();
+            for (item, seen) in self.items.iter().zip(seen.iter_mut()) {
+                if let Some(kind) = item.match_kind(path) {
+                    *seen = Some(seen.map_or(kind, |seen| seen.min(kind)));
+                }
+            }
+        }
+        self.items
+            .iter()
+            .zip(seen)
+            .filter(|(item, _)| !item.exclude)
+            .map(|(item, seen)| (item.original.as_str(), seen))
+            .collect()
+    }
+
+    /// Directories, relative to the workspace root, that contain every path the pathspec can
+    /// match, so that only those need to be searched.
+    pub fn leading_dirs(&self) -> Vec<PathBuf> {
+        let positive: Vec<_> = self.items.iter().filter(|item| !item.exclude).collect();
+        if positive.is_empty() || positive.iter().any(|item| item.icase) {
+            return vec![PathBuf::new()];
+        }
+
+        let mut dirs: Vec<PathBuf> = positive
+            .iter()
+            .map(|item| {
+                let end = item.wildcard_position().unwrap_or(item.pattern.len());
+                let literal = &item.pattern[..end];
+                let dir = if end == item.pattern.len() {
+                    literal
+                } else {
+                    &literal[..literal.iter().rposition(|b| *b == b'/').unwrap_or(0)]
+                };
+                PathBuf::from(OsStr::from_bytes(dir))
+            })
+            .collect();
+        dirs.sort();
+        // Searching a directory also searches everything inside it
+        let mut result: Vec<PathBuf> = Vec::new();
+        for dir in dirs {
+            if !result.iter().any(|outer| dir.starts_with(outer)) {
+                result.push(dir);
+            }
+        }
+        result
+    }
+}
+
+impl Item {
+    const MAGIC: &'static [&'static str] = &["exclude", "top", "icase", "literal"];
+
+    fn parse(spec: &OsStr, workspace: &Workspace) -> Result<Self> {
+        let original = spec.to_string_lossy().into_owned();
+        let spec = spec.as_bytes();
+        let mut magic = Vec::new();
+        let mut pattern = spec;
+
+        if let Some(rest) = spec.strip_prefix(b":(") {
+            let end = match rest.iter().position(|b| *b == b')') {
+                Some(end) => end,
+                None => bail!("Missing ')' at the end of pathspec magic in '{}'", original),
+            };
+            for word in rest[..end]
+                .split(|b| *b == b',')
+                .filter(|word| !word.is_empty())
+            {
+                match Self::MAGIC.iter().find(|name| name.as_bytes() == word) {
+                    Some(name) => magic.push(*name),
+                    None => bail!(
+                        "Invalid pathspec magic '{}' in '{}'",
+                        String::from_utf8_lossy(word),
+                        original
+                    ),
+                }
+            }
+            pattern = &rest[end + 1..];
+        } else if let Some(rest) = spec.strip_prefix(b":") {
+            pattern = b"";
+            for (pos, c) in rest.iter().enumerate() {
+                let name = match c {
+                    b'!' | b'^' => "exclude",
+                    b'/' => "top",
+                    b':' => {
+                        pattern = &rest[pos + 1..];
+                        break;
+                    }
+                    _ => {
+                        pattern = &rest[pos..];
+                        break;
+                    }
+                };
+                magic.push(name);
+            }
+        }
+
+        let has = |name| magic.contains(&name);
+
+        let pattern = OsStr::from_bytes(pattern);
+        let relative = if has("top") {
+            workspace.rel_path_of(workspace.root().join(pattern))?
+        } else {
+            workspace.rel_path_of(pattern)?
+        };
+
+        Ok(Self {
+            original,
+            pattern: relative.into_os_string().into_vec(),
+            exclude: has("exclude"),
+            icase: has("icase"),
+            literal: has("literal"),
+        })
+    }
+
+    fn wildcard_position(&self) -> Option<usize> {
+        if self.literal {
+            None
+        } else {
+            self.pattern
+                .iter()
+                .position(|b| matches!(b, b'*' | b'?' | b'[' | b'\\'))
+        }
+    }
+
+    fn match_kind(&self, path: &[u8]) -> Option<MatchKind> {
+        let pattern = self.pattern.as_slice();
+        let eq = |a: &[u8], b: &[u8]| {
+            if self.icase {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        };
+
+        if pattern.is_empty() {
+            return Some(MatchKind::Recursive);
+        }
+        if eq(pattern, path) {
+            return Some(MatchKind::Exact);
+        }
+        if self.wildcard_position().is_some() && wildmatch(pattern, path, self.icase) {
+            return Some(MatchKind::Wildcard);
+        }
+        if path.len() > pattern.len()
+            && path[pattern.len()] == b'/'
+            && eq(pattern, &path[..pattern.len()])
+        {
+            return Some(MatchKind::Recursive);
+        }
+        None
+    }
+}
+
+/// Matches `text` against a shell glob in which `*` also matches `/`, supporting `?`, bracket
+/// expressions such as `[a-z]` or `[!0-9]`, and backslash escapes.
+pub fn wildmatch(pattern: &[u8], text: &[u8], icase: bool) -> bool {
+    let fold = |b: u8| if icase { b.to_ascii_lowercase() } else { b };
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the most recent `*` if the rest of the pattern fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+                backtrack = Some((p, t));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match match_bracket(&pattern[p..], fold(text[t]), icase) {
+                Some((true, len)) => Some(p + len),
+                Some((false, _)) => None,
+                // An unterminated bracket is matched literally
+                None if text[t] == b'[' => Some(p + 1),
+                None => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                if fold(pattern[p + 1]) == fold(text[t]) {
+                    Some(p + 2)
+                } else {
+                    None
+                }
+            }
+            Some(&c) if fold(c) == fold(text[t]) => Some(p + 1),
+            _ => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|b| *b == b'*')
+}
+
+/// Matches a path against a pattern relative to the same directory, as in `.gitignore` and
+/// `.gitattributes` files: each component of the pattern matches one component of the path,
+/// except `**`, which matches any number of them. A leading `/` is ignored.
+pub fn wildmatch_path(pattern: &[u8], path: &[u8]) -> bool {
+    let pattern = pattern.strip_prefix(b"/").unwrap_or(pattern);
+    let pattern: Vec<_> = pattern.split(|&byte| byte == b'/').collect();
+    let path: Vec<_> = path.split(|&byte| byte == b'/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&[u8]], path: &[&[u8]]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(component), _) if *component == b"**" => {
+            (0..=path.len()).any(|skip| match_components(&pattern[1..], &path[skip..]))
+        }
+        (Some(component), Some(name)) => {
+            wildmatch(component, name, false) && match_components(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+/// Matches one character against the bracket expression at the start of `pattern`, returning
+/// whether it matched and the length of the expression, or `None` if it is not terminated.
+fn match_bracket(pattern: &[u8], c: u8, icase: bool) -> Option<(bool, usize)> {
+    let fold = |b: u8| if icase { b.to_ascii_lowercase() } else { b };
+
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == b'\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        i += 1;
+
+        let mut high = low;
+        if pattern.get(i) == Some(&b'-') && pattern.get(i + 1).is_some_and(|b| *b != b']') {
+            high = pattern[i + 1];
+            if high == b'\\' {
+                high = *pattern.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+
+        if (fold(low)..=fold(high)).contains(&c) || (low..=high).contains(&c) {
+            matched = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    use super::{wildmatch, MatchKind, Pathspec};
+    use crate::workspace::Workspace;
+
+    fn pathspec(specs: &[&str]) -> Pathspec {
+        let workspace = Workspace::new(env::current_dir().expect("current_dir"));
+        Pathspec::parse(specs, &workspace).expect("Pathspec::parse")
+    }
+
+    fn matches(specs: &[&str], path: &str) -> bool {
+        pathspec(specs).matches(Path::new(path))
+    }
+
+    #[test]
+    fn globs_match_like_fnmatch() {
+        assert!(wildmatch(b"*.rs", b"src/main.rs", false));
+        assert!(wildmatch(b"src/*", b"src/a/b", false));
+        assert!(wildmatch(b"a?c", b"abc", false));
+        assert!(!wildmatch(b"a?c", b"ac", false));
+        assert!(wildmatch(b"[a-c]x", b"bx", false));
+        assert!(!wildmatch(b"[!a-c]x", b"bx", false));
+        assert!(wildmatch(b"[]]", b"]", false));
+        assert!(wildmatch(b"\\*", b"*", false));
+        assert!(!wildmatch(b"\\*", b"x", false));
+        assert!(wildmatch(b"*a*b*", b"xxaxxbxx", false));
+        assert!(!wildmatch(b"*a*b", b"xxaxxbxx", false));
+        assert!(wildmatch(b"[A-Z]*.TXT", b"readme.txt", true));
+        assert!(wildmatch(b"[", b"[", false));
+    }
+
+    #[test]
+    fn items_match_paths_and_directories() {
+        assert!(matches(&["src"], "src"));
+        assert!(matches(&["src"], "src/main.rs"));
+        assert!(!matches(&["src"], "srcs/main.rs"));
+        assert!(matches(&["./src/../src/"], "src/main.rs"));
+        assert!(matches(&["*.rs"], "src/main.rs"));
+        assert!(!matches(&["*.rs"], "Cargo.toml"));
+        assert!(matches(&[], "anything"));
+    }
+
+    #[test]
+    fn magic_changes_matching() {
+        assert!(!matches(&["src", ":!src/main.rs"], "src/main.rs"));
+        assert!(matches(&["src", ":(exclude)*.toml"], "src/lib.rs"));
+        assert!(matches(&[":^*.toml"], "src/lib.rs"));
+        assert!(!matches(&[":^*.toml"], "Cargo.toml"));
+        assert!(matches(&[":(icase)SRC/*.RS"], "src/main.rs"));
+        assert!(!matches(&["SRC"], "src/main.rs"));
+        assert!(matches(&[":(literal)a*"], "a*"));
+        assert!(!matches(&[":(literal)a*"], "ab"));
+        assert!(matches(&[":/src"], "src/main.rs"));
+        assert!(matches(&[":(top,icase)Src"], "src/main.rs"));
+        assert!(matches(&[":!:x"], "y"));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let workspace = Workspace::new(env::current_dir().expect("current_dir"));
+        assert!(Pathspec::parse(&[":(unknown)x"], &workspace).is_err());
+        assert!(Pathspec::parse(&[":(top"], &workspace).is_err());
+    }
+
+    #[test]
+    fn reports_how_items_matched() {
+        let spec = pathspec(&["a", "b/c", "*.rs", "missing", ":!x"]);
+        let paths = ["a/file", "b/c", "d/e.rs"];
+        let seen = spec.item_matches(paths.iter().map(Path::new));
+        assert_eq!(
+            seen,
+            [
+                ("a", Some(MatchKind::Recursive)),
+                ("b/c", Some(MatchKind::Exact)),
+                ("*.rs", Some(MatchKind::Wildcard)),
+                ("missing", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_leading_directories() {
+        let dirs = pathspec(&["src/cmd/*.rs", "src/index", "docs/a?c", ":!src"]).leading_dirs();
+        assert_eq!(
+            dirs,
+            [
+                PathBuf::from("docs"),
+                PathBuf::from("src/cmd"),
+                PathBuf::from("src/index")
+            ]
+        );
+        assert_eq!(pathspec(&["*.rs", "src"]).leading_dirs(), [PathBuf::new()]);
+    }
+}
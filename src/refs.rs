@@ -1,9 +1,10 @@
 use std::fs;
 use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
+use crate::database::ObjectId;
 use crate::lockfile::*;
 
 pub struct Refs {
@@ -11,34 +12,523 @@ pub struct Refs {
 }
 
 impl Refs {
+    /// Symbolic refs followed before giving up, in case they form a loop
+    const MAX_SYMREF_DEPTH: usize = 5;
+
     pub fn new(path: PathBuf) -> Self {
         Self { root: path }
     }
 
-    pub fn update_head(&self, oid: &str) -> Result<()> {
-        if let Some(mut head) = Lockfile::hold_for_update(self.head_path())? {
-            head.write_all(oid.as_bytes())?;
-            head.write_all(b"\n")?;
-            head.commit()?;
-        } else {
-            bail!(
-                "Could not acquire lock on file: {}",
-                self.head_path().display()
-            );
+    /// Moves `HEAD`, or the branch it points to if it is symbolic.
+    pub fn update_head(&self, oid: &ObjectId) -> Result<()> {
+        self.update_ref("HEAD", oid)
+    }
+
+    pub fn read_head(&self) -> Result<Option<ObjectId>> {
+        self.read_exact("HEAD")
+    }
+
+    /// Points the ref `name`, such as `ORIG_HEAD` or `refs/heads/main`, at `oid`.
+    pub fn update_ref(&self, name: &str, oid: &ObjectId) -> Result<()> {
+        self.compare_and_swap(name, None, Some(oid))
+    }
+
+    /// Looks up `name` in the places git searches for a ref, so `main` finds `refs/heads/main`.
+    pub fn read_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        const PREFIXES: &[&str] = &["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"];
+
+        for prefix in PREFIXES {
+            let full_name = format!("{}{}", prefix, name);
+            if self.root.join(&full_name).is_file() {
+                return self.read_exact(&full_name);
+            }
+        }
+        Ok(None)
+    }
+
+    /// The object the ref with the full name `name` points to, following symbolic refs.
+    pub fn read_exact(&self, name: &str) -> Result<Option<ObjectId>> {
+        let name = self.deref(name)?;
+        Self::read_ref_file(&self.root.join(name))
+    }
+
+    /// Points `name` at `new`, or deletes it if `new` is `None`, following symbolic refs.
+    ///
+    /// If `old` is given the ref must currently have that value, where `Some(None)` means that
+    /// it must not exist. The ref stays locked between checking and writing it, so an update
+    /// made by another process in between cannot be lost.
+    pub fn compare_and_swap(
+        &self,
+        name: &str,
+        old: Option<Option<&ObjectId>>,
+        new: Option<&ObjectId>,
+    ) -> Result<()> {
+        let mut transaction = self.transaction();
+        transaction.update(name, old.map(|old| old.copied()), new.copied());
+        transaction.commit()
+    }
+
+    /// Starts a set of changes to refs that are all checked before any of them is made.
+    pub fn transaction(&self) -> RefTransaction<'_> {
+        RefTransaction {
+            refs: self,
+            updates: Vec::new(),
+        }
+    }
+
+    /// The ref that `name` points to, if it is a symbolic ref such as `HEAD` on a branch
+    pub fn read_symbolic_ref(&self, name: &str) -> Result<Option<String>> {
+        let path = self.root.join(name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).with_context(|| format!("Reading {}", path.display()))?;
+        let target = String::from_utf8_lossy(&data)
+            .strip_prefix("ref: ")
+            .map(|target| target.trim().to_owned());
+        Ok(target)
+    }
+
+    /// Makes `name` a symbolic ref pointing to the ref `target`.
+    pub fn set_symbolic_ref(&self, name: &str, target: &str) -> Result<()> {
+        check_safe_ref_name(name)?;
+        check_safe_ref_name(target)?;
+        let path = self.root.join(name);
+        let mut lock = Self::lock(&path)?;
+        writeln!(lock, "ref: {}", target)?;
+        lock.commit()
+    }
+
+    /// Every ref under `refs/` that points to an object, sorted by name
+    pub fn list_refs(&self) -> Result<Vec<(String, ObjectId)>> {
+        let mut names = Vec::new();
+        self.list_ref_names("refs", &mut names)?;
+        names.sort();
+
+        let mut refs = Vec::new();
+        for name in names {
+            if let Some(oid) = self.read_exact(&name)? {
+                refs.push((name, oid));
+            }
+        }
+        Ok(refs)
+    }
+
+    fn list_ref_names(&self, dir: &str, names: &mut Vec<String>) -> Result<()> {
+        let entries = match fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => bail!(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.list_ref_names(&name, names)?;
+            } else if !name.ends_with(".lock") {
+                names.push(name);
+            }
         }
         Ok(())
     }
 
-    pub fn read_head(&self) -> Result<Option<String>> {
-        let result = fs::read(&self.head_path());
-        match result {
-            Ok(data) => Ok(Some(String::from_utf8_lossy(&data).trim().to_owned())),
+    /// Follows symbolic refs from `name` to the ref that holds an object id, which need not
+    /// exist yet.
+    fn deref(&self, name: &str) -> Result<String> {
+        let mut name = name.to_owned();
+        for _ in 0..Self::MAX_SYMREF_DEPTH {
+            match self.read_symbolic_ref(&name)? {
+                Some(target) if !is_safe_ref_name(&target) => {
+                    bail!("Symbolic ref {} points to an invalid ref: {}", name, target)
+                }
+                Some(target) => name = target,
+                None => return Ok(name),
+            }
+        }
+        bail!("Too many levels of symbolic refs at {}", name)
+    }
+
+    /// Makes a checked update, given the lock holding its new value.
+    fn apply_update(&self, update: &RefUpdate, lock: Lockfile) -> Result<()> {
+        match update.new {
+            Some(Some(_)) => lock.commit(),
+            Some(None) => match fs::remove_file(self.root.join(&update.name)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => bail!(err),
+                _ => Ok(()),
+            },
+            // Dropping the lock releases the ref unchanged
+            None => Ok(()),
+        }
+    }
+
+    /// Puts back the value a ref had before a failed transaction changed it.
+    fn restore(&self, name: &str, value: Option<ObjectId>) -> Result<()> {
+        let path = self.root.join(name);
+        let mut lock = Self::lock(&path)?;
+        match value {
+            Some(oid) => {
+                writeln!(lock, "{}", oid)?;
+                lock.commit()
+            }
+            None => match fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => bail!(err),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn lock(path: &Path) -> Result<Lockfile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        match Lockfile::hold_for_update(path.to_owned())? {
+            Some(lock) => Ok(lock),
+            None => bail!("Could not acquire lock on file: {}", path.display()),
+        }
+    }
+
+    fn read_ref_file(path: &Path) -> Result<Option<ObjectId>> {
+        match fs::read(path) {
+            Ok(data) => {
+                let oid = String::from_utf8_lossy(&data)
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Corrupt ref in {}", path.display()))?;
+                Ok(Some(oid))
+            }
             Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
             Err(err) => bail!(err),
         }
     }
+}
+
+/// Changes to several refs, all checked before any of them is made.
+///
+/// Committing locks every ref before checking any of their old values, and writes all of the
+/// new values to the lockfiles before moving the first into place. If anything fails up to that
+/// point, every lockfile is removed again and no ref has changed. If moving a lockfile into
+/// place or deleting a ref fails, the refs already changed are restored to their old values.
+///
+/// This is not atomic: each ref is a separate file, so another process can see some refs
+/// changed and others not while the new values are moved into place, and a crash at that point,
+/// or a failure to restore a ref, leaves the update partly applied.
+pub struct RefTransaction<'a> {
+    refs: &'a Refs,
+    updates: Vec<RefUpdate>,
+}
+
+struct RefUpdate {
+    name: String,
+    /// The value the ref must have, where `Some(None)` means that it must not exist
+    old: Option<Option<ObjectId>>,
+    /// The value to give it, where `Some(None)` deletes it and `None` leaves it alone
+    new: Option<Option<ObjectId>>,
+}
+
+impl RefTransaction<'_> {
+    /// Points `name` at `new`, or deletes it if `new` is `None`, checking that it has the value
+    /// `old` first if one is given.
+    pub fn update(&mut self, name: &str, old: Option<Option<ObjectId>>, new: Option<ObjectId>) {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            old,
+            new: Some(new),
+        });
+    }
+
+    /// Checks that `name` has the value `old`, where `None` means that it must not exist,
+    /// without changing it.
+    pub fn verify(&mut self, name: &str, old: Option<ObjectId>) {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            old: Some(old),
+            new: None,
+        });
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.commit_with(Refs::apply_update)
+    }
+
+    /// Commits the transaction, making each checked update with `apply`.
+    fn commit_with<F>(self, apply: F) -> Result<()>
+    where
+        F: Fn(&Refs, &RefUpdate, Lockfile) -> Result<()>,
+    {
+        let mut updates = Vec::new();
+        for update in self.updates {
+            check_safe_ref_name(&update.name)?;
+            let name = self.refs.deref(&update.name)?;
+            updates.push(RefUpdate { name, ..update });
+        }
+        // Locking in name order means that two transactions cannot each hold a lock the other
+        // needs for long
+        updates.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(pair) = updates.windows(2).find(|pair| pair[0].name == pair[1].name) {
+            bail!("multiple updates for ref '{}' not allowed", pair[0].name);
+        }
+
+        let mut locks = Vec::new();
+        for update in &updates {
+            locks.push(Refs::lock(&self.refs.root.join(&update.name))?);
+        }
+        let mut originals = Vec::new();
+        for (update, lock) in updates.iter().zip(&mut locks) {
+            let current = Refs::read_ref_file(&self.refs.root.join(&update.name))?;
+            update.check(current)?;
+            if let Some(Some(oid)) = update.new {
+                lock.write_all(oid.to_hex().as_bytes())?;
+                lock.write_all(b"\n")?;
+            }
+            originals.push(current);
+        }
+
+        for (n, (update, lock)) in updates.iter().zip(locks).enumerate() {
+            if let Err(err) = apply(self.refs, update, lock) {
+                // The refs changed so far get their old values back, and the locks on the rest
+                // are released as they are dropped
+                for (update, original) in updates[..n].iter().zip(&originals).rev() {
+                    if update.new.is_some() {
+                        if let Err(err) = self.refs.restore(&update.name, *original) {
+                            eprintln!("error: could not restore ref '{}': {}", update.name, err);
+                        }
+                    }
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RefUpdate {
+    fn check(&self, current: Option<ObjectId>) -> Result<()> {
+        let old = match self.old {
+            Some(old) if old != current => old,
+            _ => return Ok(()),
+        };
+        match (current, old) {
+            (Some(current), Some(old)) => bail!(
+                "cannot lock ref '{}': is at {} but expected {}",
+                self.name,
+                current,
+                old
+            ),
+            (Some(_), None) => bail!("cannot lock ref '{}': reference already exists", self.name),
+            (None, _) => bail!(
+                "cannot lock ref '{}': unable to resolve reference",
+                self.name
+            ),
+        }
+    }
+}
+
+/// Checks a ref name against the rules of `git check-ref-format`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    const FORBIDDEN: &[char] = &[' ', '~', '^', ':', '?', '*', '[', '\\'];
+
+    !name.is_empty()
+        && !name.starts_with(['.', '/'])
+        && !name.ends_with(['.', '/'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("/.")
+        && !name.contains("@{")
+        && !name.contains(|c: char| c.is_ascii_control() || FORBIDDEN.contains(&c))
+}
+
+/// Whether `name` can be written to as a ref: a valid name under `refs/`, or a pseudoref such
+/// as `HEAD` or `ORIG_HEAD` made of capitals and underscores, as in git's `refname_is_safe`.
+/// Anything else, such as `config` or `index`, would name another file in the repository.
+pub fn is_safe_ref_name(name: &str) -> bool {
+    if name.starts_with("refs/") {
+        is_valid_ref_name(name)
+    } else {
+        !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+    }
+}
+
+fn check_safe_ref_name(name: &str) -> Result<()> {
+    if !is_safe_ref_name(name) {
+        bail!("'{}' is not a valid ref name", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::bail;
+    use tempfile::tempdir;
+
+    use super::{is_safe_ref_name, Refs};
+    use crate::database::ObjectId;
+
+    #[test]
+    fn follows_symbolic_refs_and_checks_old_values() {
+        let tempdir = tempdir().expect("tempdir");
+        let refs = Refs::new(tempdir.path().to_owned());
+        let one: ObjectId = "1111111111111111111111111111111111111111".parse().unwrap();
+        let two: ObjectId = "2222222222222222222222222222222222222222".parse().unwrap();
+
+        refs.set_symbolic_ref("HEAD", "refs/heads/main").unwrap();
+        assert_eq!(refs.read_head().unwrap(), None);
+        refs.update_head(&one).unwrap();
+        assert_eq!(refs.read_exact("refs/heads/main").unwrap(), Some(one));
+        assert_eq!(
+            refs.read_symbolic_ref("HEAD").unwrap().as_deref(),
+            Some("refs/heads/main")
+        );
+
+        // Names differing only after a dot must not share a lockfile
+        fs::create_dir_all(tempdir.path().join("refs/tags")).unwrap();
+        let _held = fs::File::create(tempdir.path().join("refs/tags/v1.0.lock")).unwrap();
+        refs.update_ref("refs/tags/v1.1", &one).unwrap();
+        assert!(refs.update_ref("refs/tags/v1.0", &one).is_err());
+
+        assert!(refs
+            .compare_and_swap("HEAD", Some(Some(&two)), Some(&two))
+            .is_err());
+        assert!(refs
+            .compare_and_swap("refs/heads/main", Some(None), Some(&two))
+            .is_err());
+        refs.compare_and_swap("HEAD", Some(Some(&one)), Some(&two))
+            .unwrap();
+        assert_eq!(refs.read_ref("main").unwrap(), Some(two));
+
+        refs.compare_and_swap("refs/heads/main", Some(Some(&two)), None)
+            .unwrap();
+        assert_eq!(refs.read_head().unwrap(), None);
+        let names: Vec<_> = refs
+            .list_refs()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["refs/tags/v1.1"]);
+    }
+
+    #[test]
+    fn transactions_change_every_ref_or_none() {
+        let tempdir = tempdir().expect("tempdir");
+        let refs = Refs::new(tempdir.path().to_owned());
+        let one: ObjectId = "1111111111111111111111111111111111111111".parse().unwrap();
+        let two: ObjectId = "2222222222222222222222222222222222222222".parse().unwrap();
+        refs.update_ref("refs/heads/a", &one).unwrap();
+        refs.update_ref("refs/heads/b", &one).unwrap();
+
+        let mut transaction = refs.transaction();
+        transaction.update("refs/heads/a", Some(Some(one)), Some(two));
+        transaction.update("refs/heads/new", None, Some(two));
+        transaction.update("refs/heads/b", None, None);
+        transaction.verify("refs/heads/c", Some(one));
+        assert!(transaction.commit().is_err());
+        let values: Vec<_> = refs.list_refs().unwrap();
+        assert_eq!(
+            values,
+            [
+                ("refs/heads/a".to_owned(), one),
+                ("refs/heads/b".to_owned(), one)
+            ]
+        );
+        let leftovers: Vec<_> = fs::read_dir(tempdir.path().join("refs/heads"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(leftovers.len(), 2);
+
+        let mut transaction = refs.transaction();
+        transaction.update("refs/heads/a", None, Some(two));
+        transaction.update("refs/heads/a", None, Some(one));
+        assert!(transaction.commit().is_err());
+
+        let mut transaction = refs.transaction();
+        transaction.update("refs/heads/a", Some(Some(one)), Some(two));
+        transaction.update("refs/heads/new", Some(None), Some(two));
+        transaction.update("refs/heads/b", Some(Some(one)), None);
+        transaction.verify("refs/heads/c", None);
+        transaction.commit().unwrap();
+        assert_eq!(
+            refs.list_refs().unwrap(),
+            [
+                ("refs/heads/a".to_owned(), two),
+                ("refs/heads/new".to_owned(), two)
+            ]
+        );
+    }
+
+    #[test]
+    fn transactions_undo_changes_made_before_a_later_one_fails() {
+        let tempdir = tempdir().expect("tempdir");
+        let refs = Refs::new(tempdir.path().to_owned());
+        let one: ObjectId = "1111111111111111111111111111111111111111".parse().unwrap();
+        let two: ObjectId = "2222222222222222222222222222222222222222".parse().unwrap();
+        refs.update_ref("refs/heads/a", &one).unwrap();
+        refs.update_ref("refs/heads/b", &one).unwrap();
+        refs.update_ref("refs/heads/d", &one).unwrap();
+
+        let mut transaction = refs.transaction();
+        transaction.update("refs/heads/a", None, Some(two));
+        transaction.update("refs/heads/b", None, None);
+        transaction.update("refs/heads/c", None, Some(two));
+        transaction.update("refs/heads/d", None, Some(two));
+        let err = transaction
+            .commit_with(|refs, update, lock| match update.name.as_str() {
+                "refs/heads/d" => bail!("injected failure"),
+                _ => Refs::apply_update(refs, update, lock),
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "injected failure");
+
+        assert_eq!(
+            refs.list_refs().unwrap(),
+            [
+                ("refs/heads/a".to_owned(), one),
+                ("refs/heads/b".to_owned(), one),
+                ("refs/heads/d".to_owned(), one)
+            ]
+        );
+        let mut leftovers: Vec<_> = fs::read_dir(tempdir.path().join("refs/heads"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        leftovers.sort();
+        assert_eq!(leftovers, ["a", "b", "d"]);
+    }
+
+    #[test]
+    fn only_refs_and_pseudorefs_can_be_written() {
+        let tempdir = tempdir().expect("tempdir");
+        let refs = Refs::new(tempdir.path().to_owned());
+        let one: ObjectId = "1111111111111111111111111111111111111111".parse().unwrap();
+
+        for name in ["HEAD", "ORIG_HEAD", "refs/heads/main", "refs/tags/v1.0"] {
+            assert!(is_safe_ref_name(name), "{}", name);
+        }
+        for name in [
+            "config",
+            "index",
+            "objects/..",
+            "Head",
+            "../escaped",
+            "/tmp/file",
+            "refs/../config",
+            "refs/heads/main.lock",
+            "",
+        ] {
+            assert!(!is_safe_ref_name(name), "{}", name);
+            assert!(refs.update_ref(name, &one).is_err(), "{}", name);
+            assert!(refs.set_symbolic_ref(name, "refs/heads/main").is_err());
+        }
+        assert!(refs.set_symbolic_ref("HEAD", "../escaped").is_err());
+        assert!(!tempdir.path().join("config").exists());
 
-    fn head_path(&self) -> PathBuf {
-        self.root.join("HEAD")
+        // A symbolic ref pointing outside of the repository is not followed
+        fs::write(tempdir.path().join("HEAD"), "ref: ../escaped\n").unwrap();
+        assert!(refs.read_head().is_err());
+        assert!(refs.update_head(&one).is_err());
+        assert!(!tempdir.path().join("../escaped").exists());
     }
 }
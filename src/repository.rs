@@ -1,19 +1,42 @@
+use std::convert::TryInto;
+use std::env;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
+use crate::attributes::Attributes;
+use crate::config::Config;
 use crate::database::Database;
+use crate::ignores::Ignores;
 use crate::index::Index;
 use crate::refs::Refs;
 use crate::workspace::Workspace;
 
 pub struct Repository {
+    root_path: PathBuf,
     git_path: PathBuf,
 }
 
 impl Repository {
-    pub fn new(git_path: PathBuf) -> Self {
-        Self { git_path }
+    /// The repository whose workspace is `root_path`, keeping its objects, index and refs in
+    /// `root_path/.git`
+    pub fn new(root_path: PathBuf) -> Self {
+        let git_path = root_path.join(".git");
+        Self {
+            root_path,
+            git_path,
+        }
+    }
+
+    pub fn attributes(&self) -> Result<Attributes> {
+        Attributes::load(
+            &self.root_path,
+            &self.git_path.join("info").join("attributes"),
+        )
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        Config::load(&self.git_path.join("config"))
     }
 
     pub fn database(&self) -> Database {
@@ -21,12 +44,39 @@ impl Repository {
         Database::new(path)
     }
 
+    /// The standard patterns of ignored files: those in `.gitignore` files, `.git/info/exclude`
+    /// and the file named by `core.excludesFile`
+    pub fn ignores(&self) -> Result<Ignores> {
+        let mut ignores = Ignores::new(&self.root_path);
+        ignores.read_per_directory();
+        // Later files take precedence
+        if let Some(path) = self.config()?.get("core.excludesFile") {
+            let path = match (path.strip_prefix("~/"), env::var_os("HOME")) {
+                (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(path),
+            };
+            ignores.add_file(&path)?;
+        }
+        ignores.add_file(&self.git_path.join("info").join("exclude"))?;
+        Ok(ignores)
+    }
+
+    /// The index, for reading only
     pub fn index(&self) -> Result<Index> {
         Index::load(self.git_path.join("index"))
     }
 
     pub fn index_for_update(&self) -> Result<Index> {
-        Index::load_for_update(self.git_path.join("index"))
+        let mut index = Index::load_for_update(self.git_path.join("index"))?;
+        if index.is_new() {
+            if let Some(version) = self.config()?.get_int("index.version")? {
+                let version = version
+                    .try_into()
+                    .with_context(|| format!("Bad index.version {}", version))?;
+                index.set_version(version)?;
+            }
+        }
+        Ok(index)
     }
 
     pub fn refs(&self) -> Refs {
@@ -34,6 +84,45 @@ impl Repository {
     }
 
     pub fn workspace(&self) -> Workspace {
-        Workspace::new(&self.git_path)
+        Workspace::new(&self.root_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use super::Repository;
+    use crate::database::{Blob, Object};
+
+    #[test]
+    fn keeps_its_data_in_the_git_directory() {
+        let tempdir = tempdir().expect("tempdir");
+        let root = tempdir.path();
+        let repo = Repository::new(root.to_owned());
+
+        let mut blob = Blob::new(b"hello\n".to_vec());
+        repo.database().store(&mut blob).expect("Database::store");
+        repo.refs()
+            .update_ref("refs/heads/main", blob.oid())
+            .expect("Refs::update_ref");
+        let mut index = repo.index_for_update().expect("index_for_update");
+        index
+            .add_entry(Path::new("hello.txt"), blob.oid(), 0o100644, 0)
+            .expect("Index::add_entry");
+        index.write_updates().expect("Index::write_updates");
+
+        let hex = blob.oid().to_hex();
+        assert!(root
+            .join(".git/objects")
+            .join(&hex[..2])
+            .join(&hex[2..])
+            .is_file());
+        assert!(root.join(".git/refs/heads/main").is_file());
+        assert!(root.join(".git/index").is_file());
+        assert!(!root.join("objects").exists() && !root.join("index").exists());
+        assert_eq!(repo.workspace().root(), root);
     }
 }
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::database::{ObjectId, ParsedObject};
+use crate::refs::is_valid_ref_name;
+use crate::repository::Repository;
+
+/// A revision expression such as `HEAD`, `abc1234^`, `main~3` or `HEAD:src/main.rs`.
+#[derive(Debug)]
+pub struct Revision {
+    expr: String,
+    query: Rev,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Rev {
+    Ref(String),
+    /// The nth parent, where 0 is the commit itself
+    Parent(Box<Rev>, usize),
+    /// The nth generation ancestor, following first parents
+    Ancestor(Box<Rev>, usize),
+    /// A file or directory in the tree of a commit, by its path from the root
+    Path(Box<Rev>, String),
+}
+
+impl Revision {
+    /// Shortest prefix that is looked up as an abbreviated object id
+    const MIN_PREFIX_SIZE: usize = 4;
+
+    pub fn parse(expr: &str) -> Result<Self> {
+        let query =
+            Self::parse_rev(expr).ok_or_else(|| anyhow!("Not a valid revision: '{}'", expr))?;
+        Ok(Self {
+            expr: expr.to_owned(),
+            query,
+        })
+    }
+
+    fn parse_rev(expr: &str) -> Option<Rev> {
+        if let Some((base, path)) = expr.split_once(':') {
+            let base = Box::new(Self::parse_rev(base)?);
+            return Some(Rev::Path(base, path.to_owned()));
+        }
+
+        if let Some(pos) = expr.rfind(['^', '~']) {
+            let (base, operator) = expr.split_at(pos);
+            let digits = &operator[1..];
+            let n = if digits.is_empty() {
+                1
+            } else if digits.bytes().all(|b| b.is_ascii_digit()) {
+                digits.parse().ok()?
+            } else {
+                return None;
+            };
+            let base = Box::new(Self::parse_rev(base)?);
+            return Some(match operator.as_bytes()[0] {
+                b'^' => Rev::Parent(base, n),
+                _ => Rev::Ancestor(base, n),
+            });
+        }
+
+        if is_valid_ref_name(expr) {
+            Some(Rev::Ref(expr.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the id of the commit the expression refers to, following tags.
+    pub fn resolve(&self, repo: &Repository) -> Result<ObjectId> {
+        let oid = self.resolve_rev(repo, &self.query)?;
+        self.peel_to_commit(repo, oid)
+            .with_context(|| format!("Revision '{}' is not a commit", self.expr))
+    }
+
+    /// Returns the id of whatever object the expression refers to, which may be a tag, tree or
+    /// blob as well as a commit.
+    pub fn resolve_object(&self, repo: &Repository) -> Result<ObjectId> {
+        self.resolve_rev(repo, &self.query)
+    }
+
+    /// Returns the id of the tree the expression refers to, following tags and commits.
+    pub fn resolve_tree(&self, repo: &Repository) -> Result<ObjectId> {
+        let oid = self.resolve_rev(repo, &self.query)?;
+        self.peel_to_tree(repo, oid)
+    }
+
+    fn resolve_rev(&self, repo: &Repository, rev: &Rev) -> Result<ObjectId> {
+        match rev {
+            Rev::Ref(name) => self.resolve_name(repo, name),
+            Rev::Parent(base, n) => {
+                let oid = self.resolve_rev(repo, base)?;
+                let oid = self.peel_to_commit(repo, oid)?;
+                if *n == 0 {
+                    return Ok(oid);
+                }
+                let commit = repo.database().load_commit(&oid)?;
+                commit
+                    .parents()
+                    .get(n - 1)
+                    .copied()
+                    .ok_or_else(|| anyhow!("Revision '{}' does not exist", self.expr))
+            }
+            Rev::Ancestor(base, n) => {
+                let database = repo.database();
+                let oid = self.resolve_rev(repo, base)?;
+                let mut oid = self.peel_to_commit(repo, oid)?;
+                for _ in 0..*n {
+                    oid = database
+                        .load_commit(&oid)?
+                        .parents()
+                        .first()
+                        .copied()
+                        .ok_or_else(|| anyhow!("Revision '{}' does not exist", self.expr))?;
+                }
+                Ok(oid)
+            }
+            Rev::Path(base, path) => {
+                let database = repo.database();
+                let oid = self.resolve_rev(repo, base)?;
+                let mut oid = self.peel_to_tree(repo, oid)?;
+                for name in path.split('/').filter(|name| !name.is_empty()) {
+                    let tree = database.load_tree(&oid).with_context(|| {
+                        format!("Path '{}' does not exist in '{}'", path, self.expr)
+                    })?;
+                    oid = tree
+                        .list_entries()
+                        .find(|(entry_name, _)| *entry_name == name)
+                        .map(|(_, entry)| *entry.oid())
+                        .ok_or_else(|| {
+                            anyhow!("Path '{}' does not exist in '{}'", path, self.expr)
+                        })?;
+                }
+                Ok(oid)
+            }
+        }
+    }
+
+    fn peel_to_commit(&self, repo: &Repository, mut oid: ObjectId) -> Result<ObjectId> {
+        loop {
+            match repo.database().load(&oid)? {
+                ParsedObject::Commit(_) => return Ok(oid),
+                ParsedObject::Tag(tag) => oid = *tag.object(),
+                _ => bail!("Revision '{}' is not a commit", self.expr),
+            }
+        }
+    }
+
+    /// Follows tags and commits to the tree they point to
+    fn peel_to_tree(&self, repo: &Repository, mut oid: ObjectId) -> Result<ObjectId> {
+        loop {
+            match repo.database().load(&oid)? {
+                ParsedObject::Tree(_) => return Ok(oid),
+                ParsedObject::Commit(commit) => return Ok(*commit.tree()),
+                ParsedObject::Tag(tag) => oid = *tag.object(),
+                ParsedObject::Blob(_) => bail!("Revision '{}' is not a tree", self.expr),
+            }
+        }
+    }
+
+    fn resolve_name(&self, repo: &Repository, name: &str) -> Result<ObjectId> {
+        let name = if name == "@" { "HEAD" } else { name };
+        if let Some(oid) = repo.refs().read_ref(name)? {
+            return Ok(oid);
+        }
+
+        let is_hex = name.bytes().all(|b| b.is_ascii_hexdigit());
+        if is_hex && (Self::MIN_PREFIX_SIZE..=ObjectId::HEX_SIZE).contains(&name.len()) {
+            let mut candidates = repo.database().prefix_match(name)?;
+            match candidates.len() {
+                0 => {}
+                1 => return Ok(candidates.remove(0)),
+                _ => {
+                    let candidates: Vec<_> = candidates.iter().map(|oid| oid.to_hex()).collect();
+                    bail!(
+                        "Short object id {} is ambiguous; the candidates are: {}",
+                        name,
+                        candidates.join(", ")
+                    );
+                }
+            }
+        }
+
+        bail!("Unknown revision '{}'", self.expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+    use time::OffsetDateTime;
+
+    use super::{Rev, Revision};
+    use crate::database::{Author, Blob, Commit, Object, ObjectId, Tag, Tree, TreeFile};
+    use crate::repository::Repository;
+
+    fn reference(name: &str) -> Box<Rev> {
+        Box::new(Rev::Ref(name.to_owned()))
+    }
+
+    #[test]
+    fn parses_revision_expressions() {
+        let parse = |expr| Revision::parse(expr).expect("Revision::parse").query;
+
+        assert_eq!(parse("HEAD"), Rev::Ref("HEAD".to_owned()));
+        assert_eq!(parse("@^"), Rev::Parent(reference("@"), 1));
+        assert_eq!(parse("main^2"), Rev::Parent(reference("main"), 2));
+        assert_eq!(
+            parse("HEAD~3^"),
+            Rev::Parent(Box::new(Rev::Ancestor(reference("HEAD"), 3)), 1)
+        );
+        assert_eq!(parse("abc1234~"), Rev::Ancestor(reference("abc1234"), 1));
+        assert_eq!(
+            parse("HEAD~:src/a~1.rs"),
+            Rev::Path(
+                Box::new(Rev::Ancestor(reference("HEAD"), 1)),
+                "src/a~1.rs".to_owned()
+            )
+        );
+        assert_eq!(parse("v1:"), Rev::Path(reference("v1"), "".to_owned()));
+
+        for bad in [
+            "",
+            "^",
+            "HEAD^x",
+            "a..b",
+            "refs/heads/",
+            ".hidden",
+            "x.lock",
+            "a b",
+            ":src/main.rs",
+        ] {
+            assert!(Revision::parse(bad).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn resolves_refs_ids_and_ancestors() {
+        let tempdir = tempdir().expect("tempdir");
+        let repo = Repository::new(tempdir.path().to_owned());
+        let database = repo.database();
+
+        let mut tree = Tree::new();
+        database.store(&mut tree).expect("Database::store");
+        let author = Author::new("A", "a@example.com", OffsetDateTime::unix_epoch());
+        let mut parent = None;
+        let mut oids = Vec::new();
+        for message in ["one", "two", "three"] {
+            let mut commit = Commit::new(
+                parent.into_iter().collect(),
+                *tree.oid(),
+                author.clone(),
+                message.to_owned(),
+            );
+            database.store(&mut commit).expect("Database::store");
+            parent = Some(*commit.oid());
+            oids.push(*commit.oid());
+        }
+        fs::create_dir_all(tempdir.path().join(".git/refs/heads")).expect("create_dir_all");
+        repo.refs()
+            .update_head(&oids[2])
+            .expect("Refs::update_head");
+        repo.refs()
+            .update_ref("refs/heads/main", &oids[1])
+            .expect("Refs::update_ref");
+
+        let resolve = |expr: &str| {
+            Revision::parse(expr)
+                .and_then(|revision| revision.resolve(&repo))
+                .ok()
+        };
+        assert_eq!(resolve("HEAD"), Some(oids[2]));
+        assert_eq!(resolve("@~2"), Some(oids[0]));
+        assert_eq!(resolve("HEAD^^0"), Some(oids[1]));
+        assert_eq!(resolve("main"), Some(oids[1]));
+        assert_eq!(resolve(&oids[0].to_hex()[..7]), Some(oids[0]));
+        assert_eq!(resolve("HEAD~3"), None);
+        assert_eq!(resolve("HEAD^2"), None);
+        assert_eq!(resolve("missing"), None);
+
+        // Trees are objects but not commits
+        assert_eq!(resolve(&tree.oid().to_hex()), None);
+        let unknown: ObjectId = "0000000000000000000000000000000000000001".parse().unwrap();
+        assert_eq!(resolve(&unknown.to_hex()), None);
+    }
+
+    #[test]
+    fn resolves_tags_and_paths() {
+        let tempdir = tempdir().expect("tempdir");
+        let repo = Repository::new(tempdir.path().to_owned());
+        let database = repo.database();
+
+        let mut blob = Blob::new(b"fn main() {}\n".to_vec());
+        database.store(&mut blob).expect("Database::store");
+        let mut src = Tree::build(vec![TreeFile::new("main.rs", *blob.oid(), 0o100644)])
+            .expect("Tree::build");
+        database.store(&mut src).expect("Database::store");
+        let mut tree =
+            Tree::build(vec![TreeFile::new_tree("src", *src.oid())]).expect("Tree::build");
+        database.store(&mut tree).expect("Database::store");
+        let author = Author::new("A", "a@example.com", OffsetDateTime::unix_epoch());
+        let mut commit = Commit::new(vec![], *tree.oid(), author, "one".to_owned());
+        database.store(&mut commit).expect("Database::store");
+        let data = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 0 +0000\n\nRelease\n",
+            commit.oid()
+        );
+        let mut tag = Tag::parse(data.as_bytes()).expect("Tag::parse");
+        database.store(&mut tag).expect("Database::store");
+        fs::create_dir_all(tempdir.path().join(".git/refs/tags")).expect("create_dir_all");
+        repo.refs()
+            .update_ref("refs/tags/v1", tag.oid())
+            .expect("Refs::update_ref");
+
+        let resolve_object = |expr: &str| {
+            Revision::parse(expr)
+                .and_then(|revision| revision.resolve_object(&repo))
+                .ok()
+        };
+        assert_eq!(resolve_object("refs/tags/v1"), Some(*tag.oid()));
+        assert_eq!(resolve_object("refs/tags/v1^0"), Some(*commit.oid()));
+        assert_eq!(resolve_object("refs/tags/v1:"), Some(*tree.oid()));
+        assert_eq!(
+            resolve_object("refs/tags/v1:src/main.rs"),
+            Some(*blob.oid())
+        );
+        assert_eq!(
+            resolve_object("refs/tags/v1:/src//main.rs"),
+            Some(*blob.oid())
+        );
+        assert_eq!(resolve_object("refs/tags/v1:src/lib.rs"), None);
+        assert_eq!(resolve_object("refs/tags/v1:src/main.rs/x"), None);
+
+        let resolve = |expr: &str| {
+            Revision::parse(expr)
+                .and_then(|revision| revision.resolve(&repo))
+                .ok()
+        };
+        assert_eq!(resolve("refs/tags/v1"), Some(*commit.oid()));
+        assert_eq!(resolve("refs/tags/v1:src"), None);
+    }
+}
@@ -1,7 +1,11 @@
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{bail, Context, Result};
+
+use crate::pathspec::Pathspec;
 
 #[derive(Debug)]
 pub struct Workspace {
@@ -21,7 +25,7 @@ impl Workspace {
         }
     }
 
-    pub fn path<P: AsRef<Path>>(&self, path: P) -> Result<WorkspacePath> {
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> Result<WorkspacePath<'_>> {
         let full_path = path.as_ref().canonicalize().with_context(|| {
             format!(
                 "pathspec {} did not match any files",
@@ -37,10 +41,140 @@ impl Workspace {
         })
     }
 
-    pub fn list_files<P: AsRef<Path>>(&self, path: P) -> Result<Vec<WorkspacePath>> {
-        let path = self.path(path)?;
+    /// Refers to a path relative to the workspace root, which need not exist.
+    pub fn entry_path<P: AsRef<Path>>(&self, rel_path: P) -> WorkspacePath<'_> {
+        WorkspacePath {
+            workspace: self,
+            rel_path: rel_path.as_ref().to_owned(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `path` relative to the workspace root without requiring it to exist, as for
+    /// paths that are only in the index.
+    pub fn rel_path_of<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let full_path = if path.as_ref().is_absolute() {
+            path.as_ref().to_owned()
+        } else {
+            std::env::current_dir()?.join(path.as_ref())
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in full_path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+        match normalized.strip_prefix(&self.root) {
+            Ok(rel_path) => Ok(rel_path.to_owned()),
+            Err(_) => bail!("Path {} is not inside workspace", path.as_ref().display()),
+        }
+    }
+
+    /// Deletes the file at `rel_path`, then any directories left empty by its removal. Nothing is
+    /// removed if a leading directory is a symlink, since the file is then not in the workspace.
+    pub fn remove_file<P: AsRef<Path>>(&self, rel_path: P) -> Result<()> {
+        check_path(rel_path.as_ref().as_os_str().as_bytes())?;
+        if self.has_symlink_leading_path(rel_path.as_ref()) {
+            return Ok(());
+        }
+
+        let path = self.root.join(rel_path.as_ref());
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Removing {}", rel_path.as_ref().display()))
+            }
+        }
+
+        for dir in path.ancestors().skip(1) {
+            if dir == self.root || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces whatever is at `rel_path` with a file holding `data`, creating its parent
+    /// directories as needed. Symlinks in the way are replaced rather than followed, so nothing
+    /// outside the workspace is written or removed.
+    pub fn write_file<P: AsRef<Path>>(&self, rel_path: P, data: &[u8], mode: u32) -> Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        check_path(rel_path.as_ref().as_os_str().as_bytes())?;
+        let path = self.root.join(rel_path.as_ref());
+        if let Some(parent) = path.parent() {
+            // A file or symlink may be in the way of the new directory; ancestors are checked
+            // from the root down so that no symlink is followed
+            let mut dirs: Vec<_> = parent
+                .ancestors()
+                .take_while(|dir| *dir != self.root)
+                .collect();
+            dirs.reverse();
+            for dir in dirs {
+                match dir.symlink_metadata() {
+                    Ok(metadata) if metadata.is_dir() => {}
+                    Ok(_) => {
+                        fs::remove_file(dir)?;
+                        fs::create_dir(dir)?;
+                    }
+                    Err(err) if err.kind() == ErrorKind::NotFound => fs::create_dir(dir)?,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path)?,
+            Ok(_) => fs::remove_file(&path)?,
+            Err(_) => {}
+        }
+
+        let permissions = if mode & 0o100 != 0 { 0o755 } else { 0o644 };
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(permissions)
+            .open(&path)
+            .and_then(|mut file| file.write_all(data))
+            .with_context(|| format!("Writing {}", rel_path.as_ref().display()))?;
+        Ok(())
+    }
+
+    /// Whether any directory leading to `rel_path` is a symlink or not a directory at all
+    fn has_symlink_leading_path(&self, rel_path: &Path) -> bool {
+        let mut dir = self.root.clone();
+        let components: Vec<_> = rel_path.components().collect();
+        for component in components.iter().take(components.len().saturating_sub(1)) {
+            dir.push(component);
+            match dir.symlink_metadata() {
+                Ok(metadata) if !metadata.is_dir() => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Lists the files selected by `pathspec`, searching only the directories it could match.
+    pub fn list_matching_files(&self, pathspec: &Pathspec) -> Result<Vec<WorkspacePath<'_>>> {
         let mut results = Vec::new();
-        self.list_files_in(path, &mut results)?;
+        for dir in pathspec.leading_dirs() {
+            let path = self.root.join(dir);
+            if path.symlink_metadata().is_ok() {
+                self.list_files_in(self.path(path)?, &mut results)?;
+            }
+        }
+        results.retain(|file| pathspec.matches(file.rel_path()));
+        results.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+        results.dedup_by(|a, b| a.rel_path == b.rel_path);
         Ok(results)
     }
 
@@ -49,18 +183,22 @@ impl Workspace {
         path: WorkspacePath,
         results: &mut Vec<WorkspacePath<'a>>,
     ) -> Result<()> {
-        const IGNORE_PARTS: &[&str] = &[".swp", ".un~"];
-        const IGNORE_NAMES: &[&str] = &[".git", "target"];
+        // Names are compared as raw bytes, as they need not be valid UTF-8
+        const IGNORE_PARTS: &[&[u8]] = &[b".swp", b".un~"];
+        const IGNORE_NAMES: &[&[u8]] = &[b".git", b"target"];
 
         if path.stat()?.is_dir() {
             for entry in path.path().read_dir()? {
                 let entry = entry?;
                 let name = entry.file_name();
-                let name = name.to_str().ok_or(anyhow!("Invalid filename found"))?;
-                if IGNORE_PARTS.iter().any(|ig| name.contains(ig)) {
+                let name = name.as_bytes();
+                if IGNORE_PARTS
+                    .iter()
+                    .any(|ig| name.windows(ig.len()).any(|part| part == *ig))
+                {
                     continue;
                 }
-                if IGNORE_NAMES.iter().any(|ig| name == *ig) {
+                if IGNORE_NAMES.contains(&name) {
                     continue;
                 }
                 //if entry.file_type()?.is_dir() {
@@ -76,8 +214,25 @@ impl Workspace {
     }
 }
 
+/// Rejects paths that could refer to something outside of the workspace or inside `.git`: they
+/// must be relative, and have no empty, `.`, `..` or `.git` components or NUL bytes.
+pub fn check_path(path: &[u8]) -> Result<()> {
+    let valid = !path.is_empty()
+        && !path.contains(&0)
+        && path.split(|b| *b == b'/').all(|component| {
+            !component.is_empty()
+                && component != b"."
+                && component != b".."
+                && !component.eq_ignore_ascii_case(b".git")
+        });
+    if !valid {
+        bail!("Invalid path '{}'", String::from_utf8_lossy(path));
+    }
+    Ok(())
+}
+
 impl WorkspacePath<'_> {
-    fn path(&self) -> PathBuf {
+    pub fn path(&self) -> PathBuf {
         self.workspace.root.join(&self.rel_path)
     }
 
@@ -91,9 +246,80 @@ impl WorkspacePath<'_> {
         Ok(data)
     }
 
+    pub fn open(&self) -> Result<fs::File> {
+        let file = fs::File::open(self.path())
+            .with_context(|| format!("open('{:?}'): Permission denied", self.rel_path()))?;
+        Ok(file)
+    }
+
     pub fn stat(&self) -> Result<fs::Metadata> {
         let metadata = fs::metadata(self.path())
             .with_context(|| format!("stat('{:?}'): Permission denied", self.rel_path()))?;
         Ok(metadata)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn write_file_replaces_symlinks_instead_of_following_them() -> Result<()> {
+        let outside = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        let workspace = Workspace::new(root.path());
+        symlink(outside.path().join("target"), root.path().join("file"))?;
+        symlink(outside.path(), root.path().join("dir"))?;
+
+        workspace.write_file("file", b"hello", 0o100644)?;
+        workspace.write_file("dir/file", b"hello", 0o100644)?;
+
+        assert!(!outside.path().join("target").exists());
+        assert!(!outside.path().join("file").exists());
+        assert_eq!(fs::read(root.path().join("file"))?, b"hello");
+        assert!(root.path().join("dir").symlink_metadata()?.is_dir());
+        assert_eq!(fs::read(root.path().join("dir/file"))?, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn remove_file_does_not_follow_symlinked_directories() -> Result<()> {
+        let outside = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        let workspace = Workspace::new(root.path());
+        fs::write(outside.path().join("file"), b"keep")?;
+        symlink(outside.path(), root.path().join("dir"))?;
+
+        workspace.remove_file("dir/file")?;
+
+        assert!(outside.path().join("file").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_workspace() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let workspace = Workspace::new(root.path());
+
+        for path in [
+            "",
+            "../file",
+            "a/../../file",
+            "./file",
+            ".git/config",
+            "a/.GIT/b",
+            "/etc",
+        ] {
+            assert!(
+                workspace.write_file(path, b"", 0o100644).is_err(),
+                "{}",
+                path
+            );
+            assert!(workspace.remove_file(path).is_err(), "{}", path);
+        }
+        assert!(!root.path().join(".git").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Runs the `jit` binary against scratch repositories to check that commands refuse to lose
+//! changes or to write outside the workspace.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crypto::{digest::Digest, sha1::Sha1};
+use flate2::{write::ZlibEncoder, Compression};
+use tempfile::{tempdir, TempDir};
+
+const ONE: &str = "d00491fd7e5bb6fa28c517a0bb32b8b506539d4d";
+const TWO: &str = "0cfbf08886fca9a91cb753ec8734c84fcbe52c9f";
+const THREE: &str = "00750edc07d6415dcc07ae0351e9397b0222b7ba";
+
+/// A repository in a directory of its own, inside a parent directory that nothing should be
+/// written to
+struct Repo {
+    parent: TempDir,
+}
+
+impl Repo {
+    fn new() -> Self {
+        let repo = Self {
+            parent: tempdir().expect("tempdir"),
+        };
+        fs::create_dir(repo.root()).expect("create_dir");
+        repo.jit(&["init"]);
+        repo
+    }
+
+    fn root(&self) -> PathBuf {
+        self.parent.path().join("repo")
+    }
+
+    fn run(&self, args: &[&str], stdin: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_jit"))
+            .args(args)
+            .current_dir(self.root())
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("RUST_BACKTRACE", "0")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("spawn jit");
+        child
+            .stdin
+            .take()
+            .expect("stdin")
+            .write_all(stdin.as_bytes())
+            .expect("write stdin");
+        child.wait_with_output().expect("wait for jit")
+    }
+
+    /// Runs jit, which must succeed, and returns its output
+    fn jit(&self, args: &[&str]) -> String {
+        let output = self.run(args, "");
+        assert!(
+            output.status.success(),
+            "jit {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Runs jit, which must fail, and returns its error output
+    fn jit_fails(&self, args: &[&str]) -> String {
+        let output = self.run(args, "");
+        assert!(!output.status.success(), "jit {:?} succeeded", args);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    fn commit(&self, message: &str) {
+        let output = self.run(&["commit"], message);
+        assert!(output.status.success(), "commit failed");
+    }
+
+    fn write(&self, path: &str, content: &str) {
+        let path = self.root().join(path);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create_dir_all");
+        fs::write(path, content).expect("write");
+    }
+
+    fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.root().join(path)).ok()
+    }
+
+    fn ls_files(&self, args: &[&str]) -> Vec<String> {
+        let args: Vec<_> = ["ls-files"].iter().chain(args).copied().collect();
+        self.jit(&args).lines().map(str::to_owned).collect()
+    }
+
+    /// Stores an object directly, bypassing the checks jit makes on what it is given
+    fn store_raw(&self, kind: &str, content: &[u8]) -> String {
+        let mut data = format!("{} {}\0", kind, content.len()).into_bytes();
+        data.extend_from_slice(content);
+        let mut hasher = Sha1::new();
+        hasher.input(&data);
+        let oid = hasher.result_str();
+
+        let path = self
+            .root()
+            .join(".git/objects")
+            .join(&oid[..2])
+            .join(&oid[2..]);
+        fs::create_dir_all(path.parent().expect("parent")).expect("create_dir_all");
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).expect("compress");
+        fs::write(path, encoder.finish().expect("compress")).expect("write object");
+        oid
+    }
+
+    /// Stores a commit whose tree holds `file` under a directory called `name`
+    fn store_commit_with_dir(&self, name: &str, file: &str) -> String {
+        let blob = self.store_raw("blob", b"escaped\n");
+        let subtree = self.store_raw("tree", &tree_entry("100644", file, &blob));
+        let tree = self.store_raw("tree", &tree_entry("40000", name, &subtree));
+        let commit = format!(
+            "tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nbad\n",
+            tree
+        );
+        self.store_raw("commit", commit.as_bytes())
+    }
+}
+
+fn tree_entry(mode: &str, name: &str, oid: &str) -> Vec<u8> {
+    let mut entry = format!("{} {}\0", mode, name).into_bytes();
+    for i in (0..oid.len()).step_by(2) {
+        entry.push(u8::from_str_radix(&oid[i..i + 2], 16).expect("hex"));
+    }
+    entry
+}
+
+fn exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+#[test]
+fn rm_refuses_to_remove_changed_files() {
+    let repo = Repo::new();
+    repo.write("a.txt", "one\n");
+    repo.write("b.txt", "one\n");
+    repo.jit(&["add", "."]);
+    repo.commit("first");
+
+    repo.write("a.txt", "changed\n");
+    repo.write("b.txt", "staged\n");
+    repo.jit(&["add", "b.txt"]);
+
+    let err = repo.jit_fails(&["rm", "a.txt", "b.txt"]);
+    assert!(err.contains("local modifications"), "{}", err);
+    assert!(err.contains("changes staged in the index"), "{}", err);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("changed\n"));
+    assert_eq!(repo.read("b.txt").as_deref(), Some("staged\n"));
+    assert_eq!(repo.ls_files(&[]), ["a.txt", "b.txt"]);
+
+    repo.jit(&["rm", "--cached", "b.txt"]);
+    assert_eq!(repo.read("b.txt").as_deref(), Some("staged\n"));
+    repo.jit(&["rm", "-f", "a.txt"]);
+    assert_eq!(repo.read("a.txt"), None);
+    assert!(repo.ls_files(&[]).is_empty());
+}
+
+#[test]
+fn mv_refuses_to_overwrite_targets() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("b.txt", "b\n");
+    repo.write("c.txt", "c\n");
+    repo.jit(&["add", "."]);
+
+    let err = repo.jit_fails(&["mv", "a.txt", "b.txt"]);
+    assert!(err.contains("destination exists"), "{}", err);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("a\n"));
+    assert_eq!(repo.read("b.txt").as_deref(), Some("b\n"));
+
+    // A tracked target is still there as far as the index is concerned
+    fs::remove_file(repo.root().join("b.txt")).expect("remove_file");
+    let err = repo.jit_fails(&["mv", "a.txt", "b.txt"]);
+    assert!(err.contains("destination exists"), "{}", err);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("a\n"));
+
+    // Nothing is moved if any of the moves is refused
+    repo.write("dir/c.txt", "other c\n");
+    repo.jit_fails(&["mv", "a.txt", "c.txt", "dir"]);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("a\n"));
+    assert_eq!(repo.read("dir/a.txt"), None);
+    assert_eq!(repo.ls_files(&[]), ["a.txt", "b.txt", "c.txt"]);
+
+    repo.jit(&["mv", "-f", "a.txt", "b.txt"]);
+    assert_eq!(repo.read("b.txt").as_deref(), Some("a\n"));
+    assert_eq!(repo.ls_files(&[]), ["b.txt", "c.txt"]);
+}
+
+#[test]
+fn restore_refuses_unmerged_paths() {
+    let repo = Repo::new();
+    repo.write("a.txt", "one\n");
+    repo.write("conflict.txt", "one\n");
+    repo.jit(&["add", "."]);
+    let info = format!(
+        "100644 {} 1\tconflict.txt\n100644 {} 2\tconflict.txt\n100644 {} 3\tconflict.txt\n",
+        ONE, TWO, THREE
+    );
+    let output = repo.run(&["update-index", "--index-info"], &info);
+    assert!(output.status.success(), "update-index failed");
+    repo.write("a.txt", "changed\n");
+
+    let err = repo.jit_fails(&["restore", "a.txt", "conflict.txt"]);
+    assert!(err.contains("path 'conflict.txt' is unmerged"), "{}", err);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("changed\n"));
+
+    repo.jit(&["restore", "a.txt"]);
+    assert_eq!(repo.read("a.txt").as_deref(), Some("one\n"));
+}
+
+#[test]
+fn reset_and_restore_refuse_trees_that_escape_the_workspace() {
+    let repo = Repo::new();
+    repo.write("a.txt", "one\n");
+    repo.jit(&["add", "."]);
+    repo.commit("first");
+    let head = fs::read_to_string(repo.root().join(".git/HEAD")).expect("HEAD");
+
+    for (dir, file) in [("..", "escaped.txt"), (".git", "config"), (".GIT", "hooks")] {
+        let commit = repo.store_commit_with_dir(dir, file);
+        for args in [
+            vec!["reset", "--hard", &commit],
+            vec!["reset", &commit],
+            vec!["reset", &commit, "--", "."],
+            vec!["restore", "--source", &commit, "."],
+            vec!["restore", "--staged", "--source", &commit, "."],
+        ] {
+            repo.jit_fails(&args);
+        }
+    }
+
+    assert!(!exists(&repo.parent.path().join("escaped.txt")));
+    assert!(!exists(&repo.root().join(".git/config")));
+    assert_eq!(
+        fs::read_to_string(repo.root().join(".git/HEAD")).expect("HEAD"),
+        head
+    );
+    assert_eq!(repo.read("a.txt").as_deref(), Some("one\n"));
+    assert_eq!(repo.ls_files(&[]), ["a.txt"]);
+}
+
+#[test]
+fn add_all_and_update_choose_which_files_to_stage() {
+    let repo = Repo::new();
+    repo.write(".gitignore", "*.log\n");
+    repo.write("tracked.txt", "one\n");
+    repo.write("gone.txt", "one\n");
+    repo.jit(&["add", "."]);
+    assert_eq!(
+        repo.ls_files(&[]),
+        [".gitignore", "gone.txt", "tracked.txt"]
+    );
+
+    repo.write("tracked.txt", "changed\n");
+    repo.write("new.txt", "new\n");
+    repo.write("debug.log", "ignored\n");
+    fs::remove_file(repo.root().join("gone.txt")).expect("remove_file");
+
+    repo.jit_fails(&["add", "-A", "-u"]);
+    let err = repo.jit_fails(&["add", "debug.log"]);
+    assert!(err.contains("ignored"), "{}", err);
+
+    repo.jit(&["add", "-u"]);
+    assert_eq!(repo.ls_files(&[]), [".gitignore", "tracked.txt"]);
+    assert!(repo.ls_files(&["-m"]).is_empty());
+
+    repo.jit(&["add", "-A"]);
+    assert_eq!(repo.ls_files(&[]), [".gitignore", "new.txt", "tracked.txt"]);
+}